@@ -0,0 +1,178 @@
+use super::{
+    Error, InstallOutcome, InstalledService, ServiceDiff, ServiceDisableCtx, ServiceEnableCtx,
+    ServiceInstallCtx, ServiceLevel, ServiceListCtx, ServiceRestartCtx, ServiceRollbackCtx,
+    ServiceStartCtx, ServiceStatus, ServiceStatusCtx, ServiceStatusDetail, ServiceStopCtx,
+    ServiceUninstallCtx,
+};
+use std::{future::Future, io, pin::Pin};
+
+/// Future returned by the operations of an [`AsyncServiceManager`]
+pub type ServiceFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Interface for a service manager whose operations do not block the calling thread
+///
+/// This mirrors [`ServiceManager`](crate::ServiceManager), spawning the commands of the
+/// underlying platform through `tokio::process` and writing service definitions through
+/// `tokio::fs`. As such, its futures must be polled within a tokio runtime with IO enabled.
+///
+/// Producing a service definition does not involve any IO, so use
+/// [`ServiceManager::render`](crate::ServiceManager::render) to preview an install.
+///
+/// ```
+/// use service_manager::asynchronous::AsyncServiceManager;
+/// use service_manager::{FakeCommandRunner, ServiceStatus, ServiceStatusCtx, SystemdServiceManager};
+///
+/// # tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(async {
+/// let runner = FakeCommandRunner::new();
+/// runner.push_output(3, "", "");
+///
+/// let manager = SystemdServiceManager::system().with_runner(runner.clone());
+/// let label = "com.example.echo".parse().unwrap();
+/// let status = manager.status(ServiceStatusCtx::new(label)).await.unwrap();
+///
+/// assert_eq!(status, ServiceStatus::Stopped(None));
+/// # });
+/// ```
+pub trait AsyncServiceManager: Send + Sync {
+    /// Determines if the service manager exists (e.g. is `launchd` available on the system?) and
+    /// can be used
+    fn available(&self) -> ServiceFuture<'_, bool>;
+
+    /// Installs a new service using the manager
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()>;
+
+    /// Compares the definition that would be installed with the one that is installed, like
+    /// [`ServiceManager::diff`](crate::ServiceManager::diff)
+    ///
+    /// By default, this reports that diffing services is unsupported.
+    fn diff<'a>(&'a self, _ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Diffing services is not supported by this service manager".to_string(),
+            )
+            .into())
+        })
+    }
+
+    /// Installs a service, or updates it if it is already installed with a different definition,
+    /// like [`ServiceManager::install_or_update`](crate::ServiceManager::install_or_update)
+    ///
+    /// By default, this reports that updating services is unsupported.
+    fn install_or_update(&self, _ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Updating services is not supported by this service manager".to_string(),
+            )
+            .into())
+        })
+    }
+
+    /// Uninstalls an existing service using the manager
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()>;
+
+    /// Starts a service using the manager
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()>;
+
+    /// Stops a running service using the manager
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()>;
+
+    /// Restarts a service using the manager
+    ///
+    /// By default, this stops the service and then starts it again.
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            self.stop(ServiceStopCtx {
+                label: ctx.label.clone(),
+            })
+            .await?;
+            self.start(ServiceStartCtx { label: ctx.label }).await
+        })
+    }
+
+    /// Configures an installed service to start automatically, without reinstalling it
+    ///
+    /// By default, this reports that enabling services is unsupported.
+    fn enable(&self, _ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Enabling services is not supported by this service manager".to_string(),
+            )
+            .into())
+        })
+    }
+
+    /// Configures an installed service to no longer start automatically, without reinstalling it
+    ///
+    /// By default, this reports that disabling services is unsupported.
+    fn disable(&self, _ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Disabling services is not supported by this service manager".to_string(),
+            )
+            .into())
+        })
+    }
+
+    /// Returns true if an installed service is configured to start automatically
+    ///
+    /// By default, this reports that querying whether services are enabled is unsupported.
+    fn is_enabled(&self, _ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Querying whether services are enabled is not supported by this service manager"
+                    .to_string(),
+            )
+            .into())
+        })
+    }
+
+    /// Restores the previous definition of an installed service and reloads the manager, like
+    /// [`ServiceManager::rollback`](crate::ServiceManager::rollback)
+    ///
+    /// By default, this reports that rolling back is unsupported.
+    fn rollback(&self, _ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Rolling back services is not supported by this service manager".to_string(),
+            )
+            .into())
+        })
+    }
+
+    /// Returns the current target level for the manager
+    fn level(&self) -> ServiceLevel;
+
+    /// Sets the target level for the manager
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()>;
+
+    /// Return the service status info
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus>;
+
+    /// Return detailed service status info, such as the process id and last exit code
+    ///
+    /// By default, this only reports the state derived from [`AsyncServiceManager::status`].
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        Box::pin(async move { Ok(ServiceStatusDetail::from(self.status(ctx).await?)) })
+    }
+
+    /// Lists the services installed with the manager, along with their status
+    ///
+    /// By default, this reports that listing is unsupported.
+    fn list(&self, _ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async {
+            Err(Error::Unsupported(
+                "Listing services is not supported by this service manager".to_string(),
+            )
+            .into())
+        })
+    }
+}
+
+impl<'a, S> From<S> for Box<dyn AsyncServiceManager + 'a>
+where
+    S: AsyncServiceManager + 'a,
+{
+    fn from(service_manager: S) -> Self {
+        Box::new(service_manager)
+    }
+}
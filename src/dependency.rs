@@ -0,0 +1,136 @@
+use super::Error;
+use std::{fmt, io};
+
+/// Relationship between a service and one of its [`ServiceDependency`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DependencyKind {
+    /// The other service must be running for this one to run
+    Requires,
+
+    /// The other service is started along with this one if possible, without failing when it
+    /// cannot be
+    Wants,
+
+    /// This service is started after the other one when both are being started
+    After,
+
+    /// This service is started before the other one when both are being started
+    Before,
+
+    /// This service cannot run at the same time as the other one
+    Conflicts,
+}
+
+impl fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Requires => write!(f, "requires"),
+            Self::Wants => write!(f, "wants"),
+            Self::After => write!(f, "after"),
+            Self::Before => write!(f, "before"),
+            Self::Conflicts => write!(f, "conflicts"),
+        }
+    }
+}
+
+/// Dependency of a service on another one, set through the `dependencies` field of
+/// [`ServiceInstallCtx`]
+///
+/// The other service is named as its manager knows it, such as `postgresql` or
+/// `network-online.target` with `systemd`. For a service installed through this crate, that is
+/// the name produced by [`ServiceNaming::native_name`] for its label.
+///
+/// Requiring or wanting a service does not order the two services with `systemd`, so add an
+/// [`DependencyKind::After`] dependency as well to start the other service first. Managers that
+/// are unable to express a kind of dependency fail the install with [`Error::Unsupported`].
+/// Names are written as they are into scripts and unit files, so systemd, OpenRC and rc.d fail
+/// the install with [`Error::InvalidDefinition`] for names holding whitespace, control
+/// characters, characters with a special meaning to the shell or `%`.
+///
+/// ```
+/// use service_manager::{DependencyKind, ServiceDependency};
+///
+/// let dependencies = vec![
+///     ServiceDependency::requires("rabbitmq-server"),
+///     ServiceDependency::after("rabbitmq-server"),
+/// ];
+/// assert_eq!(dependencies[1].kind, DependencyKind::After);
+/// ```
+///
+/// [`ServiceInstallCtx`]: crate::ServiceInstallCtx
+/// [`ServiceNaming::native_name`]: crate::ServiceNaming::native_name
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ServiceDependency {
+    /// Relationship with the other service
+    pub kind: DependencyKind,
+
+    /// Name of the other service
+    pub service: String,
+}
+
+impl ServiceDependency {
+    /// Creates a dependency of `kind` on `service`
+    pub fn new(kind: DependencyKind, service: impl Into<String>) -> Self {
+        Self {
+            kind,
+            service: service.into(),
+        }
+    }
+
+    /// Creates a dependency requiring `service` to be running
+    pub fn requires(service: impl Into<String>) -> Self {
+        Self::new(DependencyKind::Requires, service)
+    }
+
+    /// Creates a dependency starting `service` along with this one if possible
+    pub fn wants(service: impl Into<String>) -> Self {
+        Self::new(DependencyKind::Wants, service)
+    }
+
+    /// Creates a dependency starting this service after `service`
+    pub fn after(service: impl Into<String>) -> Self {
+        Self::new(DependencyKind::After, service)
+    }
+
+    /// Creates a dependency starting this service before `service`
+    pub fn before(service: impl Into<String>) -> Self {
+        Self::new(DependencyKind::Before, service)
+    }
+
+    /// Creates a dependency preventing this service from running along with `service`
+    pub fn conflicts(service: impl Into<String>) -> Self {
+        Self::new(DependencyKind::Conflicts, service)
+    }
+}
+
+/// Reports that `manager` is unable to express dependencies of `kind`
+pub(crate) fn unsupported_dependency(manager: &str, kind: DependencyKind) -> io::Error {
+    Error::Unsupported(format!(
+        "{manager} is unable to express {kind} dependencies"
+    ))
+    .into()
+}
+
+/// Checks that `service` can be written as is into the definitions of `manager`, which paste
+/// it unquoted into shell scripts or unit files
+///
+/// Names holding whitespace, control characters, characters with a special meaning to the shell
+/// or the `%` of systemd specifiers are rejected rather than escaped, as no service manager
+/// names its services with them.
+pub(crate) fn validate_dependency_name(manager: &str, service: &str) -> io::Result<()> {
+    if !service.is_empty()
+        && service
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@+=:,./-".contains(c))
+    {
+        return Ok(());
+    }
+    Err(Error::InvalidDefinition(format!(
+        "{manager} is unable to depend on {service:?}, as service names may only hold letters, \
+         digits and any of `_@+=:,./-`"
+    ))
+    .into())
+}
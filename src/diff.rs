@@ -0,0 +1,340 @@
+use super::RenderedFile;
+use std::path::PathBuf;
+
+/// Number of unchanged lines shown around each change of a unified diff
+const CONTEXT_LINES: usize = 3;
+
+/// State of an installed service definition compared with the one it would be installed with
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum DiffState {
+    /// Definition is not installed
+    Missing,
+
+    /// Definition is installed with the same contents
+    Identical,
+
+    /// Definition is installed with different contents, or only partially installed
+    Differs,
+}
+
+/// Comparison of the definition a service would be installed with and the one that is installed,
+/// as returned by [`ServiceManager::diff`](crate::ServiceManager::diff)
+///
+/// ```
+/// use service_manager::{DiffState, FileDiff, RenderedFile, ServiceDiff};
+///
+/// let rendered = RenderedFile {
+///     path: "/etc/init.d/echo".into(),
+///     contents: b"#!/bin/sh\necho hello\n".to_vec(),
+///     mode: 0o755,
+/// };
+/// let diff = ServiceDiff::new(vec![FileDiff::new(&rendered, Some(b"#!/bin/sh\necho bye\n"))]);
+///
+/// assert_eq!(diff.state, DiffState::Differs);
+/// assert!(diff.unified().contains("-echo bye\n+echo hello\n"));
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ServiceDiff {
+    /// Overall state of the definition, which is missing when none of its files exist and
+    /// identical when all of them match
+    pub state: DiffState,
+
+    /// Comparison of each file of the definition, in the order they are rendered
+    pub files: Vec<FileDiff>,
+}
+
+impl ServiceDiff {
+    /// Combines the comparisons of the files making up the definition of a service
+    pub fn new(files: Vec<FileDiff>) -> Self {
+        let state = if files.iter().all(|file| file.state == DiffState::Missing) {
+            DiffState::Missing
+        } else if files.iter().all(|file| file.state == DiffState::Identical) {
+            DiffState::Identical
+        } else {
+            DiffState::Differs
+        };
+
+        Self { state, files }
+    }
+
+    /// Unified diff of every file, which is empty when the definition is identical
+    pub fn unified(&self) -> String {
+        self.files
+            .iter()
+            .map(|file| file.unified.as_str())
+            .collect()
+    }
+}
+
+/// Comparison of a single file of a service definition
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct FileDiff {
+    /// Path of the file
+    pub path: PathBuf,
+
+    /// State of the installed file
+    pub state: DiffState,
+
+    /// Unified diff turning the installed file into the rendered one, which is empty when they
+    /// are identical
+    ///
+    /// Contents that are not valid unicode are shown with replacement characters.
+    pub unified: String,
+}
+
+impl FileDiff {
+    /// Compares a rendered file with the contents of the installed one, if it exists
+    pub fn new(rendered: &RenderedFile, installed: Option<&[u8]>) -> Self {
+        let path = rendered.path.clone();
+        let state = match installed {
+            None => DiffState::Missing,
+            Some(installed) if installed == rendered.contents => DiffState::Identical,
+            Some(_) => DiffState::Differs,
+        };
+
+        let unified = match state {
+            DiffState::Identical => String::new(),
+            _ => {
+                let old_name = match installed {
+                    Some(_) => path.to_string_lossy().into_owned(),
+                    None => "/dev/null".to_string(),
+                };
+                unified_diff(
+                    &String::from_utf8_lossy(installed.unwrap_or_default()),
+                    &String::from_utf8_lossy(&rendered.contents),
+                    &old_name,
+                    &path.to_string_lossy(),
+                )
+            }
+        };
+
+        Self {
+            path,
+            state,
+            unified,
+        }
+    }
+}
+
+/// Line of a diff, tagged with whether it is kept, removed or added
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Equal(&'a str),
+    Delete(&'a str),
+    Insert(&'a str),
+}
+
+/// Produces a unified diff turning `old` into `new`, named `old_name` and `new_name` in its
+/// headers
+fn unified_diff(old: &str, new: &str, old_name: &str, new_name: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+
+    let changes: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !matches!(line, DiffLine::Equal(_)))
+        .map(|(i, _)| i)
+        .collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+
+    // Changes separated by no more than twice the context share a hunk
+    let mut start = 0;
+    while start < changes.len() {
+        let mut end = start;
+        while end + 1 < changes.len() && changes[end + 1] - changes[end] <= 2 * CONTEXT_LINES + 1 {
+            end += 1;
+        }
+
+        let first = changes[start].saturating_sub(CONTEXT_LINES);
+        let last = (changes[end] + CONTEXT_LINES + 1).min(lines.len());
+        let old_before = lines[..first]
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Insert(_)))
+            .count();
+        let new_before = lines[..first]
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Delete(_)))
+            .count();
+        let hunk = &lines[first..last];
+        let old_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Insert(_)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|line| !matches!(line, DiffLine::Delete(_)))
+            .count();
+
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_before, old_len),
+            hunk_range(new_before, new_len)
+        ));
+        for line in hunk {
+            let (prefix, text) = match line {
+                DiffLine::Equal(text) => (' ', text),
+                DiffLine::Delete(text) => ('-', text),
+                DiffLine::Insert(text) => ('+', text),
+            };
+            out.push(prefix);
+            out.push_str(text);
+            out.push('\n');
+        }
+
+        start = end + 1;
+    }
+
+    out
+}
+
+/// Formats the range of a hunk, which starts after the line preceding it when it is empty
+fn hunk_range(before: usize, len: usize) -> String {
+    match len {
+        0 => format!("{before},0"),
+        1 => format!("{}", before + 1),
+        _ => format!("{},{len}", before + 1),
+    }
+}
+
+/// Computes the lines kept, removed and added to turn `old` into `new`, using the longest common
+/// subsequence of the lines that differ between their common prefix and suffix
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let prefix = old
+        .iter()
+        .zip(new.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the length of the longest common subsequence of old_mid[i..] and new_mid[j..]
+    let mut lcs = vec![vec![0usize; new_mid.len() + 1]; old_mid.len() + 1];
+    for i in (0..old_mid.len()).rev() {
+        for j in (0..new_mid.len()).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines: Vec<DiffLine<'a>> = old[..prefix].iter().map(|l| DiffLine::Equal(l)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < old_mid.len() || j < new_mid.len() {
+        if i < old_mid.len() && j < new_mid.len() && old_mid[i] == new_mid[j] {
+            lines.push(DiffLine::Equal(old_mid[i]));
+            i += 1;
+            j += 1;
+        } else if j == new_mid.len() || (i < old_mid.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(DiffLine::Delete(old_mid[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Insert(new_mid[j]));
+            j += 1;
+        }
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Equal(l)));
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    fn rendered(contents: &str) -> RenderedFile {
+        RenderedFile {
+            path: PathBuf::from("/etc/systemd/system/example-echo.service"),
+            contents: contents.as_bytes().to_vec(),
+            mode: 0o644,
+        }
+    }
+
+    #[test]
+    fn test_file_diff_states() {
+        let file = rendered("[Unit]\n");
+        assert_eq!(FileDiff::new(&file, None).state, DiffState::Missing);
+        assert_eq!(
+            FileDiff::new(&file, Some(b"[Unit]\n")).state,
+            DiffState::Identical
+        );
+        assert_eq!(
+            FileDiff::new(&file, Some(b"[Service]\n")).state,
+            DiffState::Differs
+        );
+        assert!(FileDiff::new(&file, Some(b"[Unit]\n")).unified.is_empty());
+
+        let diff = ServiceDiff::new(vec![
+            FileDiff::new(&file, None),
+            FileDiff::new(&file, Some(b"[Unit]\n")),
+        ]);
+        assert_eq!(diff.state, DiffState::Differs);
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let installed = indoc! {"
+            [Unit]
+            Description=com.example.echo
+            [Service]
+            ExecStart=/usr/local/bin/echo hello
+            Restart=on-failure
+            RestartSec=5
+            Environment=A=1
+            [Install]
+            WantedBy=multi-user.target
+        "};
+        let expected = installed.replace("echo hello", "echo goodbye");
+        let diff = FileDiff::new(&rendered(&expected), Some(installed.as_bytes()));
+
+        assert_eq!(
+            diff.unified,
+            indoc! {"
+                --- /etc/systemd/system/example-echo.service
+                +++ /etc/systemd/system/example-echo.service
+                @@ -1,7 +1,7 @@
+                 [Unit]
+                 Description=com.example.echo
+                 [Service]
+                -ExecStart=/usr/local/bin/echo hello
+                +ExecStart=/usr/local/bin/echo goodbye
+                 Restart=on-failure
+                 RestartSec=5
+                 Environment=A=1
+            "}
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_of_missing_file() {
+        let diff = FileDiff::new(&rendered("[Unit]\nDescription=echo\n"), None);
+        assert_eq!(
+            diff.unified,
+            indoc! {"
+                --- /dev/null
+                +++ /etc/systemd/system/example-echo.service
+                @@ -0,0 +1,2 @@
+                +[Unit]
+                +Description=echo
+            "}
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use std::{error, fmt, io};
+
+/// Errors that can occur when working with a [`ServiceManager`](crate::ServiceManager)
+///
+/// Operations of [`ServiceManager`](crate::ServiceManager) return [`io::Error`], which carries
+/// this type as its inner error. Use [`Error::from_io_error`] to inspect it.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The service, identified by its native name, is not installed
+    NotInstalled(String),
+
+    /// The service, identified by its native name, is already installed
+    AlreadyInstalled(String),
+
+    /// The current user is not allowed to perform the operation
+    PermissionDenied(String),
+
+    /// The program used to communicate with the service manager could not be found
+    ToolNotFound(String),
+
+    /// The program used to communicate with the service manager failed
+    CommandFailed {
+        /// Program that was executed
+        program: String,
+
+        /// Arguments provided to the program
+        args: Vec<String>,
+
+        /// Exit code of the program, if it exited normally
+        exit_code: Option<i32>,
+
+        /// Decoded standard output of the program
+        stdout: String,
+
+        /// Decoded standard error of the program
+        stderr: String,
+    },
+
+    /// The operation is not supported by the service manager
+    Unsupported(String),
+
+    /// The service definition is invalid or cannot be represented by the service manager
+    InvalidDefinition(String),
+
+    /// The label of the service, or the native name derived from it, is invalid
+    InvalidLabel(String),
+
+    /// The native name of the service is already used by a service with a different label
+    NameConflict {
+        /// Native name shared by both services
+        name: String,
+
+        /// Label of the service already using the name
+        existing: String,
+    },
+
+    /// The service, identified by its native name, has no previous definition to roll back to
+    NoBackup(String),
+
+    /// Any other I/O error, such as failing to write a service definition
+    Io(io::Error),
+}
+
+impl Error {
+    /// Returns the [`Error`] carried by an [`io::Error`] returned from a
+    /// [`ServiceManager`](crate::ServiceManager), if there is one
+    pub fn from_io_error(err: &io::Error) -> Option<&Self> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<Self>())
+    }
+
+    /// Returns the [`io::ErrorKind`] that best describes this error
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Self::NotInstalled(_) => io::ErrorKind::NotFound,
+            Self::AlreadyInstalled(_) => io::ErrorKind::AlreadyExists,
+            Self::PermissionDenied(_) => io::ErrorKind::PermissionDenied,
+            Self::ToolNotFound(_) => io::ErrorKind::NotFound,
+            Self::CommandFailed { .. } => io::ErrorKind::Other,
+            Self::Unsupported(_) => io::ErrorKind::Unsupported,
+            Self::InvalidDefinition(_) => io::ErrorKind::InvalidData,
+            Self::InvalidLabel(_) => io::ErrorKind::InvalidInput,
+            Self::NameConflict { .. } => io::ErrorKind::AlreadyExists,
+            Self::NoBackup(_) => io::ErrorKind::NotFound,
+            Self::Io(x) => x.kind(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotInstalled(service) => write!(f, "Service {service} is not installed"),
+            Self::AlreadyInstalled(service) => write!(f, "Service {service} is already installed"),
+            Self::PermissionDenied(msg) => write!(f, "Permission denied: {msg}"),
+            Self::ToolNotFound(program) => write!(f, "Unable to find {program}"),
+            Self::CommandFailed {
+                exit_code,
+                stdout,
+                stderr,
+                ..
+            } => {
+                let mut msg = stderr.as_str();
+                if msg.trim().is_empty() {
+                    msg = stdout.as_str();
+                }
+                if msg.is_empty() {
+                    msg = "Failed to execute command with no output";
+                }
+                write!(
+                    f,
+                    "Command failed with exit code {}: {}",
+                    exit_code.unwrap_or(-1),
+                    msg
+                )
+            }
+            Self::Unsupported(msg) => f.write_str(msg),
+            Self::InvalidDefinition(msg) => f.write_str(msg),
+            Self::InvalidLabel(msg) => f.write_str(msg),
+            Self::NameConflict { name, existing } => {
+                write!(f, "Service name {name} is already used by {existing}")
+            }
+            Self::NoBackup(service) => {
+                write!(
+                    f,
+                    "Service {service} has no previous definition to roll back to"
+                )
+            }
+            Self::Io(x) => write!(f, "{x}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(x) => Some(x),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        if Self::from_io_error(&err).is_some() {
+            // Checked above, so the inner error is guaranteed to be present and of our type
+            let inner = err.into_inner().unwrap();
+            *inner.downcast::<Self>().unwrap()
+        } else {
+            Self::Io(err)
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(x) => x,
+            x => io::Error::new(x.kind(), x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_round_trips_through_io_error() {
+        let err: io::Error = Error::NotInstalled("example-app".to_string()).into();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(matches!(
+            Error::from_io_error(&err),
+            Some(Error::NotInstalled(service)) if service == "example-app"
+        ));
+
+        let err = Error::from(err);
+        assert!(matches!(err, Error::NotInstalled(service) if service == "example-app"));
+    }
+
+    #[test]
+    fn test_plain_io_error_is_wrapped() {
+        let err = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert!(Error::from_io_error(&err).is_none());
+
+        let err: io::Error = Error::from(err).into();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "missing");
+    }
+}
@@ -1,3 +1,4 @@
+use super::Error;
 use cfg_if::cfg_if;
 use std::io;
 
@@ -64,15 +65,15 @@ impl ServiceManagerKind {
                     return Ok(ServiceManagerKind::OpenRc);
                 }
 
-                Err(io::Error::new(
-                    io::ErrorKind::Unsupported,
-                    "Only systemd and openrc are supported on Linux",
-                ))
+                Err(Error::Unsupported(
+                    "Only systemd and openrc are supported on Linux".to_string(),
+                )
+                .into())
             } else {
-                Err(io::Error::new(
-                    io::ErrorKind::Unsupported,
-                    "Service manager are not supported on current Operating System!",
-                ))
+                Err(Error::Unsupported(
+                    "Service manager are not supported on current Operating System!".to_string(),
+                )
+                .into())
             }
         }
     }
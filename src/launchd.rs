@@ -1,29 +1,48 @@
-use crate::utils::wrap_output;
+use crate::utils::{wrap_output, CommandOutput};
 
 use super::{
-    utils, ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    dependency::unsupported_dependency, limits::unsupported_limit, logs, naming, utils,
+    CommandRunner, DiffState, Error, InstallOutcome, LogLines, LogQuery, RenderedFile,
+    RenderedService, ResourceLimits, ServiceCommand, ServiceDisableCtx, ServiceEnableCtx,
+    ServiceInstallCtx, ServiceLabel, ServiceLevel, ServiceManager, ServiceNaming, ServiceOutput,
+    ServiceRestartCtx, ServiceRollbackCtx, ServiceStartCtx, ServiceState, ServiceStatusDetail,
+    ServiceStopCtx, ServiceUninstallCtx, SharedCommandRunner,
 };
 use plist::{Dictionary, Value};
 use std::{
-    borrow::Cow,
+    collections::HashMap,
     ffi::OsStr,
     io,
-    path::PathBuf,
-    process::{Command, Output, Stdio},
+    path::{Path, PathBuf},
 };
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 static LAUNCHCTL: &str = "launchctl";
 const PLIST_FILE_PERMISSIONS: u32 = 0o644;
 
+/// Maximum length of a service name, excluding the `.plist` suffix of its file
+const MAX_SERVICE_NAME_LEN: usize = 255 - ".plist".len();
+
 /// Configuration settings tied to launchd services
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct LaunchdConfig {
     pub install: LaunchdInstallConfig,
+
+    /// Optional directory to use as the root of the filesystem when locating plists
+    ///
+    /// Services installed into a root are not loaded, as `launchd` picks them up when the root
+    /// is booted, and starting or stopping them is unsupported.
+    pub root: Option<PathBuf>,
 }
 
 /// Configuration settings tied to launchd services during installation
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct LaunchdInstallConfig {
     /// If true, will include `KeepAlive` flag set to true
     pub keep_alive: bool,
@@ -36,13 +55,33 @@ impl Default for LaunchdInstallConfig {
 }
 
 /// Implementation of [`ServiceManager`] for MacOS's [Launchd](https://en.wikipedia.org/wiki/Launchd)
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct LaunchdServiceManager {
     /// Whether or not this manager is operating at the user-level
     pub user: bool,
 
     /// Configuration settings tied to launchd services
     pub config: LaunchdConfig,
+
+    /// Strategy deriving the label of a service within launchd, which also names its plist
+    pub naming: ServiceNaming,
+
+    /// Runner used to execute the commands of the service manager
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub runner: SharedCommandRunner,
+}
+
+impl Default for LaunchdServiceManager {
+    fn default() -> Self {
+        Self {
+            user: false,
+            config: LaunchdConfig::default(),
+            naming: ServiceNaming::QualifiedName,
+            runner: SharedCommandRunner::default(),
+        }
+    }
 }
 
 impl LaunchdServiceManager {
@@ -59,35 +98,69 @@ impl LaunchdServiceManager {
     /// Change manager to work with system services
     pub fn into_system(self) -> Self {
         Self {
-            config: self.config,
             user: false,
+            ..self
         }
     }
 
     /// Change manager to work with user services
     pub fn into_user(self) -> Self {
-        Self {
-            config: self.config,
-            user: true,
-        }
+        Self { user: true, ..self }
     }
 
     /// Update manager to use the specified config
     pub fn with_config(self, config: LaunchdConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Update manager to name services using the specified strategy
+    pub fn with_naming(self, naming: ServiceNaming) -> Self {
+        Self { naming, ..self }
+    }
+
+    /// Update manager to execute commands using the specified runner
+    pub fn with_runner(self, runner: impl CommandRunner + 'static) -> Self {
         Self {
-            config,
-            user: self.user,
+            runner: SharedCommandRunner::new(runner),
+            ..self
         }
     }
 
-    fn get_plist_path(&self, qualified_name: String) -> PathBuf {
+    /// Directory holding the plists of the services managed at the current level
+    fn dir_path(&self) -> io::Result<PathBuf> {
         let dir_path = if self.user {
-            user_agent_dir_path().unwrap()
+            user_agent_dir_path()?
         } else {
             global_daemon_dir_path()
         };
 
-        dir_path.join(format!("{}.plist", qualified_name))
+        Ok(utils::rooted(self.config.root.as_deref(), dir_path))
+    }
+
+    fn get_plist_path(&self, name: String) -> io::Result<PathBuf> {
+        Ok(self.dir_path()?.join(format!("{}.plist", name)))
+    }
+
+    /// Updates `RunAtLoad` within the plist of the service with `label`, also clearing any
+    /// override disabling the service in `launchd` when `enabled` is true
+    fn set_enabled(&self, label: &ServiceLabel, enabled: bool) -> io::Result<()> {
+        let name = self.naming.native_name(label);
+        let plist_path = self.get_plist_path(name.clone())?;
+        let plist = std::fs::read(&plist_path).map_err(|x| utils::not_installed(x, &name))?;
+        let plist = set_run_at_load(&plist, enabled)?;
+        utils::write_file(&plist_path, &plist, PLIST_FILE_PERMISSIONS)?;
+
+        if enabled && self.config.root.is_none() {
+            let uid = if self.user {
+                Some(current_uid(&self.runner)?)
+            } else {
+                None
+            };
+            let target = service_target(&name, uid.as_deref());
+            wrap_output(launchctl(&self.runner, "enable", &target)?)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -101,59 +174,134 @@ impl ServiceManager for LaunchdServiceManager {
     }
 
     fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
-        let dir_path = if self.user {
-            user_agent_dir_path()?
-        } else {
-            global_daemon_dir_path()
-        };
-
-        std::fs::create_dir_all(&dir_path)?;
-
-        let qualified_name = ctx.label.to_qualified_name();
-        let plist_path = dir_path.join(format!("{}.plist", qualified_name));
-        let plist = match ctx.contents {
-            Some(contents) => contents,
-            _ => make_plist(
-                &self.config.install,
-                &qualified_name,
-                ctx.cmd_iter(),
-                ctx.username.clone(),
-                ctx.working_directory.clone(),
-                ctx.environment.clone(),
-                ctx.autostart,
-                ctx.disable_restart_on_failure
-            ),
-        };
+        let rendered = self.render(&ctx)?;
 
         // Unload old service first if it exists
-        if plist_path.exists() {
-            let _ = wrap_output(launchctl("remove", ctx.label.to_qualified_name().as_str())?);
+        if self.config.root.is_none() && rendered.files.iter().any(|file| file.path.exists()) {
+            let _ = wrap_output(launchctl(
+                &self.runner,
+                "remove",
+                self.naming.native_name(&ctx.label).as_str(),
+            )?);
+        }
+
+        utils::install_rendered(&self.runner, &rendered)
+    }
+
+    /// Replaces a changed plist by removing the service and loading it again, as `launchd` only
+    /// reads plists when loading them
+    ///
+    /// Loading the service starts it if it runs at load or is kept alive, otherwise it is only
+    /// started again if it was running.
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        if self.config.root.is_some() {
+            return utils::install_or_update_rendered(self, &self.runner, ctx, &[]);
+        }
+
+        let rendered = self.render(&ctx)?;
+        match utils::diff_rendered(&rendered.files)?.state {
+            DiffState::Missing => {
+                self.install(ctx)?;
+                Ok(InstallOutcome::Installed)
+            }
+            DiffState::Identical => Ok(InstallOutcome::Unchanged),
+            DiffState::Differs => {
+                let label = ctx.label.clone();
+                let status = self.status(crate::ServiceStatusCtx::new(label.clone()))?;
+                let running = status == crate::ServiceStatus::Running;
+                self.install(ctx)?;
+                let status = self.status(crate::ServiceStatusCtx::new(label.clone()))?;
+                if running && status != crate::ServiceStatus::Running {
+                    self.start(ServiceStartCtx::new(label))?;
+                }
+                Ok(InstallOutcome::Updated { restarted: running })
+            }
+        }
+    }
+
+    /// Renders the plist of the service and the command loading it.
+    ///
+    /// When installing over an existing service, the old service is removed beforehand, which is
+    /// not part of the rendered commands.
+    ///
+    /// `launchd` starts jobs as soon as they are loaded, without any ordering between them, so
+    /// dependencies on other services are reported as unsupported. Services that need another
+    /// one should wait for it themselves, such as by retrying their connection to it.
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        let dir_path = self.dir_path()?;
+
+        let name = self.naming.native_name(&ctx.label);
+        naming::validate_native_name(&name, MAX_SERVICE_NAME_LEN, "launchd")?;
+        let plist_path = dir_path.join(format!("{}.plist", name));
+        utils::ensure_no_conflict(&plist_path, &name, &ctx.label)?;
+
+        if let Some(dependency) = ctx.dependencies.first() {
+            return Err(unsupported_dependency("launchd", dependency.kind));
         }
 
-        utils::write_file(
-            plist_path.as_path(),
-            plist.as_bytes(),
-            PLIST_FILE_PERMISSIONS,
-        )?;
+        let plist = match &ctx.contents {
+            Some(contents) => contents.clone(),
+            _ => utils::mark_label(
+                make_plist(
+                    &self.config.install,
+                    &name,
+                    ctx.cmd_iter(),
+                    ctx.username.clone(),
+                    ctx.working_directory.clone(),
+                    ctx.environment.clone(),
+                    ctx.autostart,
+                    ctx.disable_restart_on_failure,
+                    output_path(&ctx.stdout, "stdout")?,
+                    output_path(&ctx.stderr, "stderr")?,
+                    resource_limits(&ctx.limits)?,
+                )?,
+                &ctx.label,
+                utils::MarkerStyle::Xml,
+            ),
+        };
 
         // Load the service.
         // If "KeepAlive" is set to true, the service will immediately start.
-        wrap_output(launchctl("load", plist_path.to_string_lossy().as_ref())?)?;
+        let mut commands = Vec::new();
+        if self.config.root.is_none() {
+            commands.push(launchctl_command(
+                "load",
+                [plist_path.to_string_lossy().as_ref()],
+            ));
+        }
 
-        Ok(())
+        Ok(RenderedService {
+            files: vec![RenderedFile {
+                path: plist_path,
+                contents: plist.into_bytes(),
+                mode: PLIST_FILE_PERMISSIONS,
+            }],
+            commands,
+        })
     }
 
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
-        let plist_path = self.get_plist_path(ctx.label.to_qualified_name());
+        let plist_path = self.get_plist_path(self.naming.native_name(&ctx.label))?;
         // Service might already be removed (if it has "KeepAlive")
-        let _ = wrap_output(launchctl("remove", ctx.label.to_qualified_name().as_str())?);
-        let _ = std::fs::remove_file(plist_path);
+        if self.config.root.is_none() {
+            let _ = wrap_output(launchctl(
+                &self.runner,
+                "remove",
+                self.naming.native_name(&ctx.label).as_str(),
+            )?);
+        }
+        let _ = utils::remove_definition(&plist_path);
         Ok(())
     }
 
     fn start(&self, ctx: ServiceStartCtx) -> io::Result<()> {
+        utils::ensure_no_root(self.config.root.as_deref(), "start")?;
         // To start services that do not have "KeepAlive" set to true
-        wrap_output(launchctl("start", ctx.label.to_qualified_name().as_str())?)?;
+        wrap_output(launchctl(
+            &self.runner,
+            "start",
+            self.naming.native_name(&ctx.label).as_str(),
+        )?)?;
         Ok(())
     }
 
@@ -161,10 +309,100 @@ impl ServiceManager for LaunchdServiceManager {
     ///
     /// To stop a service with "KeepAlive" enabled, call `uninstall` instead.
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()> {
-        wrap_output(launchctl("stop", ctx.label.to_qualified_name().as_str())?)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+        wrap_output(launchctl(
+            &self.runner,
+            "stop",
+            self.naming.native_name(&ctx.label).as_str(),
+        )?)?;
+        Ok(())
+    }
+
+    /// Restarts a service by killing any running instance and starting it again.
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+        let uid = if self.user {
+            Some(current_uid(&self.runner)?)
+        } else {
+            None
+        };
+        let target = service_target(&self.naming.native_name(&ctx.label), uid.as_deref());
+        wrap_output(launchctl_with_args(
+            &self.runner,
+            "kickstart",
+            ["-k", target.as_str()],
+        )?)?;
+        Ok(())
+    }
+
+    /// Sets `RunAtLoad` in the plist of the service, and runs `launchctl enable` to clear any
+    /// override that would keep `launchd` from loading it.
+    ///
+    /// The new setting takes effect the next time the plist is loaded, such as at boot or login.
+    fn enable(&self, ctx: ServiceEnableCtx) -> io::Result<()> {
+        self.set_enabled(&ctx.label, true)
+    }
+
+    /// Clears `RunAtLoad` in the plist of the service.
+    ///
+    /// `launchctl disable` is not used, as it would also prevent starting the service manually.
+    /// A service with `KeepAlive` set is still started whenever its plist is loaded.
+    fn disable(&self, ctx: ServiceDisableCtx) -> io::Result<()> {
+        self.set_enabled(&ctx.label, false)
+    }
+
+    /// Reports whether `RunAtLoad` is set in the plist of the service.
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        let name = self.naming.native_name(&ctx.label);
+        let plist_path = self.get_plist_path(name.clone())?;
+        let plist = std::fs::read(plist_path).map_err(|x| utils::not_installed(x, &name))?;
+        run_at_load(&plist)
+    }
+
+    /// Restores the previous plist and loads it again, as `launchd` only reads plists when
+    /// loading them
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> io::Result<()> {
+        let name = self.naming.native_name(&ctx.label);
+        let plist_path = self.get_plist_path(name.clone())?;
+        if !plist_path.exists() {
+            return Err(Error::NotInstalled(name).into());
+        }
+
+        utils::restore_backup(&plist_path, &name, PLIST_FILE_PERMISSIONS)?;
+        if self.config.root.is_none() {
+            let _ = wrap_output(launchctl(&self.runner, "remove", &name)?);
+            wrap_output(launchctl(
+                &self.runner,
+                "load",
+                &plist_path.to_string_lossy(),
+            )?)?;
+        }
         Ok(())
     }
 
+    /// Reads the files named by `StandardOutPath` and `StandardErrorPath` in the plist of the
+    /// service, as `launchd` discards output that is not redirected to a file
+    fn logs(&self, label: ServiceLabel, query: LogQuery) -> io::Result<LogLines> {
+        let name = self.naming.native_name(&label);
+        let plist_path = self.get_plist_path(name.clone())?;
+        let plist = std::fs::read(plist_path).map_err(|x| utils::not_installed(x, &name))?;
+
+        let paths = log_paths(&plist)?;
+        if paths.is_empty() {
+            return Err(Error::Unsupported(format!(
+                "Service {name} does not set StandardOutPath or StandardErrorPath, so launchd \
+                 does not keep its output"
+            ))
+            .into());
+        }
+
+        let root = self.config.root.as_deref();
+        logs::tail_files(
+            paths.into_iter().map(|x| utils::rooted(root, x)).collect(),
+            &query,
+        )
+    }
+
     fn level(&self) -> ServiceLevel {
         if self.user {
             ServiceLevel::User
@@ -183,77 +421,256 @@ impl ServiceManager for LaunchdServiceManager {
     }
 
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<crate::ServiceStatus> {
-        let mut service_name = ctx.label.to_qualified_name();
-        // Due to we could not get the status of a service via a service label, so we have to run this command twice
-        // in first time, if there is a service exists, the output will advice us a full service label with a prefix.
-        // Or it will return nothing, it means the service is not installed(not exists).
-        let mut out: Cow<str> = Cow::Borrowed("");
-        for i in 0..2 {
-            let output = launchctl("print", &service_name)?;
-            if !output.status.success() {
-                if output.status.code() == Some(64) {
-                    // 64 is the exit code for a service not found
-                    out = Cow::Owned(String::from_utf8_lossy(&output.stderr).to_string());
-                    if out.trim().is_empty() {
-                        out = Cow::Owned(String::from_utf8_lossy(&output.stdout).to_string());
-                    }
-                    if i == 0 {
-                        let label = out.lines().find(|line| line.contains(&service_name));
-                        match label {
-                            Some(label) => {
-                                service_name = label.trim().to_string();
-                                continue;
-                            }
-                            None => return Ok(crate::ServiceStatus::NotInstalled),
-                        }
-                    } else {
-                        // We have access to the full service label, so it impossible to get the failed status, or it must be input error.
-                        return Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!(
-                                "Command failed with exit code {}: {}",
-                                output.status.code().unwrap_or(-1),
-                                out
-                            ),
-                        ));
-                    }
-                } else {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "Command failed with exit code {}: {}",
-                            output.status.code().unwrap_or(-1),
-                            String::from_utf8_lossy(&output.stderr)
-                        ),
-                    ));
-                }
-            }
-            out = Cow::Owned(String::from_utf8_lossy(&output.stdout).to_string());
+        if self.config.root.is_some() {
+            let plist_path = self.get_plist_path(self.naming.native_name(&ctx.label))?;
+            return Ok(utils::offline_status(&plist_path));
         }
-        let lines = out
-            .lines()
-            .map(|s| s.trim())
-            .filter(|s| s.contains("state"))
-            .collect::<Vec<&str>>();
-        if lines
-            .into_iter()
-            .any(|s| !s.contains("not running") && s.contains("running"))
-        {
-            Ok(crate::ServiceStatus::Running)
-        } else {
-            Ok(crate::ServiceStatus::Stopped(None))
+
+        match print_service(&self.runner, &self.naming.native_name(&ctx.label))? {
+            Some(out) => Ok(status_from_print(&out)),
+            None => Ok(crate::ServiceStatus::NotInstalled),
+        }
+    }
+
+    fn status_detail(&self, ctx: crate::ServiceStatusCtx) -> io::Result<ServiceStatusDetail> {
+        if self.config.root.is_some() {
+            return Ok(self.status(ctx)?.into());
+        }
+
+        match print_service(&self.runner, &self.naming.native_name(&ctx.label))? {
+            Some(out) => Ok(parse_print(&out)),
+            None => Ok(ServiceStatusDetail::new(ServiceState::NotInstalled)),
         }
     }
+
+    fn list(&self, ctx: crate::ServiceListCtx) -> io::Result<Vec<crate::InstalledService>> {
+        let dir_path = self.dir_path()?;
+
+        let mut services = Vec::new();
+        let plists = plist_definitions(&dir_path, utils::list_dir_names(&dir_path, false)?);
+        for label in utils::definition_labels(plists, &self.naming, &ctx) {
+            let status = self.status(crate::ServiceStatusCtx {
+                label: label.clone(),
+            })?;
+            services.push(crate::InstalledService { label, status });
+        }
+
+        Ok(services)
+    }
 }
 
-fn launchctl(cmd: &str, label: &str) -> io::Result<Output> {
-    Command::new(LAUNCHCTL)
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .arg(cmd)
-        .arg(label)
-        .output()
+/// Prints the information `launchd` has about a service, returning `None` if it is not installed
+fn print_service(runner: &dyn CommandRunner, qualified_name: &str) -> io::Result<Option<String>> {
+    let mut service_name = qualified_name.to_string();
+    // Due to we could not get the status of a service via a service label, so we have to run this command twice
+    // in first time, if there is a service exists, the output will advice us a full service label with a prefix.
+    // Or it will return nothing, it means the service is not installed(not exists).
+    for i in 0..2 {
+        let output = launchctl(runner, "print", &service_name)?;
+        match check_print(output, &service_name, i == 0)? {
+            PrintAttempt::Printed(out) => return Ok(Some(out)),
+            PrintAttempt::Retry(name) => service_name = name,
+            PrintAttempt::NotInstalled => return Ok(None),
+        }
+    }
+
+    unreachable!("second attempt always returns")
+}
+
+/// Outcome of a single `launchctl print` made by [`print_service`]
+enum PrintAttempt {
+    /// The service was found, along with its printed information
+    Printed(String),
+
+    /// The service was not found, but is likely known by this full service label
+    Retry(String),
+
+    /// The service was not found
+    NotInstalled,
+}
+
+/// Interprets the output of `launchctl print {service_name}`
+fn check_print(
+    output: CommandOutput,
+    service_name: &str,
+    first_attempt: bool,
+) -> io::Result<PrintAttempt> {
+    if output.status.success() {
+        return Ok(PrintAttempt::Printed(
+            String::from_utf8_lossy(&output.stdout).to_string(),
+        ));
+    }
+
+    if output.status.code() == Some(64) && first_attempt {
+        // 64 is the exit code for a service not found
+        let mut out = String::from_utf8_lossy(&output.stderr).to_string();
+        if out.trim().is_empty() {
+            out = String::from_utf8_lossy(&output.stdout).to_string();
+        }
+        match out.lines().find(|line| line.contains(service_name)) {
+            Some(label) => Ok(PrintAttempt::Retry(label.trim().to_string())),
+            None => Ok(PrintAttempt::NotInstalled),
+        }
+    } else {
+        // We have access to the full service label, so it impossible to get the failed status, or it must be input error.
+        Err(utils::command_failed(output).into())
+    }
+}
+
+/// Derives the status of a service from the information printed by `launchctl print`
+fn status_from_print(out: &str) -> crate::ServiceStatus {
+    let lines = out
+        .lines()
+        .map(|s| s.trim())
+        .filter(|s| s.contains("state"))
+        .collect::<Vec<&str>>();
+    if lines
+        .into_iter()
+        .any(|s| !s.contains("not running") && s.contains("running"))
+    {
+        crate::ServiceStatus::Running
+    } else {
+        crate::ServiceStatus::Stopped(None)
+    }
+}
+
+/// Parses the `plist` of a service as a dictionary
+fn parse_plist(plist: &[u8]) -> io::Result<Value> {
+    let value = Value::from_reader_xml(plist)
+        .map_err(|x| Error::InvalidDefinition(format!("Failed to parse the plist: {x}")))?;
+    if value.as_dictionary().is_none() {
+        return Err(
+            Error::InvalidDefinition("The plist does not hold a dictionary".to_string()).into(),
+        );
+    }
+    Ok(value)
+}
+
+/// Determines if `RunAtLoad` is set within the `plist` of a service
+fn run_at_load(plist: &[u8]) -> io::Result<bool> {
+    let value = parse_plist(plist)?;
+    Ok(value
+        .as_dictionary()
+        .and_then(|dict| dict.get("RunAtLoad"))
+        .and_then(Value::as_boolean)
+        .unwrap_or(false))
+}
+
+/// Paths of the files that stdout and stderr are redirected to within the `plist` of a service
+fn log_paths(plist: &[u8]) -> io::Result<Vec<PathBuf>> {
+    let value = parse_plist(plist)?;
+    let dict = value.as_dictionary();
+    Ok(["StandardOutPath", "StandardErrorPath"]
+        .into_iter()
+        .filter_map(|key| {
+            dict.and_then(|dict| dict.get(key))
+                .and_then(Value::as_string)
+        })
+        .map(PathBuf::from)
+        .collect())
+}
+
+/// Produces the `plist` of a service with `RunAtLoad` set to `run_at_load`, keeping the label
+/// recorded during the install
+fn set_run_at_load(plist: &[u8], run_at_load: bool) -> io::Result<Vec<u8>> {
+    let mut value = parse_plist(plist)?;
+    if let Some(dict) = value.as_dictionary_mut() {
+        dict.insert("RunAtLoad".to_string(), Value::Boolean(run_at_load));
+    }
+
+    let mut buffer = Vec::new();
+    value
+        .to_writer_xml(&mut buffer)
+        .map_err(|x| io::Error::new(io::ErrorKind::Other, x))?;
+    let updated = String::from_utf8_lossy(&buffer).into_owned();
+
+    let label = utils::marked_label(&String::from_utf8_lossy(plist))
+        .and_then(|label| label.parse::<ServiceLabel>().ok());
+    Ok(match label {
+        Some(label) => utils::mark_label(updated, &label, utils::MarkerStyle::Xml),
+        None => updated,
+    }
+    .into_bytes())
+}
+
+/// Names and paths of the services whose plists within `dir_path` are named `file_names`
+fn plist_definitions(dir_path: &Path, file_names: Vec<String>) -> Vec<(String, PathBuf)> {
+    file_names
+        .iter()
+        .filter_map(|file_name| {
+            let name = file_name.strip_suffix(".plist")?;
+            Some((name.to_string(), dir_path.join(file_name)))
+        })
+        .collect()
+}
+
+/// Parses the top-level properties printed by `launchctl print` into a detailed status
+fn parse_print(stdout: &str) -> ServiceStatusDetail {
+    // Top-level properties are indented by a single tab, nested ones by more
+    let properties: HashMap<&str, &str> = stdout
+        .lines()
+        .filter(|line| line.starts_with('\t') && !line.starts_with("\t\t"))
+        .filter_map(|line| line.split_once(" = "))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let property = |name: &str| properties.get(name).copied().unwrap_or_default();
+
+    let exit_code = property("last exit code").parse::<i32>().ok();
+    let state = match property("state") {
+        "running" => ServiceState::Running,
+        "spawn scheduled" => ServiceState::Starting,
+        "not running" if exit_code.unwrap_or_default() != 0 => ServiceState::Failed,
+        "not running" | "exited" => ServiceState::Stopped,
+        _ => ServiceState::Unknown,
+    };
+
+    ServiceStatusDetail {
+        state,
+        pid: property("pid").parse().ok(),
+        exit_code,
+        active_since: None,
+        // `runs` counts every launch of the service, including the first one
+        restart_count: property("runs")
+            .parse::<u32>()
+            .ok()
+            .map(|runs| runs.saturating_sub(1)),
+        native_state: properties.get("state").map(|state| state.to_string()),
+    }
+}
+
+fn launchctl(runner: &dyn CommandRunner, cmd: &str, label: &str) -> io::Result<CommandOutput> {
+    launchctl_with_args(runner, cmd, [label])
+}
+
+fn launchctl_with_args<'a>(
+    runner: &dyn CommandRunner,
+    cmd: &str,
+    args: impl IntoIterator<Item = &'a str>,
+) -> io::Result<CommandOutput> {
+    utils::output(runner, &launchctl_command(cmd, args))
+}
+
+fn launchctl_command<'a>(cmd: &str, args: impl IntoIterator<Item = &'a str>) -> ServiceCommand {
+    ServiceCommand::new(LAUNCHCTL).arg(cmd).args(args)
+}
+
+/// Looks up the id of the current user, which is needed to target the `gui/{uid}` domain
+fn current_uid(runner: &dyn CommandRunner) -> io::Result<String> {
+    let output = wrap_output(utils::output(runner, &current_uid_command())?)?;
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn current_uid_command() -> ServiceCommand {
+    ServiceCommand::new("id").arg("-u")
+}
+
+/// Produces the service target used by subcommands like `kickstart`, in the form of
+/// `system/{label}`, or `gui/{uid}/{label}` when given the `uid` of the current user
+fn service_target(qualified_name: &str, uid: Option<&str>) -> String {
+    match uid {
+        Some(uid) => format!("gui/{}/{}", uid, qualified_name),
+        None => format!("system/{}", qualified_name),
+    }
 }
 
 #[inline]
@@ -268,6 +685,48 @@ fn user_agent_dir_path() -> io::Result<PathBuf> {
         .join("LaunchAgents"))
 }
 
+/// Path of the file that `launchd` redirects a stream named `stream` to for `output`, which is
+/// left out when the stream is inherited
+///
+/// `launchd` always appends to the file and has no way to send a stream to syslog.
+fn output_path(output: &ServiceOutput, stream: &str) -> io::Result<Option<String>> {
+    match output {
+        ServiceOutput::Inherit => Ok(None),
+        ServiceOutput::Null => Ok(Some("/dev/null".to_string())),
+        ServiceOutput::File(path) | ServiceOutput::Append(path) => Ok(Some(
+            utils::require_utf8(path.as_os_str(), "The log file")?.to_string(),
+        )),
+        ServiceOutput::Syslog { .. } => Err(Error::Unsupported(format!(
+            "launchd is unable to send the {stream} of a service to syslog"
+        ))
+        .into()),
+    }
+}
+
+/// Produces the dictionary of `SoftResourceLimits` and `HardResourceLimits` holding `limits`
+///
+/// `launchd` only limits CPU time in seconds rather than as a share of a CPU, so a CPU quota is
+/// unsupported.
+fn resource_limits(limits: &ResourceLimits) -> io::Result<Dictionary> {
+    if limits.cpu_quota.is_some() {
+        return Err(unsupported_limit("launchd", "the CPU quota of a service"));
+    }
+
+    let mut dict = Dictionary::new();
+    for (key, limit) in [
+        ("ResidentSetSize", limits.memory_max),
+        ("NumberOfFiles", limits.nofile),
+        ("NumberOfProcesses", limits.nproc),
+        ("Core", limits.core_size),
+    ] {
+        if let Some(limit) = limit {
+            dict.insert(key.to_string(), Value::Integer(limit.into()));
+        }
+    }
+    Ok(dict)
+}
+
+#[allow(clippy::too_many_arguments)]
 fn make_plist<'a>(
     config: &LaunchdInstallConfig,
     label: &str,
@@ -277,14 +736,22 @@ fn make_plist<'a>(
     environment: Option<Vec<(String, String)>>,
     autostart: bool,
     disable_restart_on_failure: bool,
-) -> String {
+    stdout_path: Option<String>,
+    stderr_path: Option<String>,
+    resource_limits: Dictionary,
+) -> io::Result<String> {
     let mut dict = Dictionary::new();
 
     dict.insert("Label".to_string(), Value::String(label.to_string()));
 
-    let program_arguments: Vec<Value> = args
-        .map(|arg| Value::String(arg.to_string_lossy().into_owned()))
-        .collect();
+    // Property lists only hold text, so arguments that are not valid UTF-8 cannot be passed
+    let program_arguments = args
+        .map(|arg| {
+            Ok(Value::String(
+                utils::require_utf8(arg, "The argument")?.to_string(),
+            ))
+        })
+        .collect::<io::Result<Vec<Value>>>()?;
     dict.insert(
         "ProgramArguments".to_string(),
         Value::Array(program_arguments),
@@ -301,7 +768,9 @@ fn make_plist<'a>(
     if let Some(working_dir) = working_directory {
         dict.insert(
             "WorkingDirectory".to_string(),
-            Value::String(working_dir.to_string_lossy().to_string()),
+            Value::String(
+                utils::require_utf8(working_dir.as_os_str(), "The working directory")?.to_string(),
+            ),
         );
     }
 
@@ -316,6 +785,26 @@ fn make_plist<'a>(
         );
     }
 
+    if let Some(path) = stdout_path {
+        dict.insert("StandardOutPath".to_string(), Value::String(path));
+    }
+
+    if let Some(path) = stderr_path {
+        dict.insert("StandardErrorPath".to_string(), Value::String(path));
+    }
+
+    // The limits are also set as the hard limits so the service cannot raise them
+    if !resource_limits.is_empty() {
+        dict.insert(
+            "SoftResourceLimits".to_string(),
+            Value::Dictionary(resource_limits.clone()),
+        );
+        dict.insert(
+            "HardResourceLimits".to_string(),
+            Value::Dictionary(resource_limits),
+        );
+    }
+
     if autostart {
         dict.insert("RunAtLoad".to_string(), Value::Boolean(true));
     } else {
@@ -326,5 +815,184 @@ fn make_plist<'a>(
 
     let mut buffer = Vec::new();
     plist.to_writer_xml(&mut buffer).unwrap();
-    String::from_utf8(buffer).unwrap()
+    Ok(String::from_utf8(buffer).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_print() {
+        let stdout = "system/com.example.echo = {\n\
+            \tactive count = 1\n\
+            \tpath = /Library/LaunchDaemons/com.example.echo.plist\n\
+            \tstate = running\n\
+            \n\
+            \tprogram = /usr/local/bin/echo\n\
+            \targuments = {\n\
+            \t\tstate = ignored\n\
+            \t}\n\
+            \n\
+            \truns = 3\n\
+            \tpid = 4321\n\
+            \tlast exit code = 1\n\
+            }\n";
+
+        let detail = parse_print(stdout);
+        assert_eq!(detail.state, ServiceState::Running);
+        assert_eq!(detail.pid, Some(4321));
+        assert_eq!(detail.exit_code, Some(1));
+        assert_eq!(detail.restart_count, Some(2));
+        assert_eq!(detail.native_state.as_deref(), Some("running"));
+    }
+
+    #[test]
+    fn test_output_paths_and_limits_are_written() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = LaunchdServiceManager::system().with_config(LaunchdConfig {
+            root: Some(root.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        let mut ctx =
+            ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "/usr/local/bin/echo");
+        ctx.stdout = ServiceOutput::Append(PathBuf::from("/var/log/echo.log"));
+        ctx.stderr = ServiceOutput::Null;
+        manager.install(ctx.clone()).unwrap();
+
+        let mut limited = ctx.clone();
+        limited.limits = ResourceLimits::new().with_nofile(4096).with_core_size(0);
+        let rendered = manager.render(&limited).unwrap();
+        let value = parse_plist(&rendered.files[0].contents).unwrap();
+        let dict = value.as_dictionary().unwrap();
+        for key in ["SoftResourceLimits", "HardResourceLimits"] {
+            let limits = dict.get(key).and_then(Value::as_dictionary).unwrap();
+            assert_eq!(
+                limits
+                    .get("NumberOfFiles")
+                    .and_then(Value::as_unsigned_integer),
+                Some(4096)
+            );
+            assert_eq!(
+                limits.get("Core").and_then(Value::as_unsigned_integer),
+                Some(0)
+            );
+        }
+
+        limited.limits = ResourceLimits::new().with_cpu_quota(50);
+        let err = manager.render(&limited).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        let plist = std::fs::read(manager.get_plist_path("com.example.echo".into()).unwrap());
+        assert_eq!(
+            log_paths(&plist.unwrap()).unwrap(),
+            [
+                PathBuf::from("/var/log/echo.log"),
+                PathBuf::from("/dev/null")
+            ]
+        );
+
+        std::fs::create_dir_all(root.path().join("var/log")).unwrap();
+        std::fs::write(root.path().join("var/log/echo.log"), "hello\nworld\n").unwrap();
+        let lines: Vec<String> = manager
+            .logs(ctx.label.clone(), LogQuery::new().with_lines(1))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, ["world"]);
+
+        ctx.stderr = ServiceOutput::Syslog { identifier: None };
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_arguments_must_be_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = LaunchdServiceManager::system().with_config(LaunchdConfig {
+            root: Some(root.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        let mut ctx =
+            ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "/usr/local/bin/echo");
+        ctx.args = vec![std::ffi::OsString::from_vec(b"caf\xe9".to_vec())];
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_dependencies_are_unsupported() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = LaunchdServiceManager::system().with_config(LaunchdConfig {
+            root: Some(root.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        let ctx = ServiceInstallCtx::builder(
+            "com.example.consumer".parse().unwrap(),
+            "/usr/local/bin/consumer",
+        )
+        .dependency(crate::ServiceDependency::after("com.example.broker"))
+        .build();
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "launchd is unable to express after dependencies"
+        );
+    }
+
+    #[test]
+    fn test_set_run_at_load_keeps_label() {
+        let label: ServiceLabel = "com.example.echo".parse().unwrap();
+        let plist = utils::mark_label(
+            make_plist(
+                &LaunchdInstallConfig::default(),
+                "com.example.echo",
+                [OsStr::new("/usr/local/bin/echo")].into_iter(),
+                None,
+                None,
+                None,
+                true,
+                false,
+                None,
+                None,
+                Dictionary::new(),
+            )
+            .unwrap(),
+            &label,
+            utils::MarkerStyle::Xml,
+        );
+        assert!(run_at_load(plist.as_bytes()).unwrap());
+
+        let disabled = set_run_at_load(plist.as_bytes(), false).unwrap();
+        assert!(!run_at_load(&disabled).unwrap());
+        assert_eq!(
+            utils::marked_label(&String::from_utf8(disabled.clone()).unwrap()),
+            Some("com.example.echo")
+        );
+
+        let enabled = set_run_at_load(&disabled, true).unwrap();
+        assert!(run_at_load(&enabled).unwrap());
+    }
+
+    #[test]
+    fn test_parse_print_never_exited() {
+        let stdout = "gui/501/com.example.echo = {\n\
+            \tstate = not running\n\
+            \truns = 0\n\
+            \tlast exit code = (never exited)\n\
+            }\n";
+
+        let detail = parse_print(stdout);
+        assert_eq!(detail.state, ServiceState::Stopped);
+        assert_eq!(detail.pid, None);
+        assert_eq!(detail.exit_code, None);
+        assert_eq!(detail.restart_count, Some(0));
+    }
 }
@@ -0,0 +1,268 @@
+use super::{
+    check_print, current_uid_command, launchctl_command, parse_print, plist_definitions,
+    run_at_load, service_target, set_run_at_load, status_from_print, LaunchdServiceManager,
+    PrintAttempt, PLIST_FILE_PERMISSIONS,
+};
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::utils::{self, nonblocking, wrap_output};
+use crate::{
+    CommandRunner, DiffState, Error, InstallOutcome, InstalledService, ServiceDiff,
+    ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx, ServiceLabel, ServiceLevel,
+    ServiceListCtx, ServiceRestartCtx, ServiceRollbackCtx, ServiceStartCtx, ServiceState,
+    ServiceStatus, ServiceStatusCtx, ServiceStatusDetail, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::io;
+
+impl LaunchdServiceManager {
+    /// Produces the service target of the service named `name`, looking up the id of the
+    /// current user for user-level services
+    async fn service_target_async(&self, name: &str) -> io::Result<String> {
+        let uid = if self.user {
+            let output = nonblocking::output(&self.runner, &current_uid_command()).await?;
+            let output = wrap_output(output)?;
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        } else {
+            None
+        };
+        Ok(service_target(name, uid.as_deref()))
+    }
+
+    /// Updates `RunAtLoad` like its blocking counterpart
+    async fn set_enabled_async(&self, label: &ServiceLabel, enabled: bool) -> io::Result<()> {
+        let name = self.naming.native_name(label);
+        let plist_path = self.get_plist_path(name.clone())?;
+        let plist = tokio::fs::read(&plist_path)
+            .await
+            .map_err(|x| utils::not_installed(x, &name))?;
+        let plist = set_run_at_load(&plist, enabled)?;
+        nonblocking::write_file(&plist_path, &plist, PLIST_FILE_PERMISSIONS).await?;
+
+        if enabled && self.config.root.is_none() {
+            let target = self.service_target_async(&name).await?;
+            let command = launchctl_command("enable", [target.as_str()]);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl AsyncServiceManager for LaunchdServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        Box::pin(async move { crate::ServiceManager::available(self) })
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, &ctx)?;
+
+            // Unload old service first if it exists
+            if self.config.root.is_none() {
+                let mut exists = false;
+                for file in &rendered.files {
+                    exists |= nonblocking::exists(&file.path).await;
+                }
+
+                if exists {
+                    let remove =
+                        launchctl_command("remove", [self.naming.native_name(&ctx.label).as_str()]);
+                    let _ = wrap_output(nonblocking::output(&self.runner, &remove).await?);
+                }
+            }
+
+            nonblocking::install_rendered(&self.runner, &rendered).await
+        })
+    }
+
+    fn diff<'a>(&'a self, ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, ctx)?;
+            nonblocking::diff_rendered(&rendered.files).await
+        })
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async move {
+            if self.config.root.is_some() {
+                return nonblocking::install_or_update_rendered(self, &self.runner, ctx, &[]).await;
+            }
+
+            let rendered = crate::ServiceManager::render(self, &ctx)?;
+            match nonblocking::diff_rendered(&rendered.files).await?.state {
+                DiffState::Missing => {
+                    self.install(ctx).await?;
+                    Ok(InstallOutcome::Installed)
+                }
+                DiffState::Identical => Ok(InstallOutcome::Unchanged),
+                DiffState::Differs => {
+                    let label = ctx.label.clone();
+                    let status = self.status(ServiceStatusCtx::new(label.clone())).await?;
+                    let running = status == ServiceStatus::Running;
+                    self.install(ctx).await?;
+                    let status = self.status(ServiceStatusCtx::new(label.clone())).await?;
+                    if running && status != ServiceStatus::Running {
+                        self.start(ServiceStartCtx::new(label)).await?;
+                    }
+                    Ok(InstallOutcome::Updated { restarted: running })
+                }
+            }
+        })
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let plist_path = self.get_plist_path(self.naming.native_name(&ctx.label))?;
+            // Service might already be removed (if it has "KeepAlive")
+            if self.config.root.is_none() {
+                let remove =
+                    launchctl_command("remove", [self.naming.native_name(&ctx.label).as_str()]);
+                let _ = wrap_output(nonblocking::output(&self.runner, &remove).await?);
+            }
+            let _ = nonblocking::remove_definition(&plist_path).await;
+            Ok(())
+        })
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+            let start = launchctl_command("start", [self.naming.native_name(&ctx.label).as_str()]);
+            wrap_output(nonblocking::output(&self.runner, &start).await?)?;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+            let stop = launchctl_command("stop", [self.naming.native_name(&ctx.label).as_str()]);
+            wrap_output(nonblocking::output(&self.runner, &stop).await?)?;
+            Ok(())
+        })
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+            let target = self
+                .service_target_async(&self.naming.native_name(&ctx.label))
+                .await?;
+            let kickstart = launchctl_command("kickstart", ["-k", target.as_str()]);
+            wrap_output(nonblocking::output(&self.runner, &kickstart).await?)?;
+            Ok(())
+        })
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move { self.set_enabled_async(&ctx.label, true).await })
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move { self.set_enabled_async(&ctx.label, false).await })
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            let name = self.naming.native_name(&ctx.label);
+            let plist_path = self.get_plist_path(name.clone())?;
+            let plist = tokio::fs::read(plist_path)
+                .await
+                .map_err(|x| utils::not_installed(x, &name))?;
+            run_at_load(&plist)
+        })
+    }
+
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let name = self.naming.native_name(&ctx.label);
+            let plist_path = self.get_plist_path(name.clone())?;
+            if !nonblocking::exists(&plist_path).await {
+                return Err(Error::NotInstalled(name).into());
+            }
+
+            nonblocking::restore_backup(&plist_path, &name, PLIST_FILE_PERMISSIONS).await?;
+            if self.config.root.is_none() {
+                let remove = launchctl_command("remove", [name.as_str()]);
+                let _ = wrap_output(nonblocking::output(&self.runner, &remove).await?);
+                let load = launchctl_command("load", [plist_path.to_string_lossy().as_ref()]);
+                wrap_output(nonblocking::output(&self.runner, &load).await?)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn level(&self) -> ServiceLevel {
+        crate::ServiceManager::level(self)
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        crate::ServiceManager::set_level(self, level)
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        Box::pin(async move {
+            if self.config.root.is_some() {
+                let plist_path = self.get_plist_path(self.naming.native_name(&ctx.label))?;
+                return Ok(nonblocking::offline_status(&plist_path).await);
+            }
+
+            match print_service(&self.runner, &self.naming.native_name(&ctx.label)).await? {
+                Some(out) => Ok(status_from_print(&out)),
+                None => Ok(ServiceStatus::NotInstalled),
+            }
+        })
+    }
+
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        Box::pin(async move {
+            if self.config.root.is_some() {
+                return Ok(self.status(ctx).await?.into());
+            }
+
+            match print_service(&self.runner, &self.naming.native_name(&ctx.label)).await? {
+                Some(out) => Ok(parse_print(&out)),
+                None => Ok(ServiceStatusDetail::new(ServiceState::NotInstalled)),
+            }
+        })
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async move {
+            let dir_path = self.dir_path()?;
+            let file_names = nonblocking::list_dir_names(&dir_path, false).await?;
+
+            let plists = plist_definitions(&dir_path, file_names);
+
+            let mut services = Vec::new();
+            for label in nonblocking::definition_labels(plists, &self.naming, &ctx).await {
+                let status = self
+                    .status(ServiceStatusCtx {
+                        label: label.clone(),
+                    })
+                    .await?;
+                services.push(InstalledService { label, status });
+            }
+
+            Ok(services)
+        })
+    }
+}
+
+/// Prints the information `launchd` has about a service, returning `None` if it is not installed
+async fn print_service(
+    runner: &dyn CommandRunner,
+    qualified_name: &str,
+) -> io::Result<Option<String>> {
+    let mut service_name = qualified_name.to_string();
+    for i in 0..2 {
+        let print = launchctl_command("print", [service_name.as_str()]);
+        let output = nonblocking::output(runner, &print).await?;
+        match check_print(output, &service_name, i == 0)? {
+            PrintAttempt::Printed(out) => return Ok(Some(out)),
+            PrintAttempt::Retry(name) => service_name = name,
+            PrintAttempt::NotInstalled => return Ok(None),
+        }
+    }
+
+    unreachable!("second attempt always returns")
+}
@@ -9,24 +9,43 @@ use std::{
     fmt, io,
     path::PathBuf,
     str::FromStr,
+    time::SystemTime,
 };
 
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+mod dependency;
+mod diff;
+mod error;
 mod kind;
 mod launchd;
+mod limits;
+mod logs;
+mod naming;
 mod openrc;
 mod rcd;
+mod runner;
 mod sc;
 mod systemd;
+mod transaction;
 mod typed;
 mod utils;
 mod winsw;
 
+pub use dependency::*;
+pub use diff::*;
+pub use error::*;
 pub use kind::*;
 pub use launchd::*;
+pub use limits::*;
+pub use logs::*;
+pub use naming::*;
 pub use openrc::*;
 pub use rcd::*;
+pub use runner::*;
 pub use sc::*;
 pub use systemd::*;
+pub use transaction::*;
 pub use typed::*;
 pub use winsw::*;
 
@@ -39,6 +58,72 @@ pub trait ServiceManager {
     /// Installs a new service using the manager
     fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()>;
 
+    /// Produces the files and commands that [`ServiceManager::install`] would use to install a
+    /// service, without touching the filesystem or running anything
+    ///
+    /// By default, this reports that rendering is unsupported.
+    fn render(&self, _ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        Err(Error::Unsupported(
+            "Rendering services is not supported by this service manager".to_string(),
+        )
+        .into())
+    }
+
+    /// Compares the definition that [`ServiceManager::install`] would write with the one that is
+    /// installed, reporting whether it is missing, identical or differs along with a unified diff
+    ///
+    /// By default, this compares the files produced by [`ServiceManager::render`] with the ones
+    /// on disk, reporting that diffing is unsupported when there are no files to compare.
+    fn diff(&self, ctx: &ServiceInstallCtx) -> io::Result<ServiceDiff> {
+        let rendered = self.render(ctx)?;
+        if rendered.files.is_empty() {
+            return Err(Error::Unsupported(
+                "Diffing services is not supported by this service manager".to_string(),
+            )
+            .into());
+        }
+
+        utils::diff_rendered(&rendered.files)
+    }
+
+    /// Installs a service, or updates it if it is already installed with a different definition
+    ///
+    /// The rendered definition is compared with the one that is installed, doing nothing if they
+    /// are the same. Otherwise the definition is rewritten, the manager is reloaded, and the
+    /// service is restarted only if it was running. Whether the service starts automatically is
+    /// left as is, see [`ServiceManager::enable`] and [`ServiceManager::disable`].
+    ///
+    /// By default, a changed service is uninstalled and then installed again, which relies on
+    /// [`ServiceManager::render`] producing its definition files.
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        let rendered = self.render(&ctx)?;
+        if rendered.files.is_empty() {
+            return Err(Error::Unsupported(
+                "Updating services is not supported by this service manager".to_string(),
+            )
+            .into());
+        }
+
+        match utils::diff_rendered(&rendered.files)?.state {
+            DiffState::Missing => {
+                self.install(ctx)?;
+                Ok(InstallOutcome::Installed)
+            }
+            DiffState::Identical => Ok(InstallOutcome::Unchanged),
+            DiffState::Differs => {
+                let label = ctx.label.clone();
+                let running =
+                    self.status(ServiceStatusCtx::new(label.clone()))? == ServiceStatus::Running;
+                self.uninstall(ServiceUninstallCtx::new(label.clone()))?;
+                self.install(ctx)?;
+                if running {
+                    self.start(ServiceStartCtx::new(label))?;
+                }
+                Ok(InstallOutcome::Updated { restarted: running })
+            }
+        }
+    }
+
     /// Uninstalls an existing service using the manager
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()>;
 
@@ -48,6 +133,78 @@ pub trait ServiceManager {
     /// Stops a running service using the manager
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()>;
 
+    /// Restarts a service using the manager
+    ///
+    /// By default, this stops the service and then starts it again. Managers that have a native
+    /// restart operation override this to avoid racing between the two steps.
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        self.stop(ServiceStopCtx {
+            label: ctx.label.clone(),
+        })?;
+        self.start(ServiceStartCtx { label: ctx.label })
+    }
+
+    /// Configures an installed service to start automatically, without reinstalling it
+    ///
+    /// By default, this reports that enabling services is unsupported.
+    fn enable(&self, _ctx: ServiceEnableCtx) -> io::Result<()> {
+        Err(Error::Unsupported(
+            "Enabling services is not supported by this service manager".to_string(),
+        )
+        .into())
+    }
+
+    /// Configures an installed service to no longer start automatically, without reinstalling
+    /// it. The service can still be started manually.
+    ///
+    /// By default, this reports that disabling services is unsupported.
+    fn disable(&self, _ctx: ServiceDisableCtx) -> io::Result<()> {
+        Err(Error::Unsupported(
+            "Disabling services is not supported by this service manager".to_string(),
+        )
+        .into())
+    }
+
+    /// Returns true if an installed service is configured to start automatically
+    ///
+    /// By default, this reports that querying whether services are enabled is unsupported.
+    fn is_enabled(&self, _ctx: ServiceStatusCtx) -> io::Result<bool> {
+        Err(Error::Unsupported(
+            "Querying whether services are enabled is not supported by this service manager"
+                .to_string(),
+        )
+        .into())
+    }
+
+    /// Restores the definition of an installed service that was in place before it was last
+    /// overwritten, such as by [`ServiceManager::install_or_update`], and reloads the manager
+    ///
+    /// Definition files are written atomically, and their previous contents are kept as a hidden
+    /// backup alongside them whenever they change, which this puts back. The backup is kept, so
+    /// rolling back twice is the same as rolling back once. The service is not restarted.
+    ///
+    /// By default, this reports that rolling back is unsupported.
+    fn rollback(&self, _ctx: ServiceRollbackCtx) -> io::Result<()> {
+        Err(Error::Unsupported(
+            "Rolling back services is not supported by this service manager".to_string(),
+        )
+        .into())
+    }
+
+    /// Returns the output of the service with `label` selected by `query`, one line at a time
+    ///
+    /// Lines are read from wherever the manager keeps the output of services, such as the journal
+    /// of `systemd` or the log files named in the definition of the service. When following, the
+    /// returned iterator blocks until new lines are written.
+    ///
+    /// By default, this reports that reading logs is unsupported.
+    fn logs(&self, _label: ServiceLabel, _query: LogQuery) -> io::Result<LogLines> {
+        Err(
+            Error::Unsupported("Reading logs is not supported by this service manager".to_string())
+                .into(),
+        )
+    }
+
     /// Returns the current target level for the manager
     fn level(&self) -> ServiceLevel;
 
@@ -56,6 +213,23 @@ pub trait ServiceManager {
 
     /// Return the service status info
     fn status(&self, ctx: ServiceStatusCtx) -> io::Result<ServiceStatus>;
+
+    /// Return detailed service status info, such as the process id and last exit code
+    ///
+    /// By default, this only reports the state derived from [`ServiceManager::status`].
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> io::Result<ServiceStatusDetail> {
+        Ok(ServiceStatusDetail::from(self.status(ctx)?))
+    }
+
+    /// Lists the services installed with the manager, along with their status
+    ///
+    /// By default, this reports that listing is unsupported.
+    fn list(&self, _ctx: ServiceListCtx) -> io::Result<Vec<InstalledService>> {
+        Err(Error::Unsupported(
+            "Listing services is not supported by this service manager".to_string(),
+        )
+        .into())
+    }
 }
 
 impl dyn ServiceManager {
@@ -106,6 +280,8 @@ where
 
 /// Represents whether a service is system-wide or user-level
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum ServiceLevel {
     System,
     User,
@@ -113,13 +289,131 @@ pub enum ServiceLevel {
 
 /// Represents the status of a service
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum ServiceStatus {
     NotInstalled,
     Running,
     Stopped(Option<String>), // Provide a reason if possible
 }
 
+/// Outcome of [`ServiceManager::install_or_update`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum InstallOutcome {
+    /// Service was not installed, and has been installed
+    Installed,
+
+    /// Service was installed with the same definition, so nothing was done
+    Unchanged,
+
+    /// Service was installed with a different definition, which has been replaced
+    Updated {
+        /// Whether the service was running, and has been restarted to use the new definition
+        restarted: bool,
+    },
+}
+
+/// Represents the state of a service in more detail than [`ServiceStatus`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ServiceState {
+    /// Service is not installed
+    NotInstalled,
+
+    /// Service is in the process of starting
+    Starting,
+
+    /// Service is running
+    Running,
+
+    /// Service is in the process of stopping
+    Stopping,
+
+    /// Service is not running
+    Stopped,
+
+    /// Service is not running because it exited with a failure
+    Failed,
+
+    /// Service is paused
+    Paused,
+
+    /// Service manager reported a state that has no equivalent, see
+    /// [`ServiceStatusDetail::native_state`]
+    Unknown,
+}
+
+/// Represents the detailed status of a service
+///
+/// Fields that the service manager does not report are `None`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ServiceStatusDetail {
+    /// Current state of the service
+    pub state: ServiceState,
+
+    /// Id of the main process of the service while it is running
+    pub pid: Option<u32>,
+
+    /// Exit code of the last run of the service
+    ///
+    /// Processes terminated by a signal are reported as `128 + signal`, like a shell would.
+    pub exit_code: Option<i32>,
+
+    /// Time at which the service last entered its running state
+    pub active_since: Option<SystemTime>,
+
+    /// Number of times the service manager has restarted the service
+    pub restart_count: Option<u32>,
+
+    /// State of the service as reported by the service manager
+    ///
+    /// E.g. `active (running)`
+    pub native_state: Option<String>,
+}
+
+impl ServiceStatusDetail {
+    /// Creates a new status detail with the given state and no other information
+    pub fn new(state: ServiceState) -> Self {
+        Self {
+            state,
+            pid: None,
+            exit_code: None,
+            active_since: None,
+            restart_count: None,
+            native_state: None,
+        }
+    }
+
+    /// Produces the less detailed [`ServiceStatus`] matching this status
+    pub fn to_status(&self) -> ServiceStatus {
+        match self.state {
+            ServiceState::NotInstalled => ServiceStatus::NotInstalled,
+            ServiceState::Running => ServiceStatus::Running,
+            _ => ServiceStatus::Stopped(self.native_state.clone()),
+        }
+    }
+}
+
+impl From<ServiceStatus> for ServiceStatusDetail {
+    fn from(status: ServiceStatus) -> Self {
+        match status {
+            ServiceStatus::NotInstalled => Self::new(ServiceState::NotInstalled),
+            ServiceStatus::Running => Self::new(ServiceState::Running),
+            ServiceStatus::Stopped(reason) => Self {
+                native_state: reason,
+                ..Self::new(ServiceState::Stopped)
+            },
+        }
+    }
+}
+
 /// Label describing the service (e.g. `org.example.my_application`
+///
+/// With the `serde` feature, it is (de)serialized as its fully-qualified name.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct ServiceLabel {
     /// Qualifier used for services tied to management systems like `launchd`
@@ -165,6 +459,30 @@ impl ServiceLabel {
         script_name.push_str(self.application.as_str());
         script_name
     }
+
+    /// Parses a script name in the form of `{organization}-{application}`
+    ///
+    /// The qualifier is not part of a script name, so it cannot be recovered. The name is split
+    /// on its first `-`, which means that the label will always produce the same script name
+    /// again, but may attribute part of the name to the wrong field.
+    pub fn from_script_name(name: &str) -> Self {
+        match name.split_once('-') {
+            Some((organization, application))
+                if !organization.is_empty() && !application.is_empty() =>
+            {
+                Self {
+                    qualifier: None,
+                    organization: Some(organization.to_string()),
+                    application: application.to_string(),
+                }
+            }
+            _ => Self {
+                qualifier: None,
+                organization: None,
+                application: name.to_string(),
+            },
+        }
+    }
 }
 
 impl fmt::Display for ServiceLabel {
@@ -178,8 +496,24 @@ impl FromStr for ServiceLabel {
     type Err = io::Error;
 
     /// Parses a fully-qualified name in the form of `{qualifier}.{organization}.{application}`
+    ///
+    /// Each segment of the name must be non-empty and only contain ASCII letters, digits, `-`
+    /// and `_`, otherwise this fails with [`Error::InvalidLabel`].
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let tokens = s.split('.').collect::<Vec<&str>>();
+        for token in &tokens {
+            let reason = if token.is_empty() {
+                "has an empty segment"
+            } else if !token
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                "may only contain ASCII letters, digits, '-', '_' and '.'"
+            } else {
+                continue;
+            };
+            return Err(Error::InvalidLabel(format!("Service label {s:?} {reason}")).into());
+        }
 
         let label = match tokens.len() {
             1 => Self {
@@ -208,8 +542,27 @@ impl FromStr for ServiceLabel {
     }
 }
 
+#[cfg(feature = "serde")]
+impl ::serde::Serialize for ServiceLabel {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_qualified_name())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> ::serde::Deserialize<'de> for ServiceLabel {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(::serde::de::Error::custom)
+    }
+}
+
 /// Context provided to the install function of [`ServiceManager`]
+///
+/// Use [`ServiceInstallCtx::builder`] to create one, as more fields may be added in the future.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
 pub struct ServiceInstallCtx {
     /// Label associated with the service
     ///
@@ -224,6 +577,7 @@ pub struct ServiceInstallCtx {
     /// Arguments to use for the program
     ///
     /// E.g. `--arg`, `value`, `--another-arg`
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::utils::os_strings"))]
     pub args: Vec<OsString>,
 
     /// Optional contents of the service file for a given ServiceManager
@@ -243,18 +597,77 @@ pub struct ServiceInstallCtx {
     pub environment: Option<Vec<(String, String)>>,
 
     /// Specify whether the service should automatically start on reboot
+    #[cfg_attr(feature = "serde", serde(default = "crate::utils::default_autostart"))]
     pub autostart: bool,
 
     /// Optionally disable a service from restarting when it exits with a failure
     ///
     /// This could overwrite the platform specific service manager config.
+    #[cfg_attr(feature = "serde", serde(default))]
     pub disable_restart_on_failure: bool,
 
     /// Specify whether the service requires network to be up and running in order to run
+    #[cfg_attr(feature = "serde", serde(default))]
     pub requires_network: bool,
+
+    /// Where the standard output of the service goes
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stdout: ServiceOutput,
+
+    /// Where the standard error of the service goes
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stderr: ServiceOutput,
+
+    /// Limits on the resources used by the service
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limits: ResourceLimits,
+
+    /// Other services that this one depends on or is ordered against
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub dependencies: Vec<ServiceDependency>,
 }
 
 impl ServiceInstallCtx {
+    /// Creates a new context to install the service with `label` running `program`, which
+    /// starts automatically, restarts on failure and does not wait on the network
+    pub fn new(label: ServiceLabel, program: impl Into<PathBuf>) -> Self {
+        Self {
+            label,
+            program: program.into(),
+            args: Vec::new(),
+            contents: None,
+            username: None,
+            working_directory: None,
+            environment: None,
+            autostart: true,
+            disable_restart_on_failure: false,
+            requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: ResourceLimits::default(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    /// Creates a builder for a context to install the service with `label` running `program`,
+    /// starting from the defaults of [`ServiceInstallCtx::new`]
+    ///
+    /// ```
+    /// use service_manager::ServiceInstallCtx;
+    ///
+    /// let ctx = ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/bin/echo")
+    ///     .arg("hello")
+    ///     .env("GREETING", "hi")
+    ///     .autostart(false)
+    ///     .build();
+    ///
+    /// assert_eq!(ctx.args, vec!["hello"]);
+    /// assert!(!ctx.autostart);
+    /// ```
+    pub fn builder(label: ServiceLabel, program: impl Into<PathBuf>) -> ServiceInstallCtxBuilder {
+        ServiceInstallCtxBuilder(Self::new(label, program))
+    }
+
     /// Iterator over the program and its arguments
     pub fn cmd_iter(&self) -> impl Iterator<Item = &OsStr> {
         std::iter::once(self.program.as_os_str()).chain(self.args_iter())
@@ -266,8 +679,207 @@ impl ServiceInstallCtx {
     }
 }
 
+/// Builder for a [`ServiceInstallCtx`], created with [`ServiceInstallCtx::builder`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceInstallCtxBuilder(ServiceInstallCtx);
+
+impl ServiceInstallCtxBuilder {
+    /// Appends an argument for the program
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.0.args.push(arg.into());
+        self
+    }
+
+    /// Appends several arguments for the program
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.0.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Adds an environment variable to be passed to the program
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.0
+            .environment
+            .get_or_insert_with(Vec::new)
+            .push((key.into(), value.into()));
+        self
+    }
+
+    /// Adds several environment variables to be passed to the program
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.0.environment.get_or_insert_with(Vec::new).extend(
+            vars.into_iter()
+                .map(|(key, value)| (key.into(), value.into())),
+        );
+        self
+    }
+
+    /// Sets the user the service will run as
+    pub fn username(mut self, username: impl Into<String>) -> Self {
+        self.0.username = Some(username.into());
+        self
+    }
+
+    /// Sets the working directory of the program
+    pub fn working_directory(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.0.working_directory = Some(dir.into());
+        self
+    }
+
+    /// Sets the contents of the service file to use instead of the default template
+    pub fn contents(mut self, contents: impl Into<String>) -> Self {
+        self.0.contents = Some(contents.into());
+        self
+    }
+
+    /// Sets whether the service should automatically start on reboot
+    pub fn autostart(mut self, autostart: bool) -> Self {
+        self.0.autostart = autostart;
+        self
+    }
+
+    /// Sets whether the service should restart when it exits with a failure
+    pub fn restart_on_failure(mut self, restart: bool) -> Self {
+        self.0.disable_restart_on_failure = !restart;
+        self
+    }
+
+    /// Sets whether the service requires network to be up and running in order to run
+    pub fn requires_network(mut self, requires_network: bool) -> Self {
+        self.0.requires_network = requires_network;
+        self
+    }
+
+    /// Sets where the standard output of the service goes
+    pub fn stdout(mut self, stdout: ServiceOutput) -> Self {
+        self.0.stdout = stdout;
+        self
+    }
+
+    /// Sets where the standard error of the service goes
+    pub fn stderr(mut self, stderr: ServiceOutput) -> Self {
+        self.0.stderr = stderr;
+        self
+    }
+
+    /// Sets the limits on the resources used by the service
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.0.limits = limits;
+        self
+    }
+
+    /// Adds a dependency on another service
+    pub fn dependency(mut self, dependency: ServiceDependency) -> Self {
+        self.0.dependencies.push(dependency);
+        self
+    }
+
+    /// Finishes building the context
+    pub fn build(self) -> ServiceInstallCtx {
+        self.0
+    }
+}
+
+/// Files and commands that [`ServiceManager::install`] uses to install a service, as returned by
+/// [`ServiceManager::render`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedService {
+    /// Files written by the install, in the order they are written
+    pub files: Vec<RenderedFile>,
+
+    /// Commands run by the install once the files are written, in the order they are run
+    pub commands: Vec<ServiceCommand>,
+}
+
+/// File written when installing a service
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedFile {
+    /// Path of the file, whose parent directories are created if missing
+    ///
+    /// E.g. `/etc/systemd/system/example-my_application.service`
+    pub path: PathBuf,
+
+    /// Contents of the file
+    pub contents: Vec<u8>,
+
+    /// Permissions assigned to the file on unix systems
+    ///
+    /// E.g. `0o644`
+    pub mode: u32,
+}
+
+/// Command run by a [`ServiceManager`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceCommand {
+    /// Program to run
+    ///
+    /// E.g. `systemctl`
+    pub program: OsString,
+
+    /// Arguments to provide to the program
+    pub args: Vec<OsString>,
+
+    /// Optional directory to run the program within, instead of the current directory
+    pub current_dir: Option<PathBuf>,
+}
+
+impl ServiceCommand {
+    /// Creates a new command to run `program` without any arguments
+    pub fn new(program: impl Into<OsString>) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            current_dir: None,
+        }
+    }
+
+    /// Adds an argument to the command
+    pub fn arg(mut self, arg: impl Into<OsString>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Adds several arguments to the command
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets the directory to run the command within
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.current_dir = Some(dir.into());
+        self
+    }
+}
+
+impl fmt::Display for ServiceCommand {
+    /// Produces the program followed by its arguments, separated by spaces
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program.to_string_lossy())?;
+        for arg in &self.args {
+            write!(f, " {}", arg.to_string_lossy())?;
+        }
+        Ok(())
+    }
+}
+
 /// Context provided to the uninstall function of [`ServiceManager`]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
 pub struct ServiceUninstallCtx {
     /// Label associated with the service
     ///
@@ -275,8 +887,17 @@ pub struct ServiceUninstallCtx {
     pub label: ServiceLabel,
 }
 
+impl ServiceUninstallCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
 /// Context provided to the start function of [`ServiceManager`]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
 pub struct ServiceStartCtx {
     /// Label associated with the service
     ///
@@ -284,8 +905,17 @@ pub struct ServiceStartCtx {
     pub label: ServiceLabel,
 }
 
+impl ServiceStartCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
 /// Context provided to the stop function of [`ServiceManager`]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
 pub struct ServiceStopCtx {
     /// Label associated with the service
     ///
@@ -293,8 +923,89 @@ pub struct ServiceStopCtx {
     pub label: ServiceLabel,
 }
 
-/// Context provided to the status function of [`ServiceManager`]
+impl ServiceStopCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
+/// Context provided to the restart function of [`ServiceManager`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
+pub struct ServiceRestartCtx {
+    /// Label associated with the service
+    ///
+    /// E.g. `rocks.distant.manager`
+    pub label: ServiceLabel,
+}
+
+impl ServiceRestartCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
+/// Context provided to the enable function of [`ServiceManager`]
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
+pub struct ServiceEnableCtx {
+    /// Label associated with the service
+    ///
+    /// E.g. `rocks.distant.manager`
+    pub label: ServiceLabel,
+}
+
+impl ServiceEnableCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
+/// Context provided to the disable function of [`ServiceManager`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
+pub struct ServiceDisableCtx {
+    /// Label associated with the service
+    ///
+    /// E.g. `rocks.distant.manager`
+    pub label: ServiceLabel,
+}
+
+impl ServiceDisableCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
+/// Context provided to the rollback function of [`ServiceManager`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
+pub struct ServiceRollbackCtx {
+    /// Label associated with the service
+    ///
+    /// E.g. `rocks.distant.manager`
+    pub label: ServiceLabel,
+}
+
+impl ServiceRollbackCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
+/// Context provided to the status and is_enabled functions of [`ServiceManager`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[non_exhaustive]
 pub struct ServiceStatusCtx {
     /// Label associated with the service
     ///
@@ -302,6 +1013,60 @@ pub struct ServiceStatusCtx {
     pub label: ServiceLabel,
 }
 
+impl ServiceStatusCtx {
+    /// Creates a new context targeting the service with `label`
+    pub fn new(label: ServiceLabel) -> Self {
+        Self { label }
+    }
+}
+
+/// Context provided to the list function of [`ServiceManager`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct ServiceListCtx {
+    /// Optional prefix that the qualified name of a service label must start with to be listed
+    ///
+    /// E.g. `rocks.distant`
+    pub prefix: Option<String>,
+}
+
+impl ServiceListCtx {
+    /// Creates a new context listing every installed service
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only list services whose qualified name starts with `prefix`
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    /// Returns true if the label passes the filters of this context
+    pub fn matches(&self, label: &ServiceLabel) -> bool {
+        match self.prefix.as_deref() {
+            Some(prefix) => label.to_qualified_name().starts_with(prefix),
+            None => true,
+        }
+    }
+}
+
+/// Service installed with a [`ServiceManager`], as returned by its list function
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct InstalledService {
+    /// Label associated with the service
+    ///
+    /// For managers that name services by script name, such as `systemd`, the label is
+    /// reconstructed from the script name using [`ServiceLabel::from_script_name`].
+    pub label: ServiceLabel,
+
+    /// Current status of the service
+    pub status: ServiceStatus,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -341,4 +1106,79 @@ mod tests {
         assert_eq!(label.to_qualified_name(), "app123");
         assert_eq!(label.to_script_name(), "app123");
     }
+
+    #[test]
+    fn test_service_label_parsing_rejects_invalid_labels() {
+        for s in [
+            "",
+            "com..app",
+            ".app",
+            "app.",
+            "com.example.my app",
+            "com.example/app",
+        ] {
+            let err = ServiceLabel::from_str(s).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{s:?}");
+            assert!(matches!(
+                Error::from_io_error(&err),
+                Some(Error::InvalidLabel(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_service_label_from_script_name() {
+        let label = ServiceLabel::from_script_name("example-my-app");
+
+        assert_eq!(label.qualifier, None);
+        assert_eq!(label.organization, Some("example".to_string()));
+        assert_eq!(label.application, "my-app".to_string());
+        assert_eq!(label.to_script_name(), "example-my-app");
+
+        let label = ServiceLabel::from_script_name("app123");
+        assert_eq!(label.organization, None);
+        assert_eq!(label.to_script_name(), "app123");
+    }
+
+    #[test]
+    fn test_service_list_ctx_matches_prefix() {
+        let label = ServiceLabel::from_str("com.example.app123").unwrap();
+
+        assert!(ServiceListCtx::default().matches(&label));
+        assert!(ServiceListCtx {
+            prefix: Some("com.example".to_string())
+        }
+        .matches(&label));
+        assert!(!ServiceListCtx {
+            prefix: Some("org.example".to_string())
+        }
+        .matches(&label));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_service_install_ctx_serde_round_trip() {
+        let ctx = ServiceInstallCtx::builder("com.example.app123".parse().unwrap(), "/usr/bin/app")
+            .args(["--port", "8080"])
+            .env("RUST_LOG", "debug")
+            .requires_network(true)
+            .build();
+
+        let json = serde_json::to_value(&ctx).unwrap();
+        assert_eq!(json["label"], "com.example.app123");
+        assert_eq!(json["args"], serde_json::json!(["--port", "8080"]));
+        assert_eq!(
+            serde_json::from_value::<ServiceInstallCtx>(json).unwrap(),
+            ctx
+        );
+
+        // Missing fields default to those of `ServiceInstallCtx::new`
+        let ctx: ServiceInstallCtx =
+            serde_json::from_str(r#"{ "label": "example.app123", "program": "/usr/bin/app" }"#)
+                .unwrap();
+        assert_eq!(
+            ctx,
+            ServiceInstallCtx::new("example.app123".parse().unwrap(), "/usr/bin/app")
+        );
+    }
 }
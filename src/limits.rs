@@ -0,0 +1,91 @@
+use super::Error;
+use std::io;
+
+/// Limits on the resources used by a service, set through the `limits` field of
+/// [`ServiceInstallCtx`]
+///
+/// Each limit is left to the default of the manager when `None`. Not every manager can express
+/// every limit, in which case installing the service fails with [`Error::Unsupported`] rather
+/// than running it without the limit.
+///
+/// ```
+/// use service_manager::ResourceLimits;
+///
+/// let limits = ResourceLimits::new()
+///     .with_memory_max(512 * 1024 * 1024)
+///     .with_nofile(4096);
+/// assert_eq!(limits.nofile, Some(4096));
+/// assert!(!limits.is_empty());
+/// ```
+///
+/// [`ServiceInstallCtx`]: crate::ServiceInstallCtx
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct ResourceLimits {
+    /// Maximum amount of memory in bytes
+    ///
+    /// This limits the memory of the whole service with `systemd`, and the address space or
+    /// resident set size of each process where limits are set through `ulimit` or `launchd`.
+    pub memory_max: Option<u64>,
+
+    /// Maximum number of open files of each process
+    pub nofile: Option<u64>,
+
+    /// Maximum number of processes of the user running the service
+    pub nproc: Option<u64>,
+
+    /// Maximum size of core dumps in bytes, where `0` disables them
+    pub core_size: Option<u64>,
+
+    /// Maximum share of CPU time in percent of a single CPU, where `200` allows using two CPUs
+    pub cpu_quota: Option<u32>,
+}
+
+impl ResourceLimits {
+    /// Creates limits that leave every resource to the default of the manager
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the memory of the service to `bytes`
+    pub fn with_memory_max(mut self, bytes: u64) -> Self {
+        self.memory_max = Some(bytes);
+        self
+    }
+
+    /// Limits the number of open files to `files`
+    pub fn with_nofile(mut self, files: u64) -> Self {
+        self.nofile = Some(files);
+        self
+    }
+
+    /// Limits the number of processes to `processes`
+    pub fn with_nproc(mut self, processes: u64) -> Self {
+        self.nproc = Some(processes);
+        self
+    }
+
+    /// Limits the size of core dumps to `bytes`
+    pub fn with_core_size(mut self, bytes: u64) -> Self {
+        self.core_size = Some(bytes);
+        self
+    }
+
+    /// Limits the CPU time of the service to `percent` of a single CPU
+    pub fn with_cpu_quota(mut self, percent: u32) -> Self {
+        self.cpu_quota = Some(percent);
+        self
+    }
+
+    /// Returns true if no limit is set
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Reports that `manager` is unable to express the resource limit described by `limit`
+pub(crate) fn unsupported_limit(manager: &str, limit: &str) -> io::Error {
+    Error::Unsupported(format!("{manager} is unable to limit {limit}")).into()
+}
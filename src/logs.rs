@@ -0,0 +1,319 @@
+use super::Error;
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Interval between checks for new lines when following log files
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Lines of output of a service, as returned by [`ServiceManager::logs`]
+///
+/// When following the output, the iterator blocks until new lines are written and only ends if
+/// reading fails. Dropping it stops following.
+///
+/// [`ServiceManager::logs`]: crate::ServiceManager::logs
+pub type LogLines = Box<dyn Iterator<Item = io::Result<String>> + Send>;
+
+/// Query selecting the output of a service returned by [`ServiceManager::logs`]
+///
+/// ```
+/// use service_manager::LogQuery;
+///
+/// let query = LogQuery::new().with_lines(100).with_follow(true);
+/// assert_eq!(query.lines, Some(100));
+/// assert!(query.follow);
+/// ```
+///
+/// [`ServiceManager::logs`]: crate::ServiceManager::logs
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+#[non_exhaustive]
+pub struct LogQuery {
+    /// Number of most recent lines to return, or every line when `None`
+    ///
+    /// For services writing their output to several files, such as separate files for stdout
+    /// and stderr, this applies to each file.
+    pub lines: Option<usize>,
+
+    /// Only return lines written at or after this time
+    ///
+    /// This needs timestamped logs, such as the journal of `systemd`, and is reported as
+    /// unsupported for services writing their output to plain files.
+    pub since: Option<SystemTime>,
+
+    /// Keep returning new lines as they are written, rather than stopping at the last one
+    pub follow: bool,
+}
+
+impl LogQuery {
+    /// Creates a new query returning every line written so far
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only return the most recent `lines` lines
+    pub fn with_lines(mut self, lines: usize) -> Self {
+        self.lines = Some(lines);
+        self
+    }
+
+    /// Only return lines written at or after `since`
+    pub fn with_since(mut self, since: SystemTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Keep returning new lines as they are written if `follow` is true
+    pub fn with_follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+}
+
+/// Where a service sends one of its output streams, set through the `stdout` and `stderr` fields
+/// of [`ServiceInstallCtx`]
+///
+/// Not every manager can express every target, in which case installing the service fails with
+/// [`Error::Unsupported`]. Managers that open the files themselves, such as `launchd` and
+/// OpenRC, always append to them.
+///
+/// [`ServiceInstallCtx`]: crate::ServiceInstallCtx
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ServiceOutput {
+    /// Leave the stream wherever the manager sends it by default, such as the journal of
+    /// `systemd` or nowhere for `launchd`
+    #[default]
+    Inherit,
+
+    /// Discard the stream
+    Null,
+
+    /// Write the stream to the file at the path, truncating it when the service starts
+    File(PathBuf),
+
+    /// Append the stream to the file at the path
+    Append(PathBuf),
+
+    /// Send the stream to the system log, tagged with the identifier or the name of the service
+    /// if there is none
+    ///
+    /// The identifier may only hold letters, digits and any of `_@+=:,./-`, as managers write it
+    /// unquoted into their definitions. Other identifiers fail the install with
+    /// [`Error::InvalidDefinition`].
+    Syslog {
+        /// Identifier tagging the lines of the stream
+        identifier: Option<String>,
+    },
+}
+
+impl ServiceOutput {
+    /// Creates a target sending the stream to the system log tagged with `identifier`
+    pub fn syslog(identifier: impl Into<String>) -> Self {
+        Self::Syslog {
+            identifier: Some(identifier.into()),
+        }
+    }
+
+    /// Path of the file the stream is written to, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) | Self::Append(path) => Some(path),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that the syslog `identifier` can be written as is into the definitions of `manager`,
+/// which paste it unquoted into shell command lines and unit files
+pub(crate) fn validate_syslog_identifier(manager: &str, identifier: &str) -> io::Result<()> {
+    if !identifier.is_empty()
+        && identifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@+=:,./-".contains(c))
+    {
+        return Ok(());
+    }
+    Err(Error::InvalidDefinition(format!(
+        "{manager} is unable to tag the output with {identifier:?}, as syslog identifiers may \
+         only hold letters, digits and any of `_@+=:,./-`"
+    ))
+    .into())
+}
+
+/// Reads the lines of the log files at `paths` selected by `query`, in the order of the paths
+///
+/// Missing files are treated as empty, as services often create them on their first write. When
+/// following, files are polled for appended lines and read from the start again if they shrink,
+/// such as when they are rotated.
+pub(crate) fn tail_files(paths: Vec<PathBuf>, query: &LogQuery) -> io::Result<LogLines> {
+    if query.since.is_some() {
+        return Err(Error::Unsupported(
+            "Filtering logs by time is not supported for services writing to plain files"
+                .to_string(),
+        )
+        .into());
+    }
+
+    let mut pending = VecDeque::new();
+    let mut files: Vec<LogFile> = Vec::new();
+    for path in paths {
+        // Services commonly send stdout and stderr to the same file
+        if files.iter().any(|file| file.path == path) {
+            continue;
+        }
+
+        let mut file = LogFile {
+            path,
+            offset: 0,
+            partial: Vec::new(),
+        };
+        let mut lines = Vec::new();
+        file.read_lines(&mut lines)?;
+
+        // Without following, a last line that is still being written is returned as is
+        if !query.follow && !file.partial.is_empty() {
+            lines.push(String::from_utf8_lossy(&file.partial).into_owned());
+        }
+        let skip = match query.lines {
+            Some(n) => lines.len().saturating_sub(n),
+            None => 0,
+        };
+        pending.extend(lines.into_iter().skip(skip));
+        files.push(file);
+    }
+
+    Ok(Box::new(FileLines {
+        pending,
+        files,
+        follow: query.follow,
+    }))
+}
+
+/// Log file being read, along with how far it has been read
+struct LogFile {
+    path: PathBuf,
+    offset: u64,
+
+    /// Bytes of a line that has not been terminated yet
+    partial: Vec<u8>,
+}
+
+impl LogFile {
+    /// Reads the complete lines written since the last read into `lines`
+    fn read_lines(&mut self, lines: &mut impl Extend<String>) -> io::Result<()> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(x) if x.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(x) => return Err(x),
+        };
+
+        let len = file.metadata()?.len();
+        if len < self.offset {
+            self.offset = 0;
+            self.partial.clear();
+        }
+        if len == self.offset {
+            return Ok(());
+        }
+
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut buf = std::mem::take(&mut self.partial);
+        self.offset += file.read_to_end(&mut buf)? as u64;
+
+        let complete = buf.iter().rposition(|b| *b == b'\n').map_or(0, |i| i + 1);
+        self.partial = buf.split_off(complete);
+        lines.extend(buf.split_inclusive(|b| *b == b'\n').map(|line| {
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            String::from_utf8_lossy(line).into_owned()
+        }));
+        Ok(())
+    }
+}
+
+/// Lines of log files, polling them for new lines when following
+struct FileLines {
+    pending: VecDeque<String>,
+    files: Vec<LogFile>,
+    follow: bool,
+}
+
+impl Iterator for FileLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.pending.pop_front() {
+                return Some(Ok(line));
+            }
+            if !self.follow {
+                return None;
+            }
+
+            for file in self.files.iter_mut() {
+                if let Err(x) = file.read_lines(&mut self.pending) {
+                    return Some(Err(x));
+                }
+            }
+            if self.pending.is_empty() {
+                thread::sleep(FOLLOW_INTERVAL);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write};
+
+    #[test]
+    fn test_tail_files_returns_most_recent_lines_of_each_file() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let out = dir.path().join("echo.out.log");
+        let err = dir.path().join("echo.err.log");
+        fs::write(&out, "one\ntwo\r\nthree\nfour").unwrap();
+        fs::write(&err, "oops\n").unwrap();
+        let missing = dir.path().join("missing.log");
+
+        let paths = vec![out.clone(), err, out, missing];
+        let lines: Vec<String> = tail_files(paths, &LogQuery::new().with_lines(2))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, ["three", "four", "oops"]);
+
+        let err = tail_files(vec![], &LogQuery::new().with_since(SystemTime::now()))
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_tail_files_follows_appended_lines() {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("echo.log");
+        fs::write(&path, "old\npart").unwrap();
+
+        let query = LogQuery::new().with_lines(1).with_follow(true);
+        let mut lines = tail_files(vec![path.clone()], &query).unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "old");
+
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"ial\nnew\n").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "partial");
+        assert_eq!(lines.next().unwrap().unwrap(), "new");
+
+        // A file that shrinks has been rotated, so it is read from the start
+        fs::write(&path, "rotated\n").unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "rotated");
+    }
+}
@@ -0,0 +1,228 @@
+use super::{Error, ServiceLabel};
+use std::{fmt, io, sync::Arc};
+
+/// Strategy used by a service manager to derive the native name of a service from its label
+///
+/// The native name is the name of the unit, script, plist or Windows service that the label is
+/// installed as. systemd, OpenRC and rc.d default to [`ServiceNaming::ScriptName`], while
+/// launchd, `sc.exe` and WinSW default to [`ServiceNaming::QualifiedName`].
+///
+/// ```
+/// use service_manager::*;
+///
+/// let manager = SystemdServiceManager::system().with_naming(ServiceNaming::custom(|label| {
+///     format!("acme-{}", label.application)
+/// }));
+///
+/// let label: ServiceLabel = "com.example.api".parse().unwrap();
+/// assert_eq!(manager.naming.native_name(&label), "acme-api");
+/// ```
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ServiceNaming {
+    /// Use `{organization}-{application}`, see [`ServiceLabel::to_script_name`]
+    ///
+    /// Labels that only differ by their qualifier share the same script name.
+    #[default]
+    ScriptName,
+
+    /// Use `{qualifier}.{organization}.{application}`, see [`ServiceLabel::to_qualified_name`]
+    QualifiedName,
+
+    /// Use the name produced by a function of the label
+    ///
+    /// OpenRC and rc.d write the name unquoted into their scripts, so it may only hold ASCII
+    /// letters, digits and any of `_-.+@:,` with OpenRC, and must form a shell variable name
+    /// once each `-` is replaced with `_` with rc.d. Other names fail with
+    /// [`Error::InvalidLabel`].
+    ///
+    /// This cannot be serialized, and skipped when deserializing.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Arc<dyn Fn(&ServiceLabel) -> String + Send + Sync>),
+}
+
+impl ServiceNaming {
+    /// Creates a strategy naming services with the result of `f`
+    pub fn custom(f: impl Fn(&ServiceLabel) -> String + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(f))
+    }
+
+    /// Produces the native name of the service with `label`
+    pub fn native_name(&self, label: &ServiceLabel) -> String {
+        match self {
+            Self::ScriptName => label.to_script_name(),
+            Self::QualifiedName => label.to_qualified_name(),
+            Self::Custom(f) => f(label),
+        }
+    }
+
+    /// Recovers the label of a service from its native `name`, when the strategy allows it
+    ///
+    /// Script names lose the qualifier of the label, and names produced by a custom function
+    /// cannot be reversed, so they are parsed as if they were qualified names.
+    pub fn label_from_native(&self, name: &str) -> Option<ServiceLabel> {
+        match self {
+            Self::ScriptName => Some(ServiceLabel::from_script_name(name)),
+            Self::QualifiedName | Self::Custom(_) => name.parse().ok(),
+        }
+    }
+}
+
+impl fmt::Debug for ServiceNaming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ScriptName => f.write_str("ScriptName"),
+            Self::QualifiedName => f.write_str("QualifiedName"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+/// Two custom strategies are equal when they share the same function
+impl PartialEq for ServiceNaming {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::ScriptName, Self::ScriptName) => true,
+            (Self::QualifiedName, Self::QualifiedName) => true,
+            (Self::Custom(a), Self::Custom(b)) => {
+                std::ptr::eq(Arc::as_ptr(a) as *const (), Arc::as_ptr(b) as *const ())
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for ServiceNaming {}
+
+/// Checks that `name` can be used as the native name of a service by `manager`, which allows at
+/// most `max_len` characters
+pub(crate) fn validate_native_name(name: &str, max_len: usize, manager: &str) -> io::Result<()> {
+    let reason = if name.is_empty() {
+        "is empty".to_string()
+    } else if name.chars().count() > max_len {
+        format!("is longer than the {max_len} characters allowed by {manager}")
+    } else if name
+        .chars()
+        .any(|c| c.is_control() || c.is_whitespace() || matches!(c, '/' | '\\'))
+    {
+        "contains whitespace, control characters or path separators".to_string()
+    } else {
+        return Ok(());
+    };
+
+    Err(Error::InvalidLabel(format!("Service name {name:?} {reason}")).into())
+}
+
+/// Checks that `name` can be written unquoted into the shell scripts of `manager`, holding only
+/// ASCII letters, digits and any of `_-.+@:,`
+pub(crate) fn validate_script_name(name: &str, manager: &str) -> io::Result<()> {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "_-.+@:,".contains(c))
+    {
+        return Ok(());
+    }
+
+    Err(Error::InvalidLabel(format!(
+        "Service name {name:?} cannot be written into the scripts of {manager}, as it holds \
+         characters other than ASCII letters, digits and any of `_-.+@:,`"
+    ))
+    .into())
+}
+
+/// Checks that `name` is a shell variable name once each `-` is replaced with `_`, as rc.d
+/// names the variables of a script after it, such as `${name}_enable`
+pub(crate) fn validate_rcd_name(name: &str) -> io::Result<()> {
+    if !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Ok(());
+    }
+
+    Err(Error::InvalidLabel(format!(
+        "Service name {name:?} cannot name the variables of an rc.d script, as it holds \
+         characters other than ASCII letters, digits, `_` and `-` or starts with a digit"
+    ))
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ServiceManager;
+
+    #[test]
+    fn test_native_name() {
+        let label: ServiceLabel = "com.example.app123".parse().unwrap();
+
+        assert_eq!(
+            ServiceNaming::ScriptName.native_name(&label),
+            "example-app123"
+        );
+        assert_eq!(
+            ServiceNaming::QualifiedName.native_name(&label),
+            "com.example.app123"
+        );
+        assert_eq!(
+            ServiceNaming::custom(|label| label.application.to_uppercase()).native_name(&label),
+            "APP123"
+        );
+    }
+
+    #[test]
+    fn test_validate_native_name() {
+        assert!(validate_native_name("example-app123", 255, "systemd").is_ok());
+
+        for name in ["", "example app", "../example", "example\napp"] {
+            let err = validate_native_name(name, 255, "systemd").unwrap_err();
+            assert!(matches!(
+                Error::from_io_error(&err),
+                Some(Error::InvalidLabel(_))
+            ));
+        }
+
+        let err = validate_native_name(&"a".repeat(256), 255, "systemd").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("255 characters allowed by systemd"));
+    }
+
+    #[test]
+    fn test_script_names_must_be_shell_safe() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let ctx = crate::ServiceInstallCtx::new(
+            "com.example.echo".parse().unwrap(),
+            "/usr/local/bin/echo",
+        );
+        let openrc = |name: &'static str| {
+            crate::OpenRcServiceManager::system()
+                .with_config(crate::OpenRcConfig {
+                    root: Some(root.path().to_path_buf()),
+                })
+                .with_naming(ServiceNaming::custom(move |_| name.to_string()))
+        };
+        let rcd = |name: &'static str| {
+            crate::RcdServiceManager::system()
+                .with_config(crate::RcdConfig {
+                    root: Some(root.path().to_path_buf()),
+                })
+                .with_naming(ServiceNaming::custom(move |_| name.to_string()))
+        };
+        let is_invalid_label =
+            |err: io::Error| matches!(Error::from_io_error(&err), Some(Error::InvalidLabel(_)));
+
+        for name in ["echo$(id)", "echo;reboot", "`id`", "echo\"", "echo'"] {
+            assert!(is_invalid_label(openrc(name).render(&ctx).unwrap_err()));
+            assert!(is_invalid_label(rcd(name).render(&ctx).unwrap_err()));
+        }
+
+        // rc.d names variables after the script, which cannot hold a dot or start with a digit
+        assert!(openrc("acme.api").render(&ctx).is_ok());
+        assert!(is_invalid_label(rcd("acme.api").render(&ctx).unwrap_err()));
+        assert!(is_invalid_label(rcd("3com-api").render(&ctx).unwrap_err()));
+        assert!(rcd("acme-api").render(&ctx).is_ok());
+    }
+}
@@ -1,31 +1,61 @@
-use crate::utils::wrap_output;
+use crate::utils::{wrap_output, CommandOutput};
 
 use super::{
-    utils, ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    dependency::{unsupported_dependency, validate_dependency_name},
+    limits::unsupported_limit,
+    logs, naming, utils, CommandRunner, DependencyKind, Error, InstallOutcome, LogLines, LogQuery,
+    RenderedFile, RenderedService, ResourceLimits, ServiceCommand, ServiceDependency,
+    ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx, ServiceLabel, ServiceLevel,
+    ServiceManager, ServiceNaming, ServiceOutput, ServiceRestartCtx, ServiceRollbackCtx,
+    ServiceStartCtx, ServiceStopCtx, ServiceUninstallCtx, SharedCommandRunner,
 };
 use std::{
-    ffi::{OsStr, OsString},
+    ffi::OsStr,
     io,
-    path::PathBuf,
-    process::{Command, Output, Stdio},
+    path::{Path, PathBuf},
 };
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 static RC_SERVICE: &str = "rc-service";
 static RC_UPDATE: &str = "rc-update";
 
 // NOTE: On Alpine Linux, /etc/init.d/{script} has permissions of rwxr-xr-x (755)
 const SCRIPT_FILE_PERMISSIONS: u32 = 0o755;
 
+/// Maximum length of a script name, as limited by the file system
+const MAX_SCRIPT_NAME_LEN: usize = 255;
+
+/// Seconds that `supervise-daemon` waits before respawning a service that exited
+const RESPAWN_DELAY_SECS: u32 = 5;
+
 /// Configuration settings tied to OpenRC services
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
-pub struct OpenRcConfig {}
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct OpenRcConfig {
+    /// Optional directory to use as the root of the filesystem when locating scripts
+    ///
+    /// Services installed into a root are added to the default runlevel by linking them into
+    /// `/etc/runlevels/default`, while starting and stopping them is unsupported.
+    pub root: Option<PathBuf>,
+}
 
 /// Implementation of [`ServiceManager`] for Linux's [OpenRC](https://en.wikipedia.org/wiki/OpenRC)
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct OpenRcServiceManager {
     /// Configuration settings tied to OpenRC services
     pub config: OpenRcConfig,
+
+    /// Strategy deriving the name of the script of a service from its label
+    pub naming: ServiceNaming,
+
+    /// Runner used to execute the commands of the service manager
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub runner: SharedCommandRunner,
 }
 
 impl OpenRcServiceManager {
@@ -36,7 +66,56 @@ impl OpenRcServiceManager {
 
     /// Update manager to use the specified config
     pub fn with_config(self, config: OpenRcConfig) -> Self {
-        Self { config }
+        Self { config, ..self }
+    }
+
+    /// Update manager to name scripts using the specified strategy
+    pub fn with_naming(self, naming: ServiceNaming) -> Self {
+        Self { naming, ..self }
+    }
+
+    /// Update manager to execute commands using the specified runner
+    pub fn with_runner(self, runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: SharedCommandRunner::new(runner),
+            ..self
+        }
+    }
+
+    fn script_path(&self, script_name: &str) -> PathBuf {
+        utils::rooted(self.config.root.as_deref(), service_dir_path()).join(script_name)
+    }
+
+    /// Name of the script of the service with `label`, failing if it is not installed
+    fn installed_script_name(&self, label: &ServiceLabel) -> io::Result<String> {
+        let script_name = self.naming.native_name(label);
+        if !self.script_path(&script_name).exists() {
+            return Err(Error::NotInstalled(script_name).into());
+        }
+        Ok(script_name)
+    }
+
+    /// Produces the command adding the script to the default runlevel, or deleting it from
+    /// there if `add` is false
+    fn runlevel_command(&self, script_name: &str, add: bool) -> ServiceCommand {
+        match self.config.root.as_deref() {
+            // rc-update cannot operate on a root, so link the script into the runlevel directly
+            Some(root) if add => ServiceCommand::new("ln")
+                .arg("-sf")
+                .arg(service_dir_path().join(script_name))
+                .arg(runlevel_link_path(root, script_name)),
+            Some(root) => ServiceCommand::new("rm")
+                .arg("-f")
+                .arg(runlevel_link_path(root, script_name)),
+            // Add with default run level explicitly defined to prevent weird systems
+            // like alpine's docker container with openrc from setting a different
+            // run level than default
+            None => rc_update_command(
+                if add { "add" } else { "del" },
+                script_name,
+                [OsStr::new("default")],
+            ),
+        }
     }
 }
 
@@ -50,60 +129,157 @@ impl ServiceManager for OpenRcServiceManager {
     }
 
     fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
-        let dir_path = service_dir_path();
-        std::fs::create_dir_all(&dir_path)?;
-
-        let script_name = ctx.label.to_script_name();
-        let script_path = dir_path.join(&script_name);
-
-        let script = match ctx.contents {
-            Some(contents) => contents,
-            _ => make_script(
-                &script_name,
-                &script_name,
-                ctx.program.as_os_str(),
-                ctx.args,
+        utils::install_rendered(&self.runner, &self.render(&ctx)?)
+    }
+
+    /// Rewrites a changed script, restarting the service only if it was running. OpenRC reads
+    /// scripts whenever it runs them, so there is nothing to reload.
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        utils::install_or_update_rendered(self, &self.runner, ctx, &[])
+    }
+
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        let script_name = self.naming.native_name(&ctx.label);
+        naming::validate_native_name(&script_name, MAX_SCRIPT_NAME_LEN, "OpenRC")?;
+        naming::validate_script_name(&script_name, "OpenRC")?;
+        let script_path = self.script_path(&script_name);
+        utils::ensure_no_conflict(&script_path, &script_name, &ctx.label)?;
+
+        let script = match &ctx.contents {
+            Some(contents) => contents.clone().into_bytes(),
+            _ => utils::mark_label_bytes(
+                make_script(&script_name, &script_name, ctx)?,
+                &ctx.label,
+                utils::MarkerStyle::Hash,
             ),
         };
 
-        utils::write_file(
-            script_path.as_path(),
-            script.as_bytes(),
-            SCRIPT_FILE_PERMISSIONS,
-        )?;
-
+        let mut commands = Vec::new();
         if ctx.autostart {
-            // Add with default run level explicitly defined to prevent weird systems
-            // like alpine's docker container with openrc from setting a different
-            // run level than default
-            rc_update("add", &script_name, [OsStr::new("default")])?;
+            commands.push(self.runlevel_command(&script_name, true));
         }
 
-        Ok(())
+        Ok(RenderedService {
+            files: vec![RenderedFile {
+                path: script_path,
+                contents: script,
+                mode: SCRIPT_FILE_PERMISSIONS,
+            }],
+            commands,
+        })
     }
 
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
+        let script_name = self.naming.native_name(&ctx.label);
+        let script_path = self.script_path(&script_name);
+        if !script_path.exists() {
+            return Err(Error::NotInstalled(script_name).into());
+        }
+
         // If the script is configured to run at boot, remove it
-        let _ = rc_update(
-            "del",
-            &ctx.label.to_script_name(),
-            [OsStr::new("default")],
-        );
+        match self.config.root.as_deref() {
+            Some(root) => {
+                let _ = std::fs::remove_file(runlevel_link_path(root, &script_name));
+            }
+            None => {
+                let _ = rc_update(&self.runner, "del", &script_name, [OsStr::new("default")]);
+            }
+        }
 
         // Uninstall service by removing the script
-        std::fs::remove_file(service_dir_path().join(&ctx.label.to_script_name()))
+        utils::remove_definition(&script_path)
     }
 
     fn start(&self, ctx: ServiceStartCtx) -> io::Result<()> {
-        wrap_output(rc_service("start", &ctx.label.to_script_name(), [])?)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+        wrap_output(rc_service(
+            &self.runner,
+            "start",
+            &self.naming.native_name(&ctx.label),
+            [],
+        )?)?;
         Ok(())
     }
 
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()> {
-        wrap_output(rc_service("stop", &ctx.label.to_script_name(), [])?)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+        wrap_output(rc_service(
+            &self.runner,
+            "stop",
+            &self.naming.native_name(&ctx.label),
+            [],
+        )?)?;
+        Ok(())
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+        wrap_output(rc_service(
+            &self.runner,
+            "restart",
+            &self.naming.native_name(&ctx.label),
+            [],
+        )?)?;
+        Ok(())
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> io::Result<()> {
+        let script_name = self.installed_script_name(&ctx.label)?;
+        let command = self.runlevel_command(&script_name, true);
+        wrap_output(utils::output(&self.runner, &command)?)?;
         Ok(())
     }
 
+    fn disable(&self, ctx: ServiceDisableCtx) -> io::Result<()> {
+        let script_name = self.installed_script_name(&ctx.label)?;
+        let command = self.runlevel_command(&script_name, false);
+        wrap_output(utils::output(&self.runner, &command)?)?;
+        Ok(())
+    }
+
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        let script_name = self.installed_script_name(&ctx.label)?;
+        if let Some(root) = self.config.root.as_deref() {
+            return Ok(std::fs::symlink_metadata(runlevel_link_path(root, &script_name)).is_ok());
+        }
+
+        let output = wrap_output(utils::output(&self.runner, &rc_update_show_command())?)?;
+        Ok(in_default_runlevel(
+            &String::from_utf8_lossy(&output.stdout),
+            &script_name,
+        ))
+    }
+
+    /// Restores the previous script, which OpenRC reads whenever it runs it
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> io::Result<()> {
+        let script_name = self.installed_script_name(&ctx.label)?;
+        let script_path = self.script_path(&script_name);
+        utils::restore_backup(&script_path, &script_name, SCRIPT_FILE_PERMISSIONS)
+    }
+
+    /// Reads the files named by the `output_log` and `error_log` variables of the script, which
+    /// `start-stop-daemon` redirects the output of the service to
+    fn logs(&self, label: ServiceLabel, query: LogQuery) -> io::Result<LogLines> {
+        let script_name = self.installed_script_name(&label)?;
+        let script = std::fs::read(self.script_path(&script_name))?;
+        let script = String::from_utf8_lossy(&script);
+
+        let root = self.config.root.as_deref();
+        let paths: Vec<PathBuf> = ["output_log", "error_log"]
+            .into_iter()
+            .filter_map(|name| utils::evaluated_script_variable(&script, name))
+            .map(|path| utils::rooted(root, PathBuf::from(path)))
+            .collect();
+        if paths.is_empty() {
+            return Err(Error::Unsupported(format!(
+                "Service {script_name} does not set output_log or error_log, so its output is \
+                 not kept"
+            ))
+            .into());
+        }
+        logs::tail_files(paths, &query)
+    }
+
     fn level(&self) -> ServiceLevel {
         ServiceLevel::System
     }
@@ -111,129 +287,692 @@ impl ServiceManager for OpenRcServiceManager {
     fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
         match level {
             ServiceLevel::System => Ok(()),
-            ServiceLevel::User => Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "OpenRC does not support user-level services",
-            )),
+            ServiceLevel::User => Err(Error::Unsupported(
+                "OpenRC does not support user-level services".to_string(),
+            )
+            .into()),
         }
     }
 
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<crate::ServiceStatus> {
-        let output = rc_service("status", &ctx.label.to_script_name(), [])?;
-        match output.status.code() {
-            Some(1) => {
-                let mut stdio = String::from_utf8_lossy(&output.stderr);
-                if stdio.trim().is_empty() {
-                    stdio = String::from_utf8_lossy(&output.stdout);
-                }
-                if stdio.contains("does not exist") {
-                    Ok(crate::ServiceStatus::NotInstalled)
-                } else {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!(
-                            "Failed to get status of service {}: {}",
-                            ctx.label.to_script_name(),
-                            stdio
-                        ),
-                    ))
-                }
+        if self.config.root.is_some() {
+            let script_path = self.script_path(&self.naming.native_name(&ctx.label));
+            return Ok(utils::offline_status(&script_path));
+        }
+
+        let output = rc_service(
+            &self.runner,
+            "status",
+            &self.naming.native_name(&ctx.label),
+            [],
+        )?;
+        status_from_output(output)
+    }
+
+    fn status_detail(
+        &self,
+        ctx: crate::ServiceStatusCtx,
+    ) -> io::Result<crate::ServiceStatusDetail> {
+        if self.config.root.is_some() {
+            return Ok(self.status(ctx)?.into());
+        }
+
+        let script_name = self.naming.native_name(&ctx.label);
+        let output = rc_service(&self.runner, "status", &script_name, [])?;
+        let native_state = parse_status(&String::from_utf8_lossy(&output.stdout));
+        let state = state_from_output(output, native_state.as_deref())?;
+
+        // supervise-daemon writes its own pid to the pid file and records that of the service
+        // separately
+        let pid = if state == crate::ServiceState::Running {
+            [child_pid_path(&script_name), pid_file_path(&script_name)]
+                .iter()
+                .filter_map(|path| std::fs::read_to_string(path).ok())
+                .find_map(|pid| pid.trim().parse().ok())
+        } else {
+            None
+        };
+
+        Ok(crate::ServiceStatusDetail {
+            pid,
+            native_state,
+            ..crate::ServiceStatusDetail::new(state)
+        })
+    }
+
+    fn list(&self, ctx: crate::ServiceListCtx) -> io::Result<Vec<crate::InstalledService>> {
+        let mut services = Vec::new();
+        let dir_path = utils::rooted(self.config.root.as_deref(), service_dir_path());
+        let scripts =
+            utils::script_definitions(&dir_path, utils::list_dir_names(&dir_path, false)?);
+        for label in utils::definition_labels(scripts, &self.naming, &ctx) {
+            let status = self.status(crate::ServiceStatusCtx {
+                label: label.clone(),
+            })?;
+            services.push(crate::InstalledService { label, status });
+        }
+
+        Ok(services)
+    }
+}
+
+/// Parses the output of `rc-service {name} status`, e.g. ` * status: started`
+fn parse_status(stdout: &str) -> Option<String> {
+    stdout
+        .lines()
+        .filter_map(|line| line.split_once("status:"))
+        .map(|(_, state)| state.trim().to_string())
+        .next()
+}
+
+/// Interprets the exit code of `rc-service {name} status`
+fn status_from_output(output: CommandOutput) -> io::Result<crate::ServiceStatus> {
+    match output.status.code() {
+        Some(1) => {
+            let mut stdio = String::from_utf8_lossy(&output.stderr);
+            if stdio.trim().is_empty() {
+                stdio = String::from_utf8_lossy(&output.stdout);
+            }
+            if stdio.contains("does not exist") {
+                Ok(crate::ServiceStatus::NotInstalled)
+            } else {
+                Err(utils::command_failed(output).into())
             }
-            Some(0) => Ok(crate::ServiceStatus::Running),
-            Some(3) => Ok(crate::ServiceStatus::Stopped(None)),
-            _ => Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Failed to get status of service {}: {}",
-                    ctx.label.to_script_name(),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            )),
         }
+        Some(0) => Ok(crate::ServiceStatus::Running),
+        Some(3) => Ok(crate::ServiceStatus::Stopped(None)),
+        _ => Err(utils::command_failed(output).into()),
     }
 }
 
+/// Interprets the exit code of `rc-service {name} status` along with the `native_state` it
+/// printed
+fn state_from_output(
+    output: CommandOutput,
+    native_state: Option<&str>,
+) -> io::Result<crate::ServiceState> {
+    Ok(match (output.status.code(), native_state) {
+        (Some(1), _) => match status_from_output(output)? {
+            crate::ServiceStatus::NotInstalled => crate::ServiceState::NotInstalled,
+            _ => crate::ServiceState::Unknown,
+        },
+        (_, Some("starting")) => crate::ServiceState::Starting,
+        (_, Some("stopping")) => crate::ServiceState::Stopping,
+        (_, Some("crashed")) | (_, Some("failed")) => crate::ServiceState::Failed,
+        (Some(0), _) => crate::ServiceState::Running,
+        (Some(3), _) => crate::ServiceState::Stopped,
+        _ => return Err(utils::command_failed(output).into()),
+    })
+}
+
+/// Produces the command listing the scripts in the default runlevel
+fn rc_update_show_command() -> ServiceCommand {
+    ServiceCommand::new(RC_UPDATE).arg("show").arg("default")
+}
+
+/// Determines if the output of `rc-update show default` lists the script `script_name`, e.g.
+/// `            sshd | default`
+fn in_default_runlevel(stdout: &str, script_name: &str) -> bool {
+    stdout.lines().any(|line| match line.split_once('|') {
+        Some((name, runlevels)) => {
+            name.trim() == script_name && runlevels.split_whitespace().any(|x| x == "default")
+        }
+        None => false,
+    })
+}
+
+/// Path of the file that start-stop-daemon writes the pid to when the script sets `pidfile`
+fn pid_file_path(script_name: &str) -> PathBuf {
+    PathBuf::from(format!("/run/{script_name}.pid"))
+}
+
+/// Path of the file that supervise-daemon records the pid of the service it supervises in
+fn child_pid_path(script_name: &str) -> PathBuf {
+    PathBuf::from(format!("/run/openrc/options/{script_name}/child_pid"))
+}
+
 fn rc_service<'a>(
+    runner: &dyn CommandRunner,
     cmd: &str,
     service: &str,
     args: impl IntoIterator<Item = &'a OsStr>,
-) -> io::Result<Output> {
-    let mut command = Command::new(RC_SERVICE);
-    command
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+) -> io::Result<CommandOutput> {
+    utils::output(runner, &rc_service_command(cmd, service, args))
+}
+
+fn rc_service_command<'a>(
+    cmd: &str,
+    service: &str,
+    args: impl IntoIterator<Item = &'a OsStr>,
+) -> ServiceCommand {
+    ServiceCommand::new(RC_SERVICE)
         .arg(service)
-        .arg(cmd);
-    for arg in args {
-        command.arg(arg);
-    }
-    command.output()
+        .arg(cmd)
+        .args(args)
 }
 
 fn rc_update<'a>(
+    runner: &dyn CommandRunner,
     cmd: &str,
     service: &str,
     args: impl IntoIterator<Item = &'a OsStr>,
 ) -> io::Result<()> {
-    let mut command = Command::new(RC_UPDATE);
-    command
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
+    let command = rc_update_command(cmd, service, args);
+    wrap_output(utils::output(runner, &command)?)?;
+    Ok(())
+}
+
+fn rc_update_command<'a>(
+    cmd: &str,
+    service: &str,
+    args: impl IntoIterator<Item = &'a OsStr>,
+) -> ServiceCommand {
+    ServiceCommand::new(RC_UPDATE)
         .arg(cmd)
-        .arg(service);
+        .arg(service)
+        .args(args)
+}
+
+#[inline]
+fn service_dir_path() -> PathBuf {
+    PathBuf::from("/etc/init.d")
+}
 
-    for arg in args {
-        command.arg(arg);
+/// Path of the link adding a script to the default runlevel within `root`
+fn runlevel_link_path(root: &Path, script_name: &str) -> PathBuf {
+    utils::rooted(Some(root), PathBuf::from("/etc/runlevels/default")).join(script_name)
+}
+
+/// Lines of a script sending the stream named `stream` (`output` or `error`) to `output`
+///
+/// `start-stop-daemon` appends to log files and pipes the stream into a logger command, which
+/// `logger` uses to forward it to syslog at `priority`.
+fn output_lines(stream: &str, output: &ServiceOutput, priority: &str) -> io::Result<Vec<u8>> {
+    Ok(match output {
+        ServiceOutput::Inherit => Vec::new(),
+        ServiceOutput::Null => format!("{stream}_log=\"/dev/null\"\n").into_bytes(),
+        ServiceOutput::File(path) | ServiceOutput::Append(path) => {
+            let path = utils::os_str_bytes(path.as_os_str(), "The log file")?;
+            let variable = format!("{stream}_log");
+            let word = eval_word(&path, "the log file", &variable)?;
+            let mut line = format!("{variable}=").into_bytes();
+            line.extend(utils::quote_shell_string(&word));
+            line.push(b'\n');
+            line
+        }
+        ServiceOutput::Syslog { identifier } => {
+            if let Some(identifier) = identifier {
+                logs::validate_syslog_identifier("OpenRC", identifier)?;
+            }
+            let identifier = identifier.as_deref().unwrap_or("${RC_SVCNAME}");
+            format!("{stream}_logger=\"logger -t {identifier} -p daemon.{priority}\"\n")
+                .into_bytes()
+        }
+    })
+}
+
+/// Quotes `value` as a single word of a variable that `openrc-run` expands unquoted before
+/// evaluating it, such as `command_args`, `directory` or `output_log`
+///
+/// The expansion splits the variable on whitespace and joins the fields with single spaces
+/// again. Spaces are therefore escaped outside of the quotes, so that no field holds whitespace,
+/// while tabs and line breaks cannot be passed at all. Fields holding quoted characters start
+/// with a quote, so they match no file when the shell expands them as patterns.
+fn eval_word(value: &[u8], what: &str, variable: &str) -> io::Result<Vec<u8>> {
+    if value.contains(&b'\t') || value.contains(&b'\n') {
+        return Err(Error::InvalidDefinition(format!(
+            "OpenRC is unable to pass {what} {:?}, as it splits {variable} on whitespace",
+            String::from_utf8_lossy(value)
+        ))
+        .into());
     }
 
-    let output = command.output()?;
+    let mut word = Vec::new();
+    for b in utils::quote_shell_word(value)? {
+        match b {
+            b' ' => word.extend_from_slice(br"'\ '"),
+            b => word.push(b),
+        }
+    }
+    Ok(word)
+}
 
-    if output.status.success() {
-        Ok(())
-    } else {
-        let msg = String::from_utf8(output.stderr)
-            .ok()
-            .filter(|s| !s.trim().is_empty())
-            .or_else(|| {
-                String::from_utf8(output.stdout)
-                    .ok()
-                    .filter(|s| !s.trim().is_empty())
-            })
-            .unwrap_or_else(|| format!("Failed to {cmd} {service}"));
+/// Produces the value of `command_args` passing `args` to the program, each of them quoted as
+/// done by [`eval_word`]
+fn command_args<'a>(args: impl Iterator<Item = &'a OsStr>) -> io::Result<Vec<u8>> {
+    let words = args
+        .map(|arg| {
+            let arg = utils::os_str_bytes(arg, "The argument")?;
+            eval_word(&arg, "the argument", "command_args")
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(utils::quote_shell_string(&words.join(&b' ')))
+}
+
+/// Produces the lines of `depend()` declaring `dependencies`, each kind on a line of its own,
+/// along with a need for the network if `requires_network`
+///
+/// Wanted services are used if they are part of the runlevel. OpenRC has no conflicts.
+fn depend_lines(dependencies: &[ServiceDependency], requires_network: bool) -> io::Result<String> {
+    if dependencies
+        .iter()
+        .any(|x| x.kind == DependencyKind::Conflicts)
+    {
+        return Err(unsupported_dependency("OpenRC", DependencyKind::Conflicts));
+    }
+
+    for dependency in dependencies {
+        validate_dependency_name("OpenRC", &dependency.service)?;
+    }
 
-        Err(io::Error::new(io::ErrorKind::Other, msg))
+    let mut lines = String::new();
+    for (keyword, kind) in [
+        ("need", DependencyKind::Requires),
+        ("use", DependencyKind::Wants),
+        ("after", DependencyKind::After),
+        ("before", DependencyKind::Before),
+    ] {
+        let network = (requires_network && kind == DependencyKind::Requires).then(|| "net");
+        let services: Vec<&str> = network
+            .into_iter()
+            .chain(
+                dependencies
+                    .iter()
+                    .filter(|x| x.kind == kind)
+                    .map(|x| x.service.as_str()),
+            )
+            .collect();
+        if !services.is_empty() {
+            lines.push_str(&format!("    {keyword} {}\n", services.join(" ")));
+        }
     }
+    Ok(lines)
 }
 
-#[inline]
-fn service_dir_path() -> PathBuf {
-    PathBuf::from("/etc/init.d")
+/// Produces the flags of `ulimit` setting `limits` in the shell that starts the service, which
+/// sets both the soft and hard limits
+///
+/// Memory is limited through the address space in KiB and core dumps in the 512 byte blocks used
+/// by `ash` and `dash`. A CPU quota needs cgroups and is unsupported.
+fn ulimit_flags(limits: &ResourceLimits) -> io::Result<String> {
+    if limits.cpu_quota.is_some() {
+        return Err(unsupported_limit("OpenRC", "the CPU quota of a service"));
+    }
+
+    let flags: Vec<String> = [
+        (
+            "-v",
+            limits
+                .memory_max
+                .map(|x| x / 1024 + u64::from(x % 1024 != 0)),
+        ),
+        ("-n", limits.nofile),
+        ("-u", limits.nproc),
+        (
+            "-c",
+            limits.core_size.map(|x| x / 512 + u64::from(x % 512 != 0)),
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(flag, limit)| Some(format!("{flag} {}", limit?)))
+    .collect();
+    Ok(flags.join(" "))
+}
+
+/// Produces the script of the service described by `ctx`
+///
+/// Unless restarting on failure is disabled, the service runs under `supervise-daemon`, which
+/// respawns it whenever it exits. Environment variables are exported by the script, so that
+/// the daemon started by `openrc-run` inherits them.
+///
+/// The script is made of bytes rather than text, as the shell passes the bytes of arguments
+/// that are not valid UTF-8 through as they are.
+fn make_script(description: &str, provide: &str, ctx: &ServiceInstallCtx) -> io::Result<Vec<u8>> {
+    use std::io::Write as _;
+    let depend = depend_lines(&ctx.dependencies, ctx.requires_network)?;
+    let ulimit = ulimit_flags(&ctx.limits)?;
+    let program = utils::os_str_bytes(ctx.program.as_os_str(), "The program")?;
+    if !utils::is_plain_shell_word(&program) {
+        return Err(Error::InvalidDefinition(format!(
+            "OpenRC is unable to run {:?}, as it passes the command to the shell unquoted",
+            String::from_utf8_lossy(&program)
+        ))
+        .into());
+    }
+
+    let mut script = Vec::new();
+    let _ = writeln!(script, "#!/sbin/openrc-run");
+    let _ = writeln!(script);
+    script.extend_from_slice(b"description=");
+    script.extend(utils::quote_shell_string(description.as_bytes()));
+    script.extend_from_slice(b"\ncommand=");
+    script.extend_from_slice(&program);
+    script.extend_from_slice(b"\ncommand_args=");
+    script.extend(command_args(ctx.args_iter())?);
+    let _ = writeln!(script);
+
+    if let Some(username) = &ctx.username {
+        if !utils::is_plain_shell_word(username.as_bytes()) {
+            return Err(Error::InvalidDefinition(format!(
+                "OpenRC is unable to run the service as {username:?}, as it passes the user to \
+                 the shell unquoted"
+            ))
+            .into());
+        }
+        let _ = writeln!(script, "command_user=\"{username}\"");
+    }
+    if let Some(working_directory) = &ctx.working_directory {
+        let directory =
+            utils::os_str_bytes(working_directory.as_os_str(), "The working directory")?;
+        script.extend_from_slice(b"directory=");
+        script.extend(utils::quote_shell_string(&eval_word(
+            &directory,
+            "the working directory",
+            "directory",
+        )?));
+        let _ = writeln!(script);
+    }
+
+    let _ = writeln!(script, "pidfile=\"/run/${{RC_SVCNAME}}.pid\"");
+    if ctx.disable_restart_on_failure {
+        let _ = writeln!(script, "command_background=true");
+    } else {
+        let _ = writeln!(script, "supervisor=supervise-daemon");
+        // A service that keeps failing is respawned without limit rather than given up on
+        let _ = writeln!(script, "respawn_delay={RESPAWN_DELAY_SECS}");
+        let _ = writeln!(script, "respawn_max=0");
+    }
+    script.extend(output_lines("output", &ctx.stdout, "info")?);
+    script.extend(output_lines("error", &ctx.stderr, "err")?);
+    if !ulimit.is_empty() {
+        let _ = writeln!(script, "rc_ulimit=\"{ulimit}\"");
+    }
+
+    if let Some(env_vars) = &ctx.environment {
+        let _ = writeln!(script);
+        for (var, val) in env_vars {
+            if !utils::is_valid_env_name(var) {
+                return Err(Error::InvalidDefinition(format!(
+                    "OpenRC is unable to set the environment variable {var:?}, as names may \
+                     only hold letters, digits and underscores and may not start with a digit"
+                ))
+                .into());
+            }
+            let _ = write!(script, "export {var}=");
+            script.extend(utils::quote_shell_word(val.as_bytes())?);
+            let _ = writeln!(script);
+        }
+    }
+
+    let _ = writeln!(script);
+    let _ = writeln!(script, "depend() {{");
+    let _ = writeln!(script, "    provide {provide}");
+    let _ = write!(script, "{depend}}}");
+    Ok(script)
 }
 
-fn make_script(description: &str, provide: &str, program: &OsStr, args: Vec<OsString>) -> String {
-    let program = program.to_string_lossy();
-    let args = args
-        .into_iter()
-        .map(|a| a.to_string_lossy().to_string())
-        .collect::<Vec<String>>()
-        .join(" ");
-    format!(
-        r#"
-#!/sbin/openrc-run
-
-description="{description}"
-command="{program}"
-command_args="{args}"
-pidfile="/run/${{RC_SVCNAME}}.pid"
-command_background=true
-
-depend() {{
-    provide {provide}
-}}
-    "#
-    )
-    .trim()
-    .to_string()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `script` to a file, checks its syntax and sources it, returning the words held by
+    /// `variable` once evaluated the way `openrc-run` does
+    #[cfg(unix)]
+    fn eval_variable(script: &[u8], variable: &str) -> Vec<Vec<u8>> {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("script");
+        std::fs::write(&path, script).unwrap();
+        // A file that an argument expanded as a pattern would match
+        std::fs::write(dir.path().join("victim"), "").unwrap();
+
+        let check = std::process::Command::new("sh")
+            .arg("-n")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(check.success());
+
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(r#". "$1"; eval "value=\$$2"; eval set -- $value; printf '%s\0' "$@""#)
+            .arg("sh")
+            .arg(&path)
+            .arg(variable)
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut args: Vec<Vec<u8>> = output
+            .stdout
+            .split(|b| *b == 0)
+            .map(<[u8]>::to_vec)
+            .collect();
+        args.pop();
+        args
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_args_survive_the_shell() {
+        let args = [
+            "hello",
+            "two  words",
+            " padded ",
+            "it's",
+            "\"quoted\"",
+            "$HOME",
+            "${PATH}",
+            "$(echo injected)",
+            "`echo injected`",
+            "back\\slash",
+            "*",
+            "a;b && c | d > e",
+            "~",
+            "",
+            "100%",
+        ];
+        let mut args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        // The shell passes bytes that are not valid UTF-8 through as they are
+        args.push(b"caf\xe9".to_vec());
+        let mut ctx =
+            ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "/usr/local/bin/echo");
+        ctx.args = args
+            .iter()
+            .map(|arg| std::os::unix::ffi::OsStringExt::from_vec(arg.clone()))
+            .collect();
+
+        let script = make_script("echo", "echo", &ctx).unwrap();
+        assert_eq!(eval_variable(&script, "command_args"), args);
+
+        ctx.args = vec!["line\nbreak".into()];
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.args = Vec::new();
+        ctx.program = PathBuf::from("/opt/my app/echo");
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_script_output_targets_limits_and_dependencies() {
+        let ctx =
+            ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/usr/local/bin/echo")
+                .stdout(ServiceOutput::File(PathBuf::from("/var/log/echo.log")))
+                .stderr(ServiceOutput::Syslog { identifier: None })
+                .limits(
+                    ResourceLimits::new()
+                        .with_memory_max(1 << 20)
+                        .with_nofile(4096)
+                        .with_core_size(1000),
+                )
+                .dependency(ServiceDependency::requires("rabbitmq"))
+                .dependency(ServiceDependency::after("rabbitmq"))
+                .dependency(ServiceDependency::after("postgresql"))
+                .restart_on_failure(false)
+                .build();
+        let script = String::from_utf8(make_script("echo", "echo", &ctx).unwrap()).unwrap();
+        assert!(script.contains(
+            "command_background=true\n\
+             output_log=\"/var/log/echo.log\"\n\
+             error_logger=\"logger -t ${RC_SVCNAME} -p daemon.err\"\n\
+             rc_ulimit=\"-v 1024 -n 4096 -c 2\"\n"
+        ));
+        assert!(script.ends_with(
+            "depend() {\n    \
+                 provide echo\n    \
+                 need rabbitmq\n    \
+                 after rabbitmq postgresql\n\
+             }"
+        ));
+
+        let err = depend_lines(&[ServiceDependency::conflicts("other")], false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        for service in ["net; reboot", "$(reboot)", "net\n}\nreboot"] {
+            let err = depend_lines(&[ServiceDependency::after(service)], false).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let limits = ResourceLimits::new().with_cpu_quota(50);
+        let err = ulimit_flags(&limits).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            utils::script_variable(&script, "output_log").as_deref(),
+            Some("/var/log/echo.log")
+        );
+        for identifier in ["x\"; reboot; \"", "$(reboot)", "two words"] {
+            let output = ServiceOutput::syslog(identifier);
+            let err = output_lines("error", &output, "err").unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let output = ServiceOutput::File(PathBuf::from("/var/log/echo\nreboot"));
+        let err = output_lines("output", &output, "info").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_script_user_directory_environment_and_supervision() {
+        let ctx =
+            ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/usr/local/bin/echo")
+                .username("echo")
+                .working_directory("/srv/echo")
+                .env("GREETING", "hello world")
+                .env("HOME_DIR", "$HOME")
+                .requires_network(true)
+                .dependency(ServiceDependency::requires("rabbitmq"))
+                .build();
+        let script = String::from_utf8(make_script("echo", "echo", &ctx).unwrap()).unwrap();
+        assert!(script.contains(
+            "command_user=\"echo\"\n\
+             directory=\"/srv/echo\"\n\
+             pidfile=\"/run/${RC_SVCNAME}.pid\"\n\
+             supervisor=supervise-daemon\n\
+             respawn_delay=5\n\
+             respawn_max=0\n\
+             \n\
+             export GREETING='hello world'\n\
+             export HOME_DIR='$HOME'\n\
+             \n"
+        ));
+        assert!(!script.contains("command_background"));
+        assert!(script.ends_with(
+            "depend() {\n    \
+                 provide echo\n    \
+                 need net rabbitmq\n\
+             }"
+        ));
+
+        let mut ctx = ctx;
+        ctx.username = Some("echo user".to_string());
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.username = None;
+        ctx.environment = Some(vec![("1NVALID".to_string(), "value".to_string())]);
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_directory_and_environment_survive_the_shell() {
+        let ctx =
+            ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/usr/local/bin/echo")
+                .working_directory("/srv/my app's \"data\"")
+                .env("GREETING", "it's $(echo injected)")
+                .stdout(ServiceOutput::File(PathBuf::from(
+                    "/var/log/$(reboot) it's.log",
+                )))
+                .stderr(ServiceOutput::Append(PathBuf::from(
+                    "/var/log/`id`;\"err\".log",
+                )))
+                .build();
+        let script = make_script("echo", "echo", &ctx).unwrap();
+        assert_eq!(
+            eval_variable(&script, "directory"),
+            [b"/srv/my app's \"data\"".to_vec()]
+        );
+        for (variable, path) in [
+            ("output_log", "/var/log/$(reboot) it's.log"),
+            ("error_log", "/var/log/`id`;\"err\".log"),
+        ] {
+            assert_eq!(eval_variable(&script, variable), [path.as_bytes().to_vec()]);
+            let value =
+                utils::evaluated_script_variable(&String::from_utf8_lossy(&script), variable);
+            assert_eq!(value.as_deref(), Some(path));
+        }
+
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("script");
+        std::fs::write(&path, &script).unwrap();
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(r#". "$1"; exec printenv GREETING"#)
+            .arg("sh")
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"it's $(echo injected)\n");
+    }
+
+    #[test]
+    fn test_logs_are_read_from_script_log_files() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = OpenRcServiceManager::system().with_config(OpenRcConfig {
+            root: Some(root.path().to_path_buf()),
+        });
+        let label: ServiceLabel = "com.example.echo".parse().unwrap();
+
+        let script = r#"#!/sbin/openrc-run
+command="/usr/local/bin/echo"
+output_log="'/var/log/echo'\ '\"out\".log'"
+error_log='/var/log/echo\ err.log'
+"#;
+        let script_path = manager.script_path(&manager.naming.native_name(&label));
+        std::fs::create_dir_all(script_path.parent().unwrap()).unwrap();
+        std::fs::write(&script_path, script).unwrap();
+
+        let log_dir = root.path().join("var/log");
+        std::fs::create_dir_all(&log_dir).unwrap();
+        std::fs::write(log_dir.join("echo \"out\".log"), "hello\nworld\n").unwrap();
+        std::fs::write(log_dir.join("echo err.log"), "oops\n").unwrap();
+
+        let lines: Vec<String> = manager
+            .logs(label, LogQuery::new().with_lines(1))
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, ["world", "oops"]);
+
+        let err = manager
+            .logs("com.example.other".parse().unwrap(), LogQuery::new())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            Error::from_io_error(&err),
+            Some(Error::NotInstalled(_))
+        ));
+    }
 }
@@ -0,0 +1,221 @@
+use super::{
+    child_pid_path, in_default_runlevel, parse_status, pid_file_path, rc_service_command,
+    rc_update_command, rc_update_show_command, runlevel_link_path, service_dir_path,
+    state_from_output, status_from_output, OpenRcServiceManager, SCRIPT_FILE_PERMISSIONS,
+};
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::utils::{self, nonblocking, wrap_output};
+use crate::{
+    Error, InstallOutcome, InstalledService, ServiceDiff, ServiceDisableCtx, ServiceEnableCtx,
+    ServiceInstallCtx, ServiceLabel, ServiceLevel, ServiceListCtx, ServiceRestartCtx,
+    ServiceRollbackCtx, ServiceStartCtx, ServiceState, ServiceStatus, ServiceStatusCtx,
+    ServiceStatusDetail, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::{ffi::OsStr, io};
+
+impl OpenRcServiceManager {
+    /// Name of the script of the service with `label`, failing if it is not installed
+    async fn installed_script_name_async(&self, label: &ServiceLabel) -> io::Result<String> {
+        let script_name = self.naming.native_name(label);
+        if !nonblocking::exists(&self.script_path(&script_name)).await {
+            return Err(Error::NotInstalled(script_name).into());
+        }
+        Ok(script_name)
+    }
+}
+
+impl AsyncServiceManager for OpenRcServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        Box::pin(async move { crate::ServiceManager::available(self) })
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, &ctx)?;
+            nonblocking::install_rendered(&self.runner, &rendered).await
+        })
+    }
+
+    fn diff<'a>(&'a self, ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, ctx)?;
+            nonblocking::diff_rendered(&rendered.files).await
+        })
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async move {
+            nonblocking::install_or_update_rendered(self, &self.runner, ctx, &[]).await
+        })
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_name = self.naming.native_name(&ctx.label);
+            let script_path = self.script_path(&script_name);
+            if !nonblocking::exists(&script_path).await {
+                return Err(Error::NotInstalled(script_name).into());
+            }
+
+            // If the script is configured to run at boot, remove it
+            match self.config.root.as_deref() {
+                Some(root) => {
+                    let _ = tokio::fs::remove_file(runlevel_link_path(root, &script_name)).await;
+                }
+                None => {
+                    let del = rc_update_command("del", &script_name, [OsStr::new("default")]);
+                    let _ = nonblocking::output(&self.runner, &del)
+                        .await
+                        .map(wrap_output);
+                }
+            }
+
+            // Uninstall service by removing the script
+            nonblocking::remove_definition(&script_path).await
+        })
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+            let command = rc_service_command("start", &self.naming.native_name(&ctx.label), []);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+            let command = rc_service_command("stop", &self.naming.native_name(&ctx.label), []);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+            let command = rc_service_command("restart", &self.naming.native_name(&ctx.label), []);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_name = self.installed_script_name_async(&ctx.label).await?;
+            let command = self.runlevel_command(&script_name, true);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_name = self.installed_script_name_async(&ctx.label).await?;
+            let command = self.runlevel_command(&script_name, false);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            let script_name = self.installed_script_name_async(&ctx.label).await?;
+            if let Some(root) = self.config.root.as_deref() {
+                let link_path = runlevel_link_path(root, &script_name);
+                return Ok(tokio::fs::symlink_metadata(link_path).await.is_ok());
+            }
+
+            let command = rc_update_show_command();
+            let output = wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(in_default_runlevel(
+                &String::from_utf8_lossy(&output.stdout),
+                &script_name,
+            ))
+        })
+    }
+
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_name = self.installed_script_name_async(&ctx.label).await?;
+            let script_path = self.script_path(&script_name);
+            nonblocking::restore_backup(&script_path, &script_name, SCRIPT_FILE_PERMISSIONS).await
+        })
+    }
+
+    fn level(&self) -> ServiceLevel {
+        crate::ServiceManager::level(self)
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        crate::ServiceManager::set_level(self, level)
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        Box::pin(async move {
+            let script_name = self.naming.native_name(&ctx.label);
+            if self.config.root.is_some() {
+                let script_path = self.script_path(&script_name);
+                return Ok(nonblocking::offline_status(&script_path).await);
+            }
+
+            let command = rc_service_command("status", &script_name, []);
+            status_from_output(nonblocking::output(&self.runner, &command).await?)
+        })
+    }
+
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        Box::pin(async move {
+            if self.config.root.is_some() {
+                return Ok(self.status(ctx).await?.into());
+            }
+
+            let script_name = self.naming.native_name(&ctx.label);
+            let command = rc_service_command("status", &script_name, []);
+            let output = nonblocking::output(&self.runner, &command).await?;
+            let native_state = parse_status(&String::from_utf8_lossy(&output.stdout));
+            let state = state_from_output(output, native_state.as_deref())?;
+
+            let mut pid = None;
+            if state == ServiceState::Running {
+                for path in [child_pid_path(&script_name), pid_file_path(&script_name)] {
+                    pid = tokio::fs::read_to_string(path)
+                        .await
+                        .ok()
+                        .and_then(|pid| pid.trim().parse().ok());
+                    if pid.is_some() {
+                        break;
+                    }
+                }
+            }
+
+            Ok(ServiceStatusDetail {
+                pid,
+                native_state,
+                ..ServiceStatusDetail::new(state)
+            })
+        })
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async move {
+            let dir_path = utils::rooted(self.config.root.as_deref(), service_dir_path());
+            let script_names = nonblocking::list_dir_names(&dir_path, false).await?;
+            let scripts = utils::script_definitions(&dir_path, script_names);
+
+            let mut services = Vec::new();
+            for label in nonblocking::definition_labels(scripts, &self.naming, &ctx).await {
+                let status = self
+                    .status(ServiceStatusCtx {
+                        label: label.clone(),
+                    })
+                    .await?;
+                services.push(InstalledService { label, status });
+            }
+
+            Ok(services)
+        })
+    }
+}
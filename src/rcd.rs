@@ -1,28 +1,59 @@
 use super::{
-    utils, ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    dependency::{unsupported_dependency, validate_dependency_name},
+    limits::unsupported_limit,
+    logs, naming, utils, CommandRunner, DependencyKind, Error, InstallOutcome, LogLines, LogQuery,
+    RenderedFile, RenderedService, ResourceLimits, ServiceCommand, ServiceDependency,
+    ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx, ServiceLabel, ServiceLevel,
+    ServiceManager, ServiceNaming, ServiceOutput, ServiceRestartCtx, ServiceRollbackCtx,
+    ServiceStartCtx, ServiceStopCtx, ServiceUninstallCtx, SharedCommandRunner,
 };
 use std::{
-    ffi::{OsStr, OsString},
     io,
-    path::PathBuf,
-    process::{Command, ExitStatus, Stdio},
+    path::{Path, PathBuf},
+    process::ExitStatus,
 };
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 static SERVICE: &str = "service";
+static SYSRC: &str = "sysrc";
 
 // NOTE: On FreeBSD, /usr/local/etc/rc.d/{script} has permissions of rwxr-xr-x (755)
 const SCRIPT_FILE_PERMISSIONS: u32 = 0o755;
 
+/// Maximum length of a script name, as limited by the file system
+const MAX_SCRIPT_NAME_LEN: usize = 255;
+
+/// Seconds that daemon(8) waits before restarting a service that exited
+const RESTART_DELAY_SECS: u32 = 5;
+
 /// Configuration settings tied to rc.d services
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
-pub struct RcdConfig {}
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RcdConfig {
+    /// Optional directory to use as the root of the filesystem when locating scripts
+    ///
+    /// Services installed into a root are enabled through `sysrc -R`, while starting and
+    /// stopping them is unsupported.
+    pub root: Option<PathBuf>,
+}
 
 /// Implementation of [`ServiceManager`] for FreeBSD's [rc.d](https://en.wikipedia.org/wiki/Init#Research_Unix-style/BSD-style)
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct RcdServiceManager {
     /// Configuration settings tied to rc.d services
     pub config: RcdConfig,
+
+    /// Strategy deriving the name of the script of a service from its label
+    pub naming: ServiceNaming,
+
+    /// Runner used to execute the commands of the service manager
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub runner: SharedCommandRunner,
 }
 
 impl RcdServiceManager {
@@ -33,13 +64,60 @@ impl RcdServiceManager {
 
     /// Update manager to use the specified config
     pub fn with_config(self, config: RcdConfig) -> Self {
-        Self { config }
+        Self { config, ..self }
+    }
+
+    /// Update manager to name scripts using the specified strategy
+    pub fn with_naming(self, naming: ServiceNaming) -> Self {
+        Self { naming, ..self }
+    }
+
+    /// Update manager to execute commands using the specified runner
+    pub fn with_runner(self, runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: SharedCommandRunner::new(runner),
+            ..self
+        }
+    }
+
+    fn dir_path(&self) -> PathBuf {
+        utils::rooted(self.config.root.as_deref(), service_dir_path())
+    }
+
+    fn script_path(&self, name: &str) -> PathBuf {
+        self.dir_path().join(name)
+    }
+
+    /// Name of the script of the service with `label`, failing if it is not installed
+    fn installed_script_name(&self, label: &ServiceLabel) -> io::Result<String> {
+        let service = self.naming.native_name(label);
+        if !self.script_path(&service).exists() {
+            return Err(Error::NotInstalled(service).into());
+        }
+        Ok(service)
+    }
+
+    /// Produces the `sysrc` command setting the rc.conf variable that enables `service`
+    fn set_rcvar_command(&self, service: &str, enabled: bool) -> ServiceCommand {
+        let value = if enabled { "YES" } else { "NO" };
+        sysrc_command(
+            self.config.root.as_deref(),
+            [format!("{}={value}", rcvar(service))],
+        )
+    }
+
+    /// Produces the `sysrc` command printing the rc.conf variable that enables `service`
+    fn get_rcvar_command(&self, service: &str) -> ServiceCommand {
+        sysrc_command(
+            self.config.root.as_deref(),
+            ["-n".to_string(), rcvar(service)],
+        )
     }
 }
 
 impl ServiceManager for RcdServiceManager {
     fn available(&self) -> io::Result<bool> {
-        match std::fs::metadata(service_dir_path()) {
+        match std::fs::metadata(self.dir_path()) {
             Ok(_) => Ok(true),
             Err(x) if x.kind() == io::ErrorKind::NotFound => Ok(false),
             Err(x) => Err(x),
@@ -47,47 +125,148 @@ impl ServiceManager for RcdServiceManager {
     }
 
     fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
-        let service = ctx.label.to_script_name();
-        let script = match ctx.contents {
-            Some(contents) => contents,
-            _ => make_script(&service, &service, ctx.program.as_os_str(), ctx.args),
-        };
+        let rendered = self.render(&ctx)?;
+        utils::write_rendered_files(&rendered.files)?;
+        for command in &rendered.commands {
+            run_rc_d_command(&self.runner, command, true)?;
+        }
 
-        utils::write_file(
-            &rc_d_script_path(&service),
-            script.as_bytes(),
-            SCRIPT_FILE_PERMISSIONS,
-        )?;
+        Ok(())
+    }
+
+    /// Rewrites a changed script, restarting the service only if it was running. rc.d reads
+    /// scripts whenever it runs them, so there is nothing to reload.
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        utils::install_or_update_rendered(self, &self.runner, ctx, &[])
+    }
+
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        let service = self.naming.native_name(&ctx.label);
+        naming::validate_native_name(&service, MAX_SCRIPT_NAME_LEN, "rc.d")?;
+        naming::validate_rcd_name(&service)?;
+        utils::ensure_no_conflict(&self.script_path(&service), &service, &ctx.label)?;
+
+        let script = match &ctx.contents {
+            Some(contents) => contents.clone().into_bytes(),
+            _ => utils::mark_label_bytes(
+                make_script(&service, &service, ctx)?,
+                &ctx.label,
+                utils::MarkerStyle::Hash,
+            ),
+        };
 
+        let mut commands = Vec::new();
         if ctx.autostart {
-            rc_d_script("enable", &service, true)?;
+            commands.push(match self.config.root.as_deref() {
+                Some(_) => self.set_rcvar_command(&service, true),
+                None => rc_d_script_command("enable", &service),
+            });
         }
 
-        Ok(())
+        Ok(RenderedService {
+            files: vec![RenderedFile {
+                path: self.script_path(&service),
+                contents: script,
+                mode: SCRIPT_FILE_PERMISSIONS,
+            }],
+            commands,
+        })
     }
 
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
-        let service = ctx.label.to_script_name();
+        let service = self.naming.native_name(&ctx.label);
+        if !self.script_path(&service).exists() {
+            return Err(Error::NotInstalled(service).into());
+        }
 
         // Remove the service from rc.conf
-        rc_d_script("delete", &service, true)?;
+        match self.config.root.as_deref() {
+            // The variable is only present if the service was enabled, so its absence is fine
+            Some(root) => {
+                let command = sysrc_command(Some(root), ["-x".to_string(), rcvar(&service)]);
+                run_rc_d_command(&self.runner, &command, false)?;
+            }
+            None => {
+                rc_d_script(&self.runner, "delete", &service, true)?;
+            }
+        }
 
         // Delete the actual service file
-        std::fs::remove_file(rc_d_script_path(&service))
+        utils::remove_definition(&self.script_path(&service))
     }
 
     fn start(&self, ctx: ServiceStartCtx) -> io::Result<()> {
-        let service = ctx.label.to_script_name();
-        rc_d_script("start", &service, true)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+        let service = self.naming.native_name(&ctx.label);
+        rc_d_script(&self.runner, "start", &service, true)?;
         Ok(())
     }
 
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()> {
-        let service = ctx.label.to_script_name();
-        rc_d_script("stop", &service, true)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+        let service = self.naming.native_name(&ctx.label);
+        rc_d_script(&self.runner, "stop", &service, true)?;
         Ok(())
     }
 
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+        let service = self.naming.native_name(&ctx.label);
+        rc_d_script(&self.runner, "restart", &service, true)?;
+        Ok(())
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> io::Result<()> {
+        let service = self.installed_script_name(&ctx.label)?;
+        run_rc_d_command(&self.runner, &self.set_rcvar_command(&service, true), true)?;
+        Ok(())
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> io::Result<()> {
+        let service = self.installed_script_name(&ctx.label)?;
+        run_rc_d_command(&self.runner, &self.set_rcvar_command(&service, false), true)?;
+        Ok(())
+    }
+
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        let service = self.installed_script_name(&ctx.label)?;
+        let output = utils::output(&self.runner, &self.get_rcvar_command(&service))?;
+        Ok(enabled_from_sysrc(&output))
+    }
+
+    /// Restores the previous script, which rc.d reads whenever it runs it
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> io::Result<()> {
+        let service = self.installed_script_name(&ctx.label)?;
+        utils::restore_backup(
+            &self.script_path(&service),
+            &service,
+            SCRIPT_FILE_PERMISSIONS,
+        )
+    }
+
+    /// Reads the file named by the `output_file` variable of the script, which `daemon`
+    /// redirects the output of the service to
+    fn logs(&self, label: ServiceLabel, query: LogQuery) -> io::Result<LogLines> {
+        let service = self.installed_script_name(&label)?;
+        let script = std::fs::read(self.script_path(&service))?;
+        let script = String::from_utf8_lossy(&script);
+
+        let root = self.config.root.as_deref();
+        let paths: Vec<PathBuf> = ["output_file"]
+            .into_iter()
+            .filter_map(|name| utils::script_variable(&script, name))
+            .map(|path| utils::rooted(root, PathBuf::from(path)))
+            .collect();
+        if paths.is_empty() {
+            return Err(Error::Unsupported(format!(
+                "Service {service} does not set output_file, so its output is only sent to \
+                 syslog"
+            ))
+            .into());
+        }
+        logs::tail_files(paths, &query)
+    }
+
     fn level(&self) -> ServiceLevel {
         ServiceLevel::System
     }
@@ -95,32 +274,39 @@ impl ServiceManager for RcdServiceManager {
     fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
         match level {
             ServiceLevel::System => Ok(()),
-            ServiceLevel::User => Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "rc.d does not support user-level services",
-            )),
+            ServiceLevel::User => Err(Error::Unsupported(
+                "rc.d does not support user-level services".to_string(),
+            )
+            .into()),
         }
     }
 
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<crate::ServiceStatus> {
-        let service = ctx.label.to_script_name();
-        let status = rc_d_script("status", &service, false)?;
-        match status.code() {
-            Some(0) => Ok(crate::ServiceStatus::Running),
-            Some(3) => Ok(crate::ServiceStatus::Stopped(None)),
-            Some(1) => Ok(crate::ServiceStatus::NotInstalled),
-            _ => {
-                let code = status.code().unwrap_or(-1);
-                let msg = format!("Failed to get status of {service}, exit code: {code}");
-                Err(io::Error::new(io::ErrorKind::Other, msg))
-            }
+        let service = self.naming.native_name(&ctx.label);
+        if self.config.root.is_some() {
+            return Ok(utils::offline_status(&self.script_path(&service)));
         }
+
+        let command = rc_d_script_command("status", &service);
+        let status = run_rc_d_command(&self.runner, &command, false)?;
+        status_from_exit_status(&command, status)
     }
-}
 
-#[inline]
-fn rc_d_script_path(name: &str) -> PathBuf {
-    service_dir_path().join(name)
+    fn list(&self, ctx: crate::ServiceListCtx) -> io::Result<Vec<crate::InstalledService>> {
+        let dir_path = self.dir_path();
+        let scripts =
+            utils::script_definitions(&dir_path, utils::list_dir_names(&dir_path, false)?);
+
+        let mut services = Vec::new();
+        for label in utils::definition_labels(scripts, &self.naming, &ctx) {
+            let status = self.status(crate::ServiceStatusCtx {
+                label: label.clone(),
+            })?;
+            services.push(crate::InstalledService { label, status });
+        }
+
+        Ok(services)
+    }
 }
 
 #[inline]
@@ -128,63 +314,643 @@ fn service_dir_path() -> PathBuf {
     PathBuf::from("/usr/local/etc/rc.d")
 }
 
-fn rc_d_script(cmd: &str, service: &str, wrap: bool) -> io::Result<ExitStatus> {
-    // NOTE: We MUST mark stdout/stderr as null, otherwise this hangs. Attempting to use output()
+fn rc_d_script(
+    runner: &dyn CommandRunner,
+    cmd: &str,
+    service: &str,
+    wrap: bool,
+) -> io::Result<ExitStatus> {
+    run_rc_d_command(runner, &rc_d_script_command(cmd, service), wrap)
+}
+
+fn rc_d_script_command(cmd: &str, service: &str) -> ServiceCommand {
+    ServiceCommand::new(SERVICE).arg(service).arg(cmd)
+}
+
+/// Name of the variable in rc.conf that enables the service with the script `name`
+fn rcvar(name: &str) -> String {
+    format!("{}_enable", name.replace('-', "_"))
+}
+
+/// Produces a `sysrc` command editing the rc.conf, within `root` if there is one
+fn sysrc_command(root: Option<&Path>, args: impl IntoIterator<Item = String>) -> ServiceCommand {
+    let command = ServiceCommand::new(SYSRC);
+    match root {
+        Some(root) => command.arg("-R").arg(root).args(args),
+        None => command.args(args),
+    }
+}
+
+/// Interprets the output of `sysrc -n {name}_enable`, which fails if the variable is not set
+fn enabled_from_sysrc(output: &utils::CommandOutput) -> bool {
+    // rc.subr's checkyesno accepts these values in any case
+    let value = String::from_utf8_lossy(&output.stdout);
+    output.status.success()
+        && matches!(
+            value.trim().to_ascii_uppercase().as_str(),
+            "YES" | "TRUE" | "ON" | "1"
+        )
+}
+
+fn run_rc_d_command(
+    runner: &dyn CommandRunner,
+    command: &ServiceCommand,
+    wrap: bool,
+) -> io::Result<ExitStatus> {
+    // NOTE: We MUST NOT capture stdout/stderr, otherwise this hangs. Attempting to use output()
     //       does not work. The alternative is to spawn threads to read the stdout and stderr,
     //       but that seems overkill for the purpose of displaying an error message.
-    let status = Command::new(SERVICE)
-        .stdin(Stdio::null())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .arg(service)
-        .arg(cmd)
-        .status()?;
+    let status = runner
+        .status(command)
+        .map_err(|x| utils::tool_not_found(command, x))?;
+    check_rc_d_status(command, status, wrap)
+}
+
+/// Fails with the exit `status` of `command` if it did not succeed and `wrap` is true
+fn check_rc_d_status(
+    command: &ServiceCommand,
+    status: ExitStatus,
+    wrap: bool,
+) -> io::Result<ExitStatus> {
     if wrap {
         if status.success() {
             Ok(status)
         } else {
-            let msg = format!("Failed to {cmd} {service}");
-            Err(io::Error::new(io::ErrorKind::Other, msg))
+            Err(rc_d_command_failed(command, status).into())
         }
     } else {
         Ok(status)
     }
 }
 
-fn make_script(description: &str, provide: &str, program: &OsStr, args: Vec<OsString>) -> String {
+/// Interprets the exit status of `service {name} status`
+fn status_from_exit_status(
+    command: &ServiceCommand,
+    status: ExitStatus,
+) -> io::Result<crate::ServiceStatus> {
+    match status.code() {
+        Some(0) => Ok(crate::ServiceStatus::Running),
+        Some(3) => Ok(crate::ServiceStatus::Stopped(None)),
+        Some(1) => Ok(crate::ServiceStatus::NotInstalled),
+        _ => Err(rc_d_command_failed(command, status).into()),
+    }
+}
+
+/// Describes a failed invocation of [`run_rc_d_command`], whose output is never captured
+fn rc_d_command_failed(command: &ServiceCommand, status: ExitStatus) -> Error {
+    Error::CommandFailed {
+        program: command.program.to_string_lossy().into_owned(),
+        args: command
+            .args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        exit_code: status.code(),
+        stdout: String::new(),
+        stderr: String::new(),
+    }
+}
+
+/// Destination of the output of a service run by `daemon`
+#[derive(Debug, PartialEq, Eq)]
+enum DaemonOutput<'a> {
+    File(&'a Path),
+    Syslog(&'a str),
+}
+
+/// Produces the variables of the script and the `daemon` flags sending the streams of a service
+/// to `stdout` and `stderr`, where inherited streams go to syslog tagged with the name of the
+/// service
+///
+/// `daemon` appends both streams to a single file or sends them to syslog with a single tag,
+/// selecting the streams with `-m` and discarding the rest, so streams sent to different places
+/// are unsupported. The flags are written within the double quotes of `command_args`, which
+/// rc.subr evaluates again, so the path of a file is quoted as a word and escaped for the quotes.
+fn daemon_output(stdout: &ServiceOutput, stderr: &ServiceOutput) -> io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut mask = 0;
+    let mut target = None;
+    for (bit, output) in [(1, stdout), (2, stderr)] {
+        let output = match output {
+            ServiceOutput::Null => continue,
+            ServiceOutput::Inherit => DaemonOutput::Syslog("${name}"),
+            ServiceOutput::File(path) | ServiceOutput::Append(path) => DaemonOutput::File(path),
+            ServiceOutput::Syslog { identifier } => {
+                if let Some(identifier) = identifier {
+                    logs::validate_syslog_identifier("rc.d", identifier)?;
+                }
+                DaemonOutput::Syslog(identifier.as_deref().unwrap_or("${name}"))
+            }
+        };
+
+        match &target {
+            Some(target) if *target != output => {
+                return Err(Error::Unsupported(
+                    "daemon is unable to send stdout and stderr to different places".to_string(),
+                )
+                .into())
+            }
+            _ => target = Some(output),
+        }
+        mask |= bit;
+    }
+
+    let (vars, mut flags) = match target {
+        None => return Ok((Vec::new(), Vec::new())),
+        Some(DaemonOutput::File(path)) => {
+            let path = utils::os_str_bytes(path.as_os_str(), "The log file")?;
+            let mut vars = b"output_file=".to_vec();
+            vars.extend(utils::quote_shell_string(&path));
+            vars.push(b'\n');
+            let word = utils::quote_shell_string(&utils::quote_shell_word(&path)?);
+            let mut flags = b"-o ".to_vec();
+            flags.extend_from_slice(&word[1..word.len() - 1]);
+            flags.push(b' ');
+            (vars, flags)
+        }
+        Some(DaemonOutput::Syslog(tag)) => (Vec::new(), format!("-S -T {tag} ").into_bytes()),
+    };
+    if mask != 3 {
+        flags.extend_from_slice(format!("-m {mask} ").as_bytes());
+    }
+    Ok((vars, flags))
+}
+
+/// Produces the `# REQUIRE:` and `# BEFORE:` lines ordering the script among the others, after
+/// the network is up if `requires_network`
+///
+/// `rcorder` only orders scripts, so required and wanted services are started first without
+/// being needed to start this one. Conflicts cannot be expressed.
+fn order_lines(dependencies: &[ServiceDependency], requires_network: bool) -> io::Result<String> {
+    let mut require = vec!["LOGIN", "FILESYSTEMS"];
+    if requires_network {
+        require.push("NETWORKING");
+    }
+    let mut before = Vec::new();
+    for dependency in dependencies {
+        validate_dependency_name("rc.d", &dependency.service)?;
+        let service = dependency.service.as_str();
+        match dependency.kind {
+            DependencyKind::Requires | DependencyKind::Wants | DependencyKind::After => {
+                if !require.contains(&service) {
+                    require.push(service);
+                }
+            }
+            DependencyKind::Before => {
+                if !before.contains(&service) {
+                    before.push(service);
+                }
+            }
+            DependencyKind::Conflicts => {
+                return Err(unsupported_dependency("rc.d", DependencyKind::Conflicts))
+            }
+        }
+    }
+
+    let mut lines = format!("# REQUIRE: {}\n", require.join(" "));
+    if !before.is_empty() {
+        lines.push_str(&format!("# BEFORE: {}\n", before.join(" ")));
+    }
+    Ok(lines)
+}
+
+/// Produces the flags of `limits` setting `limits`, which `rc.subr` applies on top of the limits
+/// of the login class of the service when it is given as `${name}_limits`
+///
+/// Memory is limited through the virtual memory size. A CPU quota needs `rctl` and is
+/// unsupported.
+fn limits_flags(limits: &ResourceLimits) -> io::Result<String> {
+    if limits.cpu_quota.is_some() {
+        return Err(unsupported_limit("rc.d", "the CPU quota of a service"));
+    }
+
+    let flags: Vec<String> = [
+        ("-v", limits.memory_max),
+        ("-n", limits.nofile),
+        ("-u", limits.nproc),
+        ("-c", limits.core_size),
+    ]
+    .into_iter()
+    .filter_map(|(flag, limit)| Some(format!("{flag} {}", limit?)))
+    .collect();
+    Ok(flags.join(" "))
+}
+
+/// Produces the script of the service described by `ctx`
+///
+/// Unless restarting on failure is disabled, daemon(8) supervises the service and restarts it
+/// whenever it exits. `rc.subr` then stops the service through daemon(8), so the pid file holds
+/// the pid of daemon(8) and `procname` is left to match it.
+///
+/// The script is made of bytes rather than text, as the shell passes the bytes of arguments
+/// that are not valid UTF-8 through as they are.
+fn make_script(description: &str, provide: &str, ctx: &ServiceInstallCtx) -> io::Result<Vec<u8>> {
+    use std::io::Write as _;
     let name = provide.replace('-', "_");
-    let program = program.to_string_lossy();
-    let args = args
-        .into_iter()
-        .map(|a| a.to_string_lossy().to_string())
-        .collect::<Vec<String>>()
-        .join(" ");
-    format!(
-        r#"
-#!/bin/sh
-#
-# PROVIDE: {provide}
-# REQUIRE: LOGIN FILESYSTEMS
-# KEYWORD: shutdown
-
-. /etc/rc.subr
-
-name="{name}"
-desc="{description}"
-rcvar="{name}_enable"
-
-load_rc_config ${{name}}
-
-: ${{{name}_options="{args}"}}
-
-pidfile="/var/run/{name}.pid"
-procname="{program}"
-command="/usr/sbin/daemon"
-command_args="-c -S -T ${{name}} -p ${{pidfile}} ${{procname}} ${{{name}_options}}"
-
-run_rc_command "$1"
-    "#
-    )
-    .trim()
-    .to_string()
+    let order = order_lines(&ctx.dependencies, ctx.requires_network)?;
+    let (output_vars, output_flags) = daemon_output(&ctx.stdout, &ctx.stderr)?;
+    let limits = limits_flags(&ctx.limits)?;
+    let program = utils::os_str_bytes(ctx.program.as_os_str(), "The program")?;
+    if !utils::is_plain_shell_word(&program) {
+        return Err(Error::InvalidDefinition(format!(
+            "rc.d is unable to run {:?}, as it passes the command to the shell unquoted",
+            String::from_utf8_lossy(&program)
+        ))
+        .into());
+    }
+
+    // rc.subr evaluates the command line of daemon(8) when starting the service, along with
+    // the environment and directory, so each word is quoted within the value of the variable
+    let args = ctx
+        .args_iter()
+        .map(|arg| utils::os_str_bytes(arg, "The argument"))
+        .collect::<io::Result<Vec<_>>>()?;
+    let args = utils::quote_shell_words(args.iter().map(AsRef::as_ref))?;
+
+    let env = match &ctx.environment {
+        Some(env_vars) => {
+            let mut words = Vec::new();
+            for (var, val) in env_vars {
+                if !utils::is_valid_env_name(var) {
+                    return Err(Error::InvalidDefinition(format!(
+                        "rc.d is unable to set the environment variable {var:?}, as names may \
+                         only hold letters, digits and underscores and may not start with a \
+                         digit"
+                    ))
+                    .into());
+                }
+                words.push(format!("{var}={val}"));
+            }
+            Some(utils::quote_shell_words(
+                words.iter().map(String::as_bytes),
+            )?)
+        }
+        None => None,
+    };
+    let chdir = match &ctx.working_directory {
+        Some(dir) => Some(utils::quote_shell_word(&utils::os_str_bytes(
+            dir.as_os_str(),
+            "The working directory",
+        )?)?),
+        None => None,
+    };
+
+    // daemon(8) changes to the root directory with -c, unless the service has one of its own.
+    // The command line refers to the other variables, which are expanded as it is assigned.
+    let mut command_args = Vec::new();
+    if chdir.is_none() {
+        let _ = write!(command_args, "-c ");
+    }
+    if let Some(username) = &ctx.username {
+        if !utils::is_plain_shell_word(username.as_bytes()) {
+            return Err(Error::InvalidDefinition(format!(
+                "rc.d is unable to run the service as {username:?}, as it passes the user to \
+                 the shell unquoted"
+            ))
+            .into());
+        }
+        let _ = write!(command_args, "-u {username} ");
+    }
+    command_args.extend(output_flags);
+    if ctx.disable_restart_on_failure {
+        let _ = write!(command_args, "-p ${{pidfile}} ${{procname}}");
+    } else {
+        let _ = write!(command_args, "-R {RESTART_DELAY_SECS} -P ${{pidfile}} ");
+        command_args.extend_from_slice(&program);
+    }
+    let _ = write!(command_args, " ${{{name}_options}}");
+
+    let mut script = Vec::new();
+    let _ = writeln!(script, "#!/bin/sh");
+    let _ = writeln!(script, "#");
+    let _ = writeln!(script, "# PROVIDE: {provide}");
+    let _ = writeln!(script, "{order}# KEYWORD: shutdown");
+    let _ = writeln!(script);
+    let _ = writeln!(script, ". /etc/rc.subr");
+    let _ = writeln!(script);
+    let _ = writeln!(script, "name=\"{name}\"");
+    script.extend_from_slice(b"desc=");
+    script.extend(utils::quote_shell_string(description.as_bytes()));
+    let _ = writeln!(script);
+    let _ = writeln!(script, "rcvar=\"{name}_enable\"");
+    let _ = writeln!(script);
+    let _ = writeln!(script, "load_rc_config ${{name}}");
+    let _ = writeln!(script);
+    let _ = write!(script, ": ${{{name}_options=");
+    script.extend(utils::quote_shell_string(&args));
+    let _ = writeln!(script, "}}");
+    if let Some(env) = env {
+        let _ = write!(script, ": ${{{name}_env=");
+        script.extend(utils::quote_shell_string(&env));
+        let _ = writeln!(script, "}}");
+    }
+    if let Some(chdir) = chdir {
+        let _ = write!(script, ": ${{{name}_chdir=");
+        script.extend(utils::quote_shell_string(&chdir));
+        let _ = writeln!(script, "}}");
+    }
+    if !limits.is_empty() {
+        let _ = writeln!(script, ": ${{{name}_limits=\"{limits}\"}}");
+    }
+    let _ = writeln!(script);
+    let _ = writeln!(script, "pidfile=\"/var/run/{name}.pid\"");
+    script.extend(output_vars);
+    if ctx.disable_restart_on_failure {
+        script.extend_from_slice(b"procname=");
+        script.extend_from_slice(&program);
+        let _ = writeln!(script);
+    }
+    let _ = writeln!(script, "command=\"/usr/sbin/daemon\"");
+    script.extend_from_slice(b"command_args=\"");
+    script.extend(command_args);
+    let _ = writeln!(script, "\"");
+    let _ = writeln!(script);
+    let _ = write!(script, "run_rc_command \"$1\"");
+    Ok(script)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `script` to a file, checks its syntax and sources it with `rc.subr` stubbed out,
+    /// returning the words held by `variable` once evaluated the way `rc.subr` does
+    #[cfg(unix)]
+    fn eval_variable(script: &[u8], variable: &str) -> Vec<Vec<u8>> {
+        let dir = assert_fs::TempDir::new().unwrap();
+        let path = dir.path().join("script");
+        let source = b". /etc/rc.subr".as_slice();
+        let at = script
+            .windows(source.len())
+            .position(|line| line == source)
+            .unwrap();
+        let stubs = b"load_rc_config() { :; }\nrun_rc_command() { :; }".as_slice();
+        let script = [&script[..at], stubs, &script[at + source.len()..]].concat();
+        std::fs::write(&path, script).unwrap();
+        // A file that an argument expanded as a pattern would match
+        std::fs::write(dir.path().join("victim"), "").unwrap();
+
+        let check = std::process::Command::new("sh")
+            .arg("-n")
+            .arg(&path)
+            .status()
+            .unwrap();
+        assert!(check.success());
+
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(r#". "$1"; eval "value=\${$2}"; eval "set -- $value"; printf '%s\0' "$@""#)
+            .arg("sh")
+            .arg(&path)
+            .arg(variable)
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let mut args: Vec<Vec<u8>> = output
+            .stdout
+            .split(|b| *b == 0)
+            .map(<[u8]>::to_vec)
+            .collect();
+        args.pop();
+        args
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_args_survive_the_shell() {
+        let args = [
+            "hello",
+            "two  words",
+            "tab\tand\nline break",
+            "it's",
+            "\"quoted\"",
+            "$HOME",
+            "${PATH}",
+            "$(echo injected)",
+            "`echo injected`",
+            "back\\slash",
+            "*",
+            "a;b && c | d > e",
+            "~",
+            "",
+            "100%",
+        ];
+        let mut args: Vec<Vec<u8>> = args.iter().map(|arg| arg.as_bytes().to_vec()).collect();
+        // The shell passes bytes that are not valid UTF-8 through as they are
+        args.push(b"caf\xe9".to_vec());
+        let mut ctx =
+            ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "/usr/local/bin/echo");
+        ctx.args = args
+            .iter()
+            .map(|arg| std::os::unix::ffi::OsStringExt::from_vec(arg.clone()))
+            .collect();
+
+        let script = make_script("echo", "echo", &ctx).unwrap();
+        let command_line = eval_variable(&script, "command_args");
+        let (program, passed) = command_line.split_at(command_line.len() - args.len());
+        assert_eq!(program.last().unwrap(), b"/usr/local/bin/echo");
+        assert_eq!(passed, args);
+
+        ctx.args = vec!["nul\0".into()];
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.args = Vec::new();
+        ctx.program = PathBuf::from("/opt/$(reboot)/echo");
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_daemon_output_flags() {
+        let file = ServiceOutput::Append(PathBuf::from("/var/log/echo.log"));
+        let inherit = ServiceOutput::Inherit;
+
+        let (vars, flags) = daemon_output(&inherit, &inherit).unwrap();
+        assert_eq!(
+            (vars.as_slice(), flags.as_slice()),
+            (&b""[..], &b"-S -T ${name} "[..])
+        );
+
+        let (vars, flags) = daemon_output(&file, &file).unwrap();
+        assert_eq!(vars, b"output_file=\"/var/log/echo.log\"\n");
+        assert_eq!(flags, b"-o /var/log/echo.log ");
+
+        let (_, flags) =
+            daemon_output(&ServiceOutput::Null, &ServiceOutput::syslog("echo")).unwrap();
+        assert_eq!(flags, b"-S -T echo -m 2 ");
+
+        let (vars, flags) = daemon_output(&ServiceOutput::Null, &ServiceOutput::Null).unwrap();
+        assert_eq!((vars.as_slice(), flags.as_slice()), (&b""[..], &b""[..]));
+
+        // The path is evaluated along with the rest of command_args
+        let hostile = ServiceOutput::File(PathBuf::from("/var/log/$(reboot) it's.log"));
+        let (vars, flags) = daemon_output(&hostile, &ServiceOutput::Null).unwrap();
+        assert_eq!(vars, b"output_file=\"/var/log/\\$(reboot) it's.log\"\n");
+        assert_eq!(flags, br"-o '/var/log/\$(reboot) it'\\''s.log' -m 1 ");
+
+        let err = daemon_output(&file, &inherit).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        for identifier in ["x\"; reboot; \"", "$(reboot)", "line\nbreak"] {
+            let output = ServiceOutput::syslog(identifier);
+            let err = daemon_output(&output, &output).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_script_limits() {
+        let limits = ResourceLimits::new()
+            .with_memory_max(1 << 30)
+            .with_nproc(64)
+            .with_core_size(0);
+        let ctx =
+            ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/usr/local/bin/echo")
+                .limits(limits)
+                .build();
+        let script = String::from_utf8(make_script("echo", "example-echo", &ctx).unwrap()).unwrap();
+        assert!(script.contains(": ${example_echo_limits=\"-v 1073741824 -u 64 -c 0\"}\n"));
+
+        let limits = ResourceLimits::new().with_cpu_quota(50);
+        let err = limits_flags(&limits).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[test]
+    fn test_script_order() {
+        let ctx = ServiceInstallCtx::builder(
+            "com.example.consumer".parse().unwrap(),
+            "/usr/local/bin/consumer",
+        )
+        .dependency(ServiceDependency::requires("rabbitmq"))
+        .dependency(ServiceDependency::after("rabbitmq"))
+        .dependency(ServiceDependency::wants("postgresql"))
+        .dependency(ServiceDependency::before("nginx"))
+        .build();
+        let script = String::from_utf8(make_script("consumer", "consumer", &ctx).unwrap()).unwrap();
+        assert!(script.contains(
+            "# PROVIDE: consumer\n\
+             # REQUIRE: LOGIN FILESYSTEMS rabbitmq postgresql\n\
+             # BEFORE: nginx\n\
+             # KEYWORD: shutdown\n"
+        ));
+
+        let err = order_lines(&[ServiceDependency::conflicts("sendmail")], false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        for service in ["sendmail\nreboot", "`reboot`", "a;b"] {
+            let err = order_lines(&[ServiceDependency::before(service)], false).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_script_user_environment_directory_network_and_restart() {
+        let ctx =
+            ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/usr/local/bin/echo")
+                .arg("hello")
+                .username("echo")
+                .env("GREETING", "hello world")
+                .working_directory("/srv/echo")
+                .requires_network(true)
+                .build();
+        let script = String::from_utf8(make_script("echo", "echo", &ctx).unwrap()).unwrap();
+        assert!(script.contains("# REQUIRE: LOGIN FILESYSTEMS NETWORKING\n"));
+        assert!(script.contains(
+            ": ${echo_options=\"hello\"}\n\
+             : ${echo_env=\"'GREETING=hello world'\"}\n\
+             : ${echo_chdir=\"/srv/echo\"}\n"
+        ));
+        assert!(script.contains(
+            "command_args=\"-u echo -S -T ${name} -R 5 -P ${pidfile} /usr/local/bin/echo \
+             ${echo_options}\"\n"
+        ));
+        assert!(!script.contains("procname="));
+
+        let mut ctx = ctx;
+        ctx.working_directory = None;
+        ctx.username = None;
+        ctx.disable_restart_on_failure = true;
+        let script = String::from_utf8(make_script("echo", "echo", &ctx).unwrap()).unwrap();
+        assert!(script.contains(
+            "procname=/usr/local/bin/echo\n\
+             command=\"/usr/sbin/daemon\"\n\
+             command_args=\"-c -S -T ${name} -p ${pidfile} ${procname} ${echo_options}\"\n"
+        ));
+
+        ctx.username = Some("$(reboot)".to_string());
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.username = None;
+        ctx.environment = Some(vec![("NOT-VALID".to_string(), "value".to_string())]);
+        let err = make_script("echo", "echo", &ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_script_environment_and_directory_survive_the_shell() {
+        let ctx =
+            ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "/usr/local/bin/echo")
+                .env("GREETING", "it's $(echo injected)")
+                .env("EMPTY", "")
+                .working_directory("/srv/my app")
+                .stdout(ServiceOutput::File(PathBuf::from(
+                    "/var/log/`id` \"out\".log",
+                )))
+                .stderr(ServiceOutput::File(PathBuf::from(
+                    "/var/log/`id` \"out\".log",
+                )))
+                .build();
+        let script = make_script("echo", "echo", &ctx).unwrap();
+        assert_eq!(
+            eval_variable(&script, "echo_env"),
+            [
+                b"GREETING=it's $(echo injected)".to_vec(),
+                b"EMPTY=".to_vec()
+            ]
+        );
+        assert_eq!(
+            eval_variable(&script, "echo_chdir"),
+            [b"/srv/my app".to_vec()]
+        );
+        let command_line = eval_variable(&script, "command_args");
+        assert_eq!(
+            command_line[..2],
+            [b"-o".to_vec(), b"/var/log/`id` \"out\".log".to_vec()]
+        );
+    }
+
+    #[test]
+    fn test_logs_are_read_from_output_file() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = RcdServiceManager::system().with_config(RcdConfig {
+            root: Some(root.path().to_path_buf()),
+        });
+
+        let mut ctx =
+            ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "/usr/local/bin/echo");
+        ctx.autostart = false;
+        manager.install(ctx.clone()).unwrap();
+        let err = manager
+            .logs(ctx.label.clone(), LogQuery::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        ctx.stdout = ServiceOutput::File(PathBuf::from("/var/log/echo.log"));
+        ctx.stderr = ctx.stdout.clone();
+        manager.install(ctx.clone()).unwrap();
+        std::fs::create_dir_all(root.path().join("var/log")).unwrap();
+        std::fs::write(root.path().join("var/log/echo.log"), "hello\n").unwrap();
+
+        let lines: Vec<String> = manager
+            .logs(ctx.label, LogQuery::new())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, ["hello"]);
+    }
 }
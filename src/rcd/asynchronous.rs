@@ -0,0 +1,200 @@
+use super::{
+    check_rc_d_status, enabled_from_sysrc, rc_d_script_command, rcvar, status_from_exit_status,
+    sysrc_command, RcdServiceManager, SCRIPT_FILE_PERMISSIONS,
+};
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::utils::{self, nonblocking};
+use crate::{
+    CommandRunner, Error, InstallOutcome, InstalledService, ServiceCommand, ServiceDiff,
+    ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx, ServiceLabel, ServiceLevel,
+    ServiceListCtx, ServiceRestartCtx, ServiceRollbackCtx, ServiceStartCtx, ServiceStatus,
+    ServiceStatusCtx, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::{io, process::ExitStatus};
+
+impl RcdServiceManager {
+    /// Name of the script of the service with `label`, failing if it is not installed
+    async fn installed_script_name_async(&self, label: &ServiceLabel) -> io::Result<String> {
+        let service = self.naming.native_name(label);
+        if !nonblocking::exists(&self.script_path(&service)).await {
+            return Err(Error::NotInstalled(service).into());
+        }
+        Ok(service)
+    }
+}
+
+impl AsyncServiceManager for RcdServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            match tokio::fs::metadata(self.dir_path()).await {
+                Ok(_) => Ok(true),
+                Err(x) if x.kind() == io::ErrorKind::NotFound => Ok(false),
+                Err(x) => Err(x),
+            }
+        })
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, &ctx)?;
+            nonblocking::write_rendered_files(&rendered.files).await?;
+            for command in &rendered.commands {
+                run_rc_d_command(&self.runner, command, true).await?;
+            }
+
+            Ok(())
+        })
+    }
+
+    fn diff<'a>(&'a self, ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, ctx)?;
+            nonblocking::diff_rendered(&rendered.files).await
+        })
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async move {
+            nonblocking::install_or_update_rendered(self, &self.runner, ctx, &[]).await
+        })
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service = self.naming.native_name(&ctx.label);
+            if !nonblocking::exists(&self.script_path(&service)).await {
+                return Err(Error::NotInstalled(service).into());
+            }
+
+            // Remove the service from rc.conf. Within a root, the variable is only present if the
+            // service was enabled, so its absence is fine
+            let (command, wrap) = match self.config.root.as_deref() {
+                Some(root) => (
+                    sysrc_command(Some(root), ["-x".to_string(), rcvar(&service)]),
+                    false,
+                ),
+                None => (rc_d_script_command("delete", &service), true),
+            };
+            run_rc_d_command(&self.runner, &command, wrap).await?;
+
+            // Delete the actual service file
+            nonblocking::remove_definition(&self.script_path(&service)).await
+        })
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+            let command = rc_d_script_command("start", &self.naming.native_name(&ctx.label));
+            run_rc_d_command(&self.runner, &command, true).await?;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+            let command = rc_d_script_command("stop", &self.naming.native_name(&ctx.label));
+            run_rc_d_command(&self.runner, &command, true).await?;
+            Ok(())
+        })
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+            let command = rc_d_script_command("restart", &self.naming.native_name(&ctx.label));
+            run_rc_d_command(&self.runner, &command, true).await?;
+            Ok(())
+        })
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service = self.installed_script_name_async(&ctx.label).await?;
+            let command = self.set_rcvar_command(&service, true);
+            run_rc_d_command(&self.runner, &command, true).await?;
+            Ok(())
+        })
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service = self.installed_script_name_async(&ctx.label).await?;
+            let command = self.set_rcvar_command(&service, false);
+            run_rc_d_command(&self.runner, &command, true).await?;
+            Ok(())
+        })
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            let service = self.installed_script_name_async(&ctx.label).await?;
+            let command = self.get_rcvar_command(&service);
+            Ok(enabled_from_sysrc(
+                &nonblocking::output(&self.runner, &command).await?,
+            ))
+        })
+    }
+
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service = self.installed_script_name_async(&ctx.label).await?;
+            let script_path = self.script_path(&service);
+            nonblocking::restore_backup(&script_path, &service, SCRIPT_FILE_PERMISSIONS).await
+        })
+    }
+
+    fn level(&self) -> ServiceLevel {
+        crate::ServiceManager::level(self)
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        crate::ServiceManager::set_level(self, level)
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        Box::pin(async move {
+            let service = self.naming.native_name(&ctx.label);
+            if self.config.root.is_some() {
+                return Ok(nonblocking::offline_status(&self.script_path(&service)).await);
+            }
+
+            let command = rc_d_script_command("status", &service);
+            let status = run_rc_d_command(&self.runner, &command, false).await?;
+            status_from_exit_status(&command, status)
+        })
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async move {
+            let script_names = nonblocking::list_dir_names(&self.dir_path(), false).await?;
+            let scripts = utils::script_definitions(&self.dir_path(), script_names);
+
+            let mut services = Vec::new();
+            for label in nonblocking::definition_labels(scripts, &self.naming, &ctx).await {
+                let status = self
+                    .status(ServiceStatusCtx {
+                        label: label.clone(),
+                    })
+                    .await?;
+                services.push(InstalledService { label, status });
+            }
+
+            Ok(services)
+        })
+    }
+}
+
+/// Runs a command without capturing its stdout and stderr, like its blocking counterpart
+async fn run_rc_d_command(
+    runner: &dyn CommandRunner,
+    command: &ServiceCommand,
+    wrap: bool,
+) -> io::Result<ExitStatus> {
+    let status = runner
+        .status_async(command)
+        .await
+        .map_err(|x| utils::tool_not_found(command, x))?;
+    check_rc_d_status(command, status, wrap)
+}
@@ -0,0 +1,473 @@
+#[cfg(feature = "tokio")]
+use super::asynchronous::ServiceFuture;
+use super::{utils, LogLines, ServiceCommand};
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, BufRead, BufReader, Read},
+    process::{Child, ChildStdout, Command, ExitStatus, Output, Stdio},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+/// Interface used by service managers to run the commands of the underlying platform
+///
+/// Provide an implementation with `with_runner` on a service manager to control how commands
+/// are run, such as with a [`FakeCommandRunner`] in tests.
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    /// Runs the command to completion, capturing its stdout and stderr
+    fn run(&self, command: &ServiceCommand) -> io::Result<Output>;
+
+    /// Runs the command to completion without capturing its stdout and stderr
+    ///
+    /// This is used for commands that may leave background processes holding onto their output,
+    /// such as the scripts of rc.d. By default, this runs the command using
+    /// [`CommandRunner::run`] and discards its output.
+    fn status(&self, command: &ServiceCommand) -> io::Result<ExitStatus> {
+        Ok(self.run(command)?.status)
+    }
+
+    /// Runs the command, returning the lines it prints to stdout as they are printed
+    ///
+    /// This is used for commands that may run indefinitely, such as `journalctl --follow`. A
+    /// failure of the command is reported as the last line. By default, this runs the command to
+    /// completion using [`CommandRunner::run`] and returns the lines of its stdout.
+    fn run_lines(&self, command: &ServiceCommand) -> io::Result<LogLines> {
+        let output = self.run(command)?;
+        let mut lines: Vec<io::Result<String>> = output_lines(&output.stdout).map(Ok).collect();
+        if !output.status.success() {
+            let output = utils::command_output(command, output);
+            lines.push(Err(utils::command_failed(output).into()));
+        }
+        Ok(Box::new(lines.into_iter()))
+    }
+
+    /// Runs the command to completion without blocking the calling thread, capturing its stdout
+    /// and stderr
+    ///
+    /// By default, this runs the command using [`CommandRunner::run`], which is only suitable
+    /// for runners that do not block, such as a [`FakeCommandRunner`].
+    #[cfg(feature = "tokio")]
+    fn run_async<'a>(&'a self, command: &'a ServiceCommand) -> ServiceFuture<'a, Output> {
+        Box::pin(async move { self.run(command) })
+    }
+
+    /// Runs the command to completion without blocking the calling thread or capturing its
+    /// stdout and stderr
+    ///
+    /// By default, this runs the command using [`CommandRunner::status`], which is only suitable
+    /// for runners that do not block, such as a [`FakeCommandRunner`].
+    #[cfg(feature = "tokio")]
+    fn status_async<'a>(&'a self, command: &'a ServiceCommand) -> ServiceFuture<'a, ExitStatus> {
+        Box::pin(async move { self.status(command) })
+    }
+}
+
+/// [`CommandRunner`] that spawns a process for each command, used by default
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProcessCommandRunner;
+
+impl ProcessCommandRunner {
+    fn command(command: &ServiceCommand) -> Command {
+        let mut cmd = Command::new(&command.program);
+        cmd.stdin(Stdio::null()).args(&command.args);
+        if let Some(dir) = &command.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+
+    #[cfg(feature = "tokio")]
+    fn tokio_command(command: &ServiceCommand) -> tokio::process::Command {
+        let mut cmd = tokio::process::Command::new(&command.program);
+        cmd.stdin(Stdio::null()).args(&command.args);
+        if let Some(dir) = &command.current_dir {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+}
+
+impl CommandRunner for ProcessCommandRunner {
+    fn run(&self, command: &ServiceCommand) -> io::Result<Output> {
+        Self::command(command)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+    }
+
+    fn status(&self, command: &ServiceCommand) -> io::Result<ExitStatus> {
+        Self::command(command)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+    }
+
+    fn run_lines(&self, command: &ServiceCommand) -> io::Result<LogLines> {
+        let mut child = Self::command(command)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // Stderr is drained on its own thread so a chatty command cannot block on a full pipe
+        // while stdout is being read
+        let stderr = child.stderr.take().map(|mut stderr| {
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = stderr.read_to_end(&mut buf);
+                buf
+            })
+        });
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+
+        Ok(Box::new(ProcessLines {
+            command: command.clone(),
+            child,
+            stdout,
+            stderr,
+            done: false,
+        }))
+    }
+
+    #[cfg(feature = "tokio")]
+    fn run_async<'a>(&'a self, command: &'a ServiceCommand) -> ServiceFuture<'a, Output> {
+        let mut cmd = Self::tokio_command(command);
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        Box::pin(async move { cmd.output().await })
+    }
+
+    #[cfg(feature = "tokio")]
+    fn status_async<'a>(&'a self, command: &'a ServiceCommand) -> ServiceFuture<'a, ExitStatus> {
+        let mut cmd = Self::tokio_command(command);
+        cmd.stdout(Stdio::null()).stderr(Stdio::null());
+        Box::pin(async move { cmd.status().await })
+    }
+}
+
+/// Lines printed to stdout by a process spawned with [`ProcessCommandRunner::run_lines`], which
+/// is killed if the lines are dropped before it exits
+struct ProcessLines {
+    command: ServiceCommand,
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    done: bool,
+}
+
+impl ProcessLines {
+    /// Waits for the process to exit once its stdout is closed, reporting a failure as an error
+    fn finish(&mut self) -> Option<io::Result<String>> {
+        self.done = true;
+        let status = match self.child.wait() {
+            Ok(status) => status,
+            Err(x) => return Some(Err(x)),
+        };
+        if status.success() {
+            return None;
+        }
+
+        let stderr = self
+            .stderr
+            .take()
+            .and_then(|handle| handle.join().ok())
+            .unwrap_or_default();
+        let output = Output {
+            status,
+            stdout: Vec::new(),
+            stderr,
+        };
+        let output = utils::command_output(&self.command, output);
+        Some(Err(utils::command_failed(output).into()))
+    }
+}
+
+impl Iterator for ProcessLines {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let mut line = Vec::new();
+        match self.stdout.read_until(b'\n', &mut line) {
+            Ok(0) => self.finish(),
+            Ok(_) => Some(Ok(decode_line(&line))),
+            Err(x) => {
+                self.done = true;
+                Some(Err(x))
+            }
+        }
+    }
+}
+
+impl Drop for ProcessLines {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+/// Splits output into lines, without their line endings
+fn output_lines(output: &[u8]) -> impl Iterator<Item = String> + '_ {
+    output.split_inclusive(|b| *b == b'\n').map(decode_line)
+}
+
+/// Decodes a line of output, dropping its line ending and replacing anything that is not valid
+/// unicode
+fn decode_line(line: &[u8]) -> String {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    String::from_utf8_lossy(line).into_owned()
+}
+
+/// [`CommandRunner`] held by a service manager, defaulting to a [`ProcessCommandRunner`]
+///
+/// Two instances are equal when they share the same runner.
+#[derive(Clone, Debug, Default)]
+pub struct SharedCommandRunner(Option<Arc<dyn CommandRunner>>);
+
+impl SharedCommandRunner {
+    /// Wraps the runner so it can be shared between service managers
+    pub fn new(runner: impl CommandRunner + 'static) -> Self {
+        Self(Some(Arc::new(runner)))
+    }
+}
+
+impl CommandRunner for SharedCommandRunner {
+    fn run(&self, command: &ServiceCommand) -> io::Result<Output> {
+        match &self.0 {
+            Some(runner) => runner.run(command),
+            None => ProcessCommandRunner.run(command),
+        }
+    }
+
+    fn status(&self, command: &ServiceCommand) -> io::Result<ExitStatus> {
+        match &self.0 {
+            Some(runner) => runner.status(command),
+            None => ProcessCommandRunner.status(command),
+        }
+    }
+
+    fn run_lines(&self, command: &ServiceCommand) -> io::Result<LogLines> {
+        match &self.0 {
+            Some(runner) => runner.run_lines(command),
+            None => ProcessCommandRunner.run_lines(command),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn run_async<'a>(&'a self, command: &'a ServiceCommand) -> ServiceFuture<'a, Output> {
+        match &self.0 {
+            Some(runner) => runner.run_async(command),
+            None => ProcessCommandRunner.run_async(command),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    fn status_async<'a>(&'a self, command: &'a ServiceCommand) -> ServiceFuture<'a, ExitStatus> {
+        match &self.0 {
+            Some(runner) => runner.status_async(command),
+            None => ProcessCommandRunner.status_async(command),
+        }
+    }
+}
+
+impl PartialEq for SharedCommandRunner {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => {
+                std::ptr::eq(Arc::as_ptr(a) as *const (), Arc::as_ptr(b) as *const ())
+            }
+            (None, None) => true,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for SharedCommandRunner {}
+
+/// [`CommandRunner`] that records the commands it is asked to run instead of running them,
+/// answering each with a scripted result
+///
+/// Results are returned in the order they were pushed. Once they run out, every command
+/// succeeds with no output. Clones share their recorded commands and scripted results, so a
+/// clone can be handed to a service manager while the original is inspected.
+///
+/// ```
+/// use service_manager::*;
+///
+/// let runner = FakeCommandRunner::new();
+/// runner.push_output(3, "", "");
+///
+/// let manager = SystemdServiceManager::system().with_runner(runner.clone());
+/// let label = "com.example.echo".parse().unwrap();
+/// let status = manager.status(ServiceStatusCtx::new(label)).unwrap();
+///
+/// assert_eq!(status, ServiceStatus::Stopped(None));
+/// assert_eq!(runner.commands()[0].to_string(), "systemctl status example-echo");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct FakeCommandRunner {
+    inner: Arc<Mutex<FakeCommandRunnerState>>,
+}
+
+#[derive(Debug, Default)]
+struct FakeCommandRunnerState {
+    commands: Vec<ServiceCommand>,
+    results: VecDeque<io::Result<Output>>,
+}
+
+impl FakeCommandRunner {
+    /// Creates a new runner without any scripted results
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scripts the next command to exit with `exit_code`, having printed `stdout` and `stderr`
+    pub fn push_output(
+        &self,
+        exit_code: i32,
+        stdout: impl Into<Vec<u8>>,
+        stderr: impl Into<Vec<u8>>,
+    ) -> &Self {
+        let output = Output {
+            status: exit_status(exit_code),
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+        };
+        self.lock().results.push_back(Ok(output));
+        self
+    }
+
+    /// Scripts the next command to fail to run with `err`
+    ///
+    /// E.g. an error of kind [`io::ErrorKind::NotFound`] acts as if the program is missing.
+    pub fn push_error(&self, err: io::Error) -> &Self {
+        self.lock().results.push_back(Err(err));
+        self
+    }
+
+    /// Returns the commands run so far, in the order they were run
+    pub fn commands(&self) -> Vec<ServiceCommand> {
+        self.lock().commands.clone()
+    }
+
+    /// Forgets the commands run so far, keeping any remaining scripted results
+    pub fn clear_commands(&self) {
+        self.lock().commands.clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, FakeCommandRunnerState> {
+        // A panic while holding the lock cannot leave the state inconsistent
+        self.inner.lock().unwrap_or_else(|x| x.into_inner())
+    }
+}
+
+impl CommandRunner for FakeCommandRunner {
+    fn run(&self, command: &ServiceCommand) -> io::Result<Output> {
+        let mut state = self.lock();
+        state.commands.push(command.clone());
+        state.results.pop_front().unwrap_or_else(|| {
+            Ok(Output {
+                status: exit_status(0),
+                stdout: Vec::new(),
+                stderr: Vec::new(),
+            })
+        })
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+
+    // The raw status holds the exit code in its second byte, as returned by `waitpid`
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fake_runner_records_commands_and_scripts_results() {
+        let runner = FakeCommandRunner::new();
+        runner
+            .push_output(3, "out", "err")
+            .push_error(io::Error::new(io::ErrorKind::NotFound, "missing"));
+
+        let shared = SharedCommandRunner::new(runner.clone());
+        let command = ServiceCommand::new("systemctl").arg("status");
+
+        let output = shared.run(&command).unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out");
+        assert_eq!(output.stderr, b"err");
+
+        let err = shared.run(&command).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        // Once the scripted results run out, commands succeed
+        assert!(shared.status(&command).unwrap().success());
+        assert_eq!(runner.commands(), vec![command; 3]);
+    }
+
+    #[cfg(all(unix, feature = "tokio"))]
+    #[tokio::test]
+    async fn test_process_runner_runs_commands_asynchronously() {
+        let command = ServiceCommand::new("sh")
+            .arg("-c")
+            .arg("echo out; echo err >&2; exit 3");
+
+        let output = ProcessCommandRunner.run_async(&command).await.unwrap();
+        assert_eq!(output.status.code(), Some(3));
+        assert_eq!(output.stdout, b"out\n");
+        assert_eq!(output.stderr, b"err\n");
+
+        let status = ProcessCommandRunner.status_async(&command).await.unwrap();
+        assert_eq!(status.code(), Some(3));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_process_runner_streams_lines() {
+        let command = ServiceCommand::new("sh")
+            .arg("-c")
+            .arg("printf 'one\\ntwo\\r\\nthree'; echo err >&2; exit 3");
+
+        let lines: Vec<_> = ProcessCommandRunner.run_lines(&command).unwrap().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0].as_ref().unwrap(), "one");
+        assert_eq!(lines[1].as_ref().unwrap(), "two");
+        assert_eq!(lines[2].as_ref().unwrap(), "three");
+        assert!(lines[3].as_ref().unwrap_err().to_string().contains("err"));
+
+        // Dropping the lines of a command that has not exited stops it
+        let command = ServiceCommand::new("sh")
+            .arg("-c")
+            .arg("echo ready; sleep 60");
+        let mut lines = ProcessCommandRunner.run_lines(&command).unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "ready");
+        drop(lines);
+    }
+
+    #[test]
+    fn test_shared_runner_equality() {
+        let shared = SharedCommandRunner::new(FakeCommandRunner::new());
+        assert_eq!(shared, shared.clone());
+        assert_ne!(shared, SharedCommandRunner::new(FakeCommandRunner::new()));
+        assert_eq!(
+            SharedCommandRunner::default(),
+            SharedCommandRunner::default()
+        );
+    }
+}
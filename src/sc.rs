@@ -1,16 +1,22 @@
-use crate::utils::wrap_output;
+use crate::utils::{self, CommandOutput};
 
 use super::{
-    ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    dependency::unsupported_dependency, limits::unsupported_limit, naming, CommandRunner,
+    DependencyKind, Error, InstallOutcome, InstalledService, RenderedService, ServiceCommand,
+    ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx, ServiceLevel, ServiceListCtx,
+    ServiceManager, ServiceNaming, ServiceOutput, ServiceRestartCtx, ServiceStartCtx, ServiceState,
+    ServiceStatus, ServiceStatusDetail, ServiceStopCtx, ServiceUninstallCtx, SharedCommandRunner,
 };
 use std::{
     borrow::Cow,
+    collections::HashMap,
     ffi::{OsStr, OsString},
     fmt, io,
-    process::{Command, Output, Stdio},
 };
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 #[cfg(windows)]
 mod shell_escape;
 
@@ -26,14 +32,26 @@ mod shell_escape {
 
 static SC_EXE: &str = "sc.exe";
 
+// ref: https://learn.microsoft.com/en-us/windows/win32/debug/system-error-codes
+const ERROR_ACCESS_DENIED: i32 = 5;
+const ERROR_SERVICE_DOES_NOT_EXIST: i32 = 1060;
+const ERROR_SERVICE_EXISTS: i32 = 1073;
+
+/// Maximum length of the name of a Windows service
+const MAX_SERVICE_NAME_LEN: usize = 256;
+
 /// Configuration settings tied to sc.exe services
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ScConfig {
     pub install: ScInstallConfig,
 }
 
 /// Configuration settings tied to sc.exe services during installation
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ScInstallConfig {
     /// Type of windows service for install
     pub service_type: WindowsServiceType,
@@ -46,6 +64,8 @@ pub struct ScInstallConfig {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum WindowsServiceType {
     /// Service runs in its own process. It does not share an executable file with other services
     Own,
@@ -69,6 +89,19 @@ impl Default for WindowsServiceType {
     }
 }
 
+impl WindowsServiceType {
+    /// Code of the service type, as reported by `sc.exe qc`
+    fn code(self) -> u32 {
+        match self {
+            Self::Own => 0x10,
+            Self::Share => 0x20,
+            Self::Kernel => 0x1,
+            Self::FileSys => 0x2,
+            Self::Rec => 0x8,
+        }
+    }
+}
+
 impl fmt::Display for WindowsServiceType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -82,6 +115,8 @@ impl fmt::Display for WindowsServiceType {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum WindowsStartType {
     /// Specifies a device driver that is loaded by the boot loader
     Boot,
@@ -107,6 +142,19 @@ impl Default for WindowsStartType {
     }
 }
 
+impl WindowsStartType {
+    /// Code of the start type, as reported by `sc.exe qc`
+    fn code(self) -> u32 {
+        match self {
+            Self::Boot => 0,
+            Self::System => 1,
+            Self::Auto => 2,
+            Self::Demand => 3,
+            Self::Disabled => 4,
+        }
+    }
+}
+
 impl fmt::Display for WindowsStartType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -120,6 +168,8 @@ impl fmt::Display for WindowsStartType {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum WindowsErrorSeverity {
     /// Specifies that the error is logged. A message box is displayed, informing the user that a service has failed to start. Startup will continue
     Normal,
@@ -145,6 +195,18 @@ impl Default for WindowsErrorSeverity {
     }
 }
 
+impl WindowsErrorSeverity {
+    /// Code of the error severity, as reported by `sc.exe qc`
+    fn code(self) -> u32 {
+        match self {
+            Self::Ignore => 0,
+            Self::Normal => 1,
+            Self::Severe => 2,
+            Self::Critical => 3,
+        }
+    }
+}
+
 impl fmt::Display for WindowsErrorSeverity {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -158,10 +220,29 @@ impl fmt::Display for WindowsErrorSeverity {
 
 /// Implementation of [`ServiceManager`] for [Window Service](https://en.wikipedia.org/wiki/Windows_service)
 /// leveraging [`sc.exe`](https://docs.microsoft.com/en-us/previous-versions/windows/it-pro/windows-server-2012-r2-and-2012/cc754599(v=ws.11))
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct ScServiceManager {
     /// Configuration settings tied to rc.d services
     pub config: ScConfig,
+
+    /// Strategy deriving the name of the Windows service from its label
+    pub naming: ServiceNaming,
+
+    /// Runner used to execute the commands of the service manager
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub runner: SharedCommandRunner,
+}
+
+impl Default for ScServiceManager {
+    fn default() -> Self {
+        Self {
+            config: ScConfig::default(),
+            naming: ServiceNaming::QualifiedName,
+            runner: SharedCommandRunner::default(),
+        }
+    }
 }
 
 impl ScServiceManager {
@@ -172,31 +253,60 @@ impl ScServiceManager {
 
     /// Update manager to use the specified config
     pub fn with_config(self, config: ScConfig) -> Self {
-        Self { config }
+        Self { config, ..self }
     }
-}
 
-impl ServiceManager for ScServiceManager {
-    fn available(&self) -> io::Result<bool> {
-        match which::which(SC_EXE) {
-            Ok(_) => Ok(true),
-            Err(which::Error::CannotFindBinaryPath) => Ok(false),
-            Err(x) => Err(io::Error::new(io::ErrorKind::Other, x)),
+    /// Update manager to name services using the specified strategy
+    pub fn with_naming(self, naming: ServiceNaming) -> Self {
+        Self { naming, ..self }
+    }
+
+    /// Update manager to execute commands using the specified runner
+    pub fn with_runner(self, runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: SharedCommandRunner::new(runner),
+            ..self
         }
     }
 
-    fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
+    /// Settings of the service described by `ctx`, validating the name of the service
+    fn settings(&self, ctx: &ServiceInstallCtx) -> io::Result<ScServiceSettings> {
+        let service_name = self.naming.native_name(&ctx.label);
+        naming::validate_native_name(&service_name, MAX_SERVICE_NAME_LEN, "sc.exe")?;
+
+        // Windows services have no console, and the service control manager does not capture
+        // anything they write to it
+        if ctx.stdout != ServiceOutput::Inherit || ctx.stderr != ServiceOutput::Inherit {
+            return Err(Error::Unsupported(
+                "sc.exe is unable to redirect the output of a service".to_string(),
+            )
+            .into());
+        }
+        if !ctx.limits.is_empty() {
+            return Err(unsupported_limit("sc.exe", "the resources of a service"));
+        }
+
+        // A service only starts once the services it depends on are running, which both
+        // requires and orders them
+        let mut dependencies = Vec::new();
+        for dependency in &ctx.dependencies {
+            match dependency.kind {
+                DependencyKind::Requires | DependencyKind::After => {
+                    if !dependencies.contains(&dependency.service) {
+                        dependencies.push(dependency.service.clone());
+                    }
+                }
+                kind => return Err(unsupported_dependency("sc.exe", kind)),
+            }
+        }
 
-        let service_type = OsString::from(self.config.install.service_type.to_string());
-        let error_severity = OsString::from(self.config.install.error_severity.to_string());
         let start_type = if ctx.autostart {
-            OsString::from("Auto")
+            WindowsStartType::Auto
         } else {
             // TODO: Perhaps it could be useful to make `start_type` an `Option`? That way you
             // could have `Auto`/`Demand` based on `autostart`, and if `start_type` is set, its
             // special value will override `autostart`.
-            OsString::from(self.config.install.start_type.to_string())
+            self.config.install.start_type
         };
 
         // Build our binary including arguments, following similar approach as windows-service-rs
@@ -207,50 +317,211 @@ impl ServiceManager for ScServiceManager {
             binpath.push(shell_escape::escape(Cow::Borrowed(arg)));
         }
 
-        let display_name = OsStr::new(&service_name);
+        Ok(ScServiceSettings {
+            service_type: self.config.install.service_type,
+            start_type,
+            error_severity: self.config.install.error_severity,
+            binpath,
+            display_name: OsString::from(ctx.label.to_qualified_name()),
+            dependencies,
+        })
+    }
+}
+
+/// Settings of a service, as passed to `sc.exe create` and `sc.exe config`
+struct ScServiceSettings {
+    service_type: WindowsServiceType,
+    start_type: WindowsStartType,
+    error_severity: WindowsErrorSeverity,
+    binpath: OsString,
+    display_name: OsString,
+    dependencies: Vec<String>,
+}
 
-        wrap_output(sc_exe(
-            "create",
-            &service_name,
-            [
-                // type= {service_type}
-                OsStr::new("type="),
-                service_type.as_os_str(),
-                // start= {start_type}
-                OsStr::new("start="),
-                start_type.as_os_str(),
-                // error= {error_severity}
-                OsStr::new("error="),
-                error_severity.as_os_str(),
-                // binpath= "{program} {args}"
-                OsStr::new("binpath="),
-                binpath.as_os_str(),
-                // displayname= {display_name}
-                OsStr::new("displayname="),
-                display_name,
-            ],
-        )?)?;
+impl ScServiceSettings {
+    /// Produces the `sc.exe {cmd}` command applying these settings to the service
+    fn command(&self, cmd: &str, service_name: &str) -> ServiceCommand {
+        let service_type = OsString::from(self.service_type.to_string());
+        let start_type = OsString::from(self.start_type.to_string());
+        let error_severity = OsString::from(self.error_severity.to_string());
+        let mut args = vec![
+            // type= {service_type}
+            OsStr::new("type="),
+            service_type.as_os_str(),
+            // start= {start_type}
+            OsStr::new("start="),
+            start_type.as_os_str(),
+            // error= {error_severity}
+            OsStr::new("error="),
+            error_severity.as_os_str(),
+            // binpath= "{program} {args}"
+            OsStr::new("binpath="),
+            self.binpath.as_os_str(),
+            // displayname= {display_name}
+            OsStr::new("displayname="),
+            self.display_name.as_os_str(),
+        ];
+
+        // depend= {service}/{service}, where a lone slash clears the dependencies of an
+        // existing service
+        let depend = match self.dependencies.join("/") {
+            depend if depend.is_empty() => String::from("/"),
+            depend => depend,
+        };
+        if cmd != "create" || !self.dependencies.is_empty() {
+            args.push(OsStr::new("depend="));
+            args.push(OsStr::new(&depend));
+        }
+        sc_service_command(cmd, service_name, args)
+    }
+
+    /// Reports whether the output of `sc.exe qc` shows a service configured with these settings
+    ///
+    /// Values are in the form of `{code}  {description}`, e.g. `2   AUTO_START`, where the code
+    /// of the service type is hexadecimal. Each dependency after the first is listed on a line of
+    /// its own with an empty key.
+    fn matches_qc(&self, stdout: &str) -> bool {
+        let mut properties: HashMap<&str, &str> = HashMap::new();
+        let mut dependencies = Vec::new();
+        let mut key = "";
+        for (name, value) in stdout.lines().filter_map(|line| line.split_once(':')) {
+            let value = value.trim();
+            if !name.trim().is_empty() {
+                key = name.trim();
+                properties.insert(key, value);
+            }
+            if key == "DEPENDENCIES" && !value.is_empty() {
+                dependencies.push(value);
+            }
+        }
+        let code = |name: &str, radix: u32| {
+            properties
+                .get(name)
+                .and_then(|value| value.split_whitespace().next())
+                .and_then(|value| u32::from_str_radix(value, radix).ok())
+        };
+
+        code("TYPE", 16) == Some(self.service_type.code())
+            && code("START_TYPE", 10) == Some(self.start_type.code())
+            && code("ERROR_CONTROL", 10) == Some(self.error_severity.code())
+            && properties.get("BINARY_PATH_NAME").copied()
+                == Some(self.binpath.to_string_lossy().as_ref())
+            && properties.get("DISPLAY_NAME").copied()
+                == Some(self.display_name.to_string_lossy().as_ref())
+            && dependencies == self.dependencies
+    }
+}
+
+impl ServiceManager for ScServiceManager {
+    fn available(&self) -> io::Result<bool> {
+        match which::which(SC_EXE) {
+            Ok(_) => Ok(true),
+            Err(which::Error::CannotFindBinaryPath) => Ok(false),
+            Err(x) => Err(io::Error::new(io::ErrorKind::Other, x)),
+        }
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
+        let service_name = self.naming.native_name(&ctx.label);
+        for command in self.render(&ctx)?.commands {
+            wrap_sc_output(&service_name, utils::output(&self.runner, &command)?)?;
+        }
         Ok(())
     }
 
+    /// Registers the service, or compares its configuration as queried through `sc.exe qc`
+    /// with the rendered one and applies any change through `sc.exe config`, restarting the
+    /// service only if it was running
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let settings = self.settings(&ctx)?;
+        let output = sc_exe(&self.runner, "qc", &service_name, [])?;
+        if output.status.code() == Some(ERROR_SERVICE_DOES_NOT_EXIST) {
+            self.install(ctx)?;
+            return Ok(InstallOutcome::Installed);
+        }
+
+        let output = wrap_sc_output(&service_name, output)?;
+        if settings.matches_qc(&String::from_utf8_lossy(&output.stdout)) {
+            return Ok(InstallOutcome::Unchanged);
+        }
+
+        let running =
+            self.status(crate::ServiceStatusCtx::new(ctx.label.clone()))? == ServiceStatus::Running;
+        let config = settings.command("config", &service_name);
+        wrap_sc_output(&service_name, utils::output(&self.runner, &config)?)?;
+        if running {
+            self.restart(ServiceRestartCtx::new(ctx.label))?;
+        }
+        Ok(InstallOutcome::Updated { restarted: running })
+    }
+
+    /// Renders the `sc.exe create` command registering the service, as `sc.exe` does not use
+    /// any definition files
+    ///
+    /// The display name of the service is its fully-qualified label. Registering a service
+    /// under a name that is already used fails with [`Error::AlreadyInstalled`].
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let create = self.settings(ctx)?.command("create", &service_name);
+        Ok(RenderedService {
+            files: Vec::new(),
+            commands: vec![create],
+        })
+    }
+
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        wrap_output(sc_exe("delete", &service_name, [])?)?;
+        let service_name = self.naming.native_name(&ctx.label);
+        wrap_sc_output(
+            &service_name,
+            sc_exe(&self.runner, "delete", &service_name, [])?,
+        )?;
         Ok(())
     }
 
     fn start(&self, ctx: ServiceStartCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        wrap_output(sc_exe("start", &service_name, [])?)?;
+        let service_name = self.naming.native_name(&ctx.label);
+        wrap_sc_output(
+            &service_name,
+            sc_exe(&self.runner, "start", &service_name, [])?,
+        )?;
         Ok(())
     }
 
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        wrap_output(sc_exe("stop", &service_name, [])?)?;
+        let service_name = self.naming.native_name(&ctx.label);
+        wrap_sc_output(
+            &service_name,
+            sc_exe(&self.runner, "stop", &service_name, [])?,
+        )?;
         Ok(())
     }
 
+    /// Changes the start type of the service to `auto`
+    fn enable(&self, ctx: ServiceEnableCtx) -> io::Result<()> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let command = start_type_command(&service_name, WindowsStartType::Auto);
+        wrap_sc_output(&service_name, utils::output(&self.runner, &command)?)?;
+        Ok(())
+    }
+
+    /// Changes the start type of the service to `demand`, so it can still be started manually
+    fn disable(&self, ctx: ServiceDisableCtx) -> io::Result<()> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let command = start_type_command(&service_name, WindowsStartType::Demand);
+        wrap_sc_output(&service_name, utils::output(&self.runner, &command)?)?;
+        Ok(())
+    }
+
+    /// Reports whether the start type of the service, as queried through `sc.exe qc`, starts it
+    /// automatically
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let output = sc_exe(&self.runner, "qc", &service_name, [])?;
+        let output = wrap_sc_output(&service_name, output)?;
+        enabled_from_qc(&service_name, &String::from_utf8_lossy(&output.stdout))
+    }
+
     fn level(&self) -> ServiceLevel {
         ServiceLevel::System
     }
@@ -258,62 +529,370 @@ impl ServiceManager for ScServiceManager {
     fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
         match level {
             ServiceLevel::System => Ok(()),
-            ServiceLevel::User => Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "sc.exe does not support user-level services",
-            )),
+            ServiceLevel::User => Err(Error::Unsupported(
+                "sc.exe does not support user-level services".to_string(),
+            )
+            .into()),
         }
     }
 
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<crate::ServiceStatus> {
-        let service_name = ctx.label.to_qualified_name();
-        let output = sc_exe("query", &service_name, [])?;
-        if !output.status.success() {
-            if matches!(output.status.code(), Some(1060)) {
-                // 1060 = The specified service does not exist as an installed service.
-                return Ok(crate::ServiceStatus::NotInstalled);
+        let service_name = self.naming.native_name(&ctx.label);
+        let output = sc_exe(&self.runner, "query", &service_name, [])?;
+        status_from_query(&service_name, output)
+    }
+
+    fn status_detail(&self, ctx: crate::ServiceStatusCtx) -> io::Result<ServiceStatusDetail> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let output = sc_exe(&self.runner, "queryex", &service_name, [])?;
+        detail_from_queryex(&service_name, output)
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> io::Result<Vec<InstalledService>> {
+        let output = utils::wrap_output(utils::output(&self.runner, &query_all_command())?)?;
+        Ok(installed_from_query_all(&output, &self.naming, &ctx))
+    }
+}
+
+/// Interprets the output of `sc.exe query` for a single service
+fn status_from_query(service_name: &str, output: CommandOutput) -> io::Result<ServiceStatus> {
+    if !output.status.success() {
+        if matches!(output.status.code(), Some(ERROR_SERVICE_DOES_NOT_EXIST)) {
+            return Ok(crate::ServiceStatus::NotInstalled);
+        }
+        return Err(sc_error(service_name, output).into());
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let line = stdout.split('\n').find(|line| {
+        line.trim_matches(['\r', ' '])
+            .to_lowercase()
+            .starts_with("state")
+    });
+    let status = match line {
+        Some(line) if line.contains("RUNNING") => crate::ServiceStatus::Running,
+        _ => crate::ServiceStatus::Stopped(None), // TODO: more statuses?
+    };
+    Ok(status)
+}
+
+/// Interprets the output of `sc.exe queryex` for a single service
+fn detail_from_queryex(
+    service_name: &str,
+    output: CommandOutput,
+) -> io::Result<ServiceStatusDetail> {
+    if !output.status.success() {
+        if matches!(output.status.code(), Some(ERROR_SERVICE_DOES_NOT_EXIST)) {
+            return Ok(ServiceStatusDetail::new(ServiceState::NotInstalled));
+        }
+        return Err(sc_error(service_name, output).into());
+    }
+
+    Ok(parse_queryex(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Produces the command querying every service, regardless of its state
+fn query_all_command() -> ServiceCommand {
+    sc_command(
+        "query",
+        [
+            OsStr::new("type="),
+            OsStr::new("service"),
+            OsStr::new("state="),
+            OsStr::new("all"),
+        ],
+    )
+}
+
+/// Collects the services printed by `sc.exe query state= all`, filtered by `ctx`
+///
+/// Services whose label cannot be recovered from their name using `naming`, such as names with
+/// spaces, are skipped.
+fn installed_from_query_all(
+    output: &CommandOutput,
+    naming: &ServiceNaming,
+    ctx: &ServiceListCtx,
+) -> Vec<InstalledService> {
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut services = Vec::new();
+    for (service_name, status) in parse_query_all(&stdout) {
+        match naming.label_from_native(&service_name) {
+            Some(label) if ctx.matches(&label) => {
+                services.push(InstalledService { label, status });
             }
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Command failed with exit code {}: {}",
-                    output.status.code().unwrap_or(-1),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
+            _ => {}
+        }
+    }
+
+    services
+}
+
+/// Parses the output of `sc.exe queryex` for a single service into a detailed status
+fn parse_queryex(stdout: &str) -> ServiceStatusDetail {
+    // Values are in the form of `{number}  {description}`, e.g. `4  RUNNING`
+    let properties: HashMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim(), value.trim()))
+        .collect();
+    let number = |name: &str| {
+        properties
+            .get(name)
+            .and_then(|value| value.split_whitespace().next())
+            .and_then(|value| value.parse::<i32>().ok())
+    };
+
+    // ERROR_SERVICE_SPECIFIC_ERROR (1066) means that the service reported its own exit code,
+    // while ERROR_SERVICE_NEVER_STARTED (1077) means that there is no exit code to report
+    let exit_code = match number("WIN32_EXIT_CODE") {
+        Some(1066) => number("SERVICE_EXIT_CODE"),
+        Some(1077) => None,
+        code => code,
+    };
+
+    let state = match number("STATE") {
+        Some(1) if exit_code.unwrap_or_default() != 0 => ServiceState::Failed,
+        Some(1) => ServiceState::Stopped,
+        Some(2) | Some(5) => ServiceState::Starting,
+        Some(3) | Some(6) => ServiceState::Stopping,
+        Some(4) => ServiceState::Running,
+        Some(7) => ServiceState::Paused,
+        _ => ServiceState::Unknown,
+    };
+
+    ServiceStatusDetail {
+        state,
+        pid: number("PID").filter(|pid| *pid > 0).map(|pid| pid as u32),
+        exit_code,
+        active_since: None,
+        restart_count: None,
+        native_state: properties
+            .get("STATE")
+            .and_then(|value| value.split_whitespace().nth(1))
+            .map(|value| value.to_string()),
+    }
+}
+
+/// Produces the `sc.exe config` command changing the start type of the service
+fn start_type_command(service_name: &str, start_type: WindowsStartType) -> ServiceCommand {
+    let start_type = OsString::from(start_type.to_string());
+    sc_service_command(
+        "config",
+        service_name,
+        [OsStr::new("start="), start_type.as_os_str()],
+    )
+}
+
+/// Interprets the output of `sc.exe qc`, whose `START_TYPE` is in the form of
+/// `{number}  {description}`, e.g. `2   AUTO_START`
+fn enabled_from_qc(service_name: &str, stdout: &str) -> io::Result<bool> {
+    let start_type = stdout
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim() == "START_TYPE")
+        .and_then(|(_, value)| value.split_whitespace().next())
+        .and_then(|value| value.parse::<u32>().ok());
+
+    // Boot (0), system (1) and auto (2) services start without being asked to, unlike demand (3)
+    // and disabled (4) ones
+    match start_type {
+        Some(start_type) => Ok(start_type <= 2),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unable to find the start type of {service_name} in the output of sc.exe qc"),
+        )),
+    }
+}
+
+/// Parses the output of `sc.exe query state= all` into the name and status of each service
+fn parse_query_all(stdout: &str) -> Vec<(String, ServiceStatus)> {
+    let mut services = Vec::new();
+    for line in stdout.lines() {
+        let line = line.trim_matches(['\r', ' ']);
+        if let Some(service_name) = line.strip_prefix("SERVICE_NAME:") {
+            services.push((
+                service_name.trim().to_string(),
+                ServiceStatus::Stopped(None),
             ));
+        } else if line.to_lowercase().starts_with("state") && line.contains("RUNNING") {
+            if let Some((_, status)) = services.last_mut() {
+                *status = ServiceStatus::Running;
+            }
         }
+    }
+    services
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let line = stdout.split('\n').find(|line| {
-            line.trim_matches(&['\r', ' '])
-                .to_lowercase()
-                .starts_with("state")
-        });
-        let status = match line {
-            Some(line) if line.contains("RUNNING") => crate::ServiceStatus::Running,
-            _ => crate::ServiceStatus::Stopped(None), // TODO: more statuses?
-        };
-        Ok(status)
+/// Converts the output of a failed `sc.exe` command into an [`Error`], using the system error
+/// code it exits with
+fn sc_error(service_name: &str, output: CommandOutput) -> Error {
+    match output.status.code() {
+        Some(ERROR_SERVICE_DOES_NOT_EXIST) => Error::NotInstalled(service_name.to_string()),
+        Some(ERROR_SERVICE_EXISTS) => Error::AlreadyInstalled(service_name.to_string()),
+        Some(ERROR_ACCESS_DENIED) => {
+            Error::PermissionDenied(utils::command_failed(output).to_string())
+        }
+        _ => utils::command_failed(output),
+    }
+}
+
+/// Warp the output of a `sc.exe` command in a `std::io::Result` if the command failed
+fn wrap_sc_output(service_name: &str, output: CommandOutput) -> io::Result<CommandOutput> {
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(sc_error(service_name, output).into())
     }
 }
 
 fn sc_exe<'a>(
+    runner: &dyn CommandRunner,
     cmd: &str,
-    service_name: &str,
+    service_name: &'a str,
+    args: impl IntoIterator<Item = &'a OsStr>,
+) -> io::Result<CommandOutput> {
+    utils::output(runner, &sc_service_command(cmd, service_name, args))
+}
+
+fn sc_service_command<'a>(
+    cmd: &str,
+    service_name: &'a str,
     args: impl IntoIterator<Item = &'a OsStr>,
-) -> io::Result<Output> {
-    let mut command = Command::new(SC_EXE);
+) -> ServiceCommand {
+    sc_command(cmd, std::iter::once(OsStr::new(service_name)).chain(args))
+}
+
+fn sc_command<'a>(cmd: &str, args: impl IntoIterator<Item = &'a OsStr>) -> ServiceCommand {
+    ServiceCommand::new(SC_EXE).arg(cmd).args(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use indoc::indoc;
+
+    #[test]
+    fn test_parse_queryex() {
+        let stdout = indoc! {"
+            SERVICE_NAME: com.example.echo
+                    TYPE               : 10  WIN32_OWN_PROCESS
+                    STATE              : 4  RUNNING
+                                            (STOPPABLE, NOT_PAUSABLE, ACCEPTS_SHUTDOWN)
+                    WIN32_EXIT_CODE    : 0  (0x0)
+                    SERVICE_EXIT_CODE  : 0  (0x0)
+                    CHECKPOINT         : 0x0
+                    WAIT_HINT          : 0x0
+                    PID                : 1234
+                    FLAGS              :
+        "};
+
+        let detail = parse_queryex(stdout);
+        assert_eq!(detail.state, ServiceState::Running);
+        assert_eq!(detail.pid, Some(1234));
+        assert_eq!(detail.exit_code, Some(0));
+        assert_eq!(detail.native_state.as_deref(), Some("RUNNING"));
+    }
+
+    #[test]
+    fn test_parse_queryex_service_specific_error() {
+        let stdout = indoc! {"
+            SERVICE_NAME: com.example.echo
+                    TYPE               : 10  WIN32_OWN_PROCESS
+                    STATE              : 1  STOPPED
+                    WIN32_EXIT_CODE    : 1066  (0x42a)
+                    SERVICE_EXIT_CODE  : 3  (0x3)
+                    PID                : 0
+        "};
+
+        let detail = parse_queryex(stdout);
+        assert_eq!(detail.state, ServiceState::Failed);
+        assert_eq!(detail.pid, None);
+        assert_eq!(detail.exit_code, Some(3));
+    }
 
-    command
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+    #[test]
+    fn test_enabled_from_qc() {
+        let stdout = indoc! {r"
+            [SC] QueryServiceConfig SUCCESS
 
-    command.arg(cmd).arg(service_name);
+            SERVICE_NAME: com.example.echo
+                    TYPE               : 10  WIN32_OWN_PROCESS
+                    START_TYPE         : 2   AUTO_START  (DELAYED)
+                    ERROR_CONTROL      : 1   NORMAL
+                    BINARY_PATH_NAME   : C:\echo.exe hello
+                    DISPLAY_NAME       : com.example.echo
+        "};
+        assert!(enabled_from_qc("com.example.echo", stdout).unwrap());
 
-    for arg in args {
-        command.arg(arg);
+        let stdout = stdout.replace("2   AUTO_START  (DELAYED)", "3   DEMAND_START");
+        assert!(!enabled_from_qc("com.example.echo", &stdout).unwrap());
+
+        assert!(enabled_from_qc("com.example.echo", "").is_err());
     }
 
-    command.output()
+    #[test]
+    fn test_settings_match_qc() {
+        let settings = ScServiceSettings {
+            service_type: WindowsServiceType::Own,
+            start_type: WindowsStartType::Auto,
+            error_severity: WindowsErrorSeverity::Normal,
+            binpath: OsString::from(r"C:\echo.exe hello"),
+            display_name: OsString::from("com.example.echo"),
+            dependencies: vec!["Tcpip".to_string(), "Afd".to_string()],
+        };
+        let stdout = indoc! {r"
+            [SC] QueryServiceConfig SUCCESS
+
+            SERVICE_NAME: com.example.echo
+                    TYPE               : 10  WIN32_OWN_PROCESS
+                    START_TYPE         : 2   AUTO_START
+                    ERROR_CONTROL      : 1   NORMAL
+                    BINARY_PATH_NAME   : C:\echo.exe hello
+                    LOAD_ORDER_GROUP   :
+                    TAG                : 0
+                    DISPLAY_NAME       : com.example.echo
+                    DEPENDENCIES       : Tcpip
+                                       : Afd
+                    SERVICE_START_NAME : LocalSystem
+        "};
+        assert!(settings.matches_qc(stdout));
+
+        let without_afd = stdout.replace("                   : Afd\n", "");
+        assert!(!settings.matches_qc(&without_afd));
+
+        let stdout = stdout.replace("echo.exe hello", "echo.exe goodbye");
+        assert!(!settings.matches_qc(&stdout));
+
+        let stdout = stdout.replace("2   AUTO_START", "3   DEMAND_START");
+        assert!(!settings.matches_qc(&stdout));
+    }
+
+    #[test]
+    fn test_parse_query_all() {
+        let stdout = indoc! {"
+            SERVICE_NAME: com.example.running
+            DISPLAY_NAME: com.example.running
+                    TYPE               : 10  WIN32_OWN_PROCESS
+                    STATE              : 4  RUNNING
+                                            (STOPPABLE, NOT_PAUSABLE, ACCEPTS_SHUTDOWN)
+                    WIN32_EXIT_CODE    : 0  (0x0)
+
+            SERVICE_NAME: com.example.stopped
+            DISPLAY_NAME: com.example.stopped
+                    TYPE               : 10  WIN32_OWN_PROCESS
+                    STATE              : 1  STOPPED
+                    WIN32_EXIT_CODE    : 1077  (0x435)
+        "};
+
+        assert_eq!(
+            parse_query_all(stdout),
+            vec![
+                ("com.example.running".to_string(), ServiceStatus::Running),
+                (
+                    "com.example.stopped".to_string(),
+                    ServiceStatus::Stopped(None)
+                ),
+            ]
+        );
+    }
 }
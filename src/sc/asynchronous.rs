@@ -0,0 +1,158 @@
+use super::{
+    detail_from_queryex, enabled_from_qc, installed_from_query_all, query_all_command,
+    sc_service_command, start_type_command, status_from_query, wrap_sc_output, ScServiceManager,
+    WindowsStartType, ERROR_SERVICE_DOES_NOT_EXIST,
+};
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::utils::{self, nonblocking};
+use crate::{
+    InstallOutcome, InstalledService, ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx,
+    ServiceLevel, ServiceListCtx, ServiceRestartCtx, ServiceStartCtx, ServiceStatus,
+    ServiceStatusCtx, ServiceStatusDetail, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::io;
+
+impl ScServiceManager {
+    /// Runs `sc.exe {cmd} {service_name}`, failing if it does not succeed
+    async fn sc_exe_async(&self, cmd: &str, service_name: &str) -> io::Result<()> {
+        let command = sc_service_command(cmd, service_name, []);
+        let output = nonblocking::output(&self.runner, &command).await?;
+        wrap_sc_output(service_name, output)?;
+        Ok(())
+    }
+}
+
+impl AsyncServiceManager for ScServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        Box::pin(async move { crate::ServiceManager::available(self) })
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            for command in crate::ServiceManager::render(self, &ctx)?.commands {
+                let output = nonblocking::output(&self.runner, &command).await?;
+                wrap_sc_output(&service_name, output)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let settings = self.settings(&ctx)?;
+            let qc = sc_service_command("qc", &service_name, []);
+            let output = nonblocking::output(&self.runner, &qc).await?;
+            if output.status.code() == Some(ERROR_SERVICE_DOES_NOT_EXIST) {
+                self.install(ctx).await?;
+                return Ok(InstallOutcome::Installed);
+            }
+
+            let output = wrap_sc_output(&service_name, output)?;
+            if settings.matches_qc(&String::from_utf8_lossy(&output.stdout)) {
+                return Ok(InstallOutcome::Unchanged);
+            }
+
+            let status = self
+                .status(ServiceStatusCtx::new(ctx.label.clone()))
+                .await?;
+            let running = status == ServiceStatus::Running;
+            let config = settings.command("config", &service_name);
+            let output = nonblocking::output(&self.runner, &config).await?;
+            wrap_sc_output(&service_name, output)?;
+            if running {
+                self.restart(ServiceRestartCtx::new(ctx.label)).await?;
+            }
+            Ok(InstallOutcome::Updated { restarted: running })
+        })
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            self.sc_exe_async("delete", &self.naming.native_name(&ctx.label))
+                .await
+        })
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            self.sc_exe_async("start", &self.naming.native_name(&ctx.label))
+                .await
+        })
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            self.sc_exe_async("stop", &self.naming.native_name(&ctx.label))
+                .await
+        })
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let command = start_type_command(&service_name, WindowsStartType::Auto);
+            let output = nonblocking::output(&self.runner, &command).await?;
+            wrap_sc_output(&service_name, output)?;
+            Ok(())
+        })
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let command = start_type_command(&service_name, WindowsStartType::Demand);
+            let output = nonblocking::output(&self.runner, &command).await?;
+            wrap_sc_output(&service_name, output)?;
+            Ok(())
+        })
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let command = sc_service_command("qc", &service_name, []);
+            let output = nonblocking::output(&self.runner, &command).await?;
+            let output = wrap_sc_output(&service_name, output)?;
+            enabled_from_qc(&service_name, &String::from_utf8_lossy(&output.stdout))
+        })
+    }
+
+    fn level(&self) -> ServiceLevel {
+        crate::ServiceManager::level(self)
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        crate::ServiceManager::set_level(self, level)
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let command = sc_service_command("query", &service_name, []);
+            let output = nonblocking::output(&self.runner, &command).await?;
+            status_from_query(&service_name, output)
+        })
+    }
+
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let command = sc_service_command("queryex", &service_name, []);
+            let output = nonblocking::output(&self.runner, &command).await?;
+            detail_from_queryex(&service_name, output)
+        })
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async move {
+            let output = nonblocking::output(&self.runner, &query_all_command()).await?;
+            Ok(installed_from_query_all(
+                &utils::wrap_output(output)?,
+                &self.naming,
+                &ctx,
+            ))
+        })
+    }
+}
@@ -1,26 +1,62 @@
-use crate::utils::wrap_output;
+use crate::utils::{wrap_output, CommandOutput};
 
 use super::{
-    utils, ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    dependency::validate_dependency_name, logs, naming, utils, CommandRunner, DependencyKind,
+    Error, InstallOutcome, LogLines, LogQuery, RenderedFile, RenderedService, ServiceCommand,
+    ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx, ServiceLabel, ServiceLevel,
+    ServiceManager, ServiceNaming, ServiceOutput, ServiceRestartCtx, ServiceRollbackCtx,
+    ServiceStartCtx, ServiceState, ServiceStatusDetail, ServiceStopCtx, ServiceUninstallCtx,
+    SharedCommandRunner,
 };
 use std::{
+    collections::HashMap,
+    ffi::OsString,
     fmt, io,
-    path::PathBuf,
-    process::{Command, Output, Stdio},
+    path::{Path, PathBuf},
+    time::{Duration, UNIX_EPOCH},
 };
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 static SYSTEMCTL: &str = "systemctl";
+static JOURNALCTL: &str = "journalctl";
+
+/// Properties queried through `systemctl show` to produce a [`ServiceStatusDetail`]
+static SHOW_PROPERTIES: &[&str] = &[
+    "LoadState",
+    "ActiveState",
+    "SubState",
+    "MainPID",
+    "ExecMainCode",
+    "ExecMainStatus",
+    "NRestarts",
+    "ActiveEnterTimestamp",
+];
 const SERVICE_FILE_PERMISSIONS: u32 = 0o644;
 
+/// Maximum length of a unit name, excluding its `.service` suffix
+const MAX_UNIT_NAME_LEN: usize = 255 - ".service".len();
+
 /// Configuration settings tied to systemd services
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SystemdConfig {
     pub install: SystemdInstallConfig,
+
+    /// Optional directory to use as the root of the filesystem, like `systemctl --root`
+    ///
+    /// Services are installed into and removed from the root using offline `systemctl`
+    /// operations, while starting and stopping them is unsupported. User-level services are
+    /// installed for every user of the root, like `systemctl --global`.
+    pub root: Option<PathBuf>,
 }
 
 /// Configuration settings tied to systemd services during installation
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SystemdInstallConfig {
     pub start_limit_interval_sec: Option<u32>,
     pub start_limit_burst: Option<u32>,
@@ -40,6 +76,8 @@ impl Default for SystemdInstallConfig {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "kebab-case"))]
 pub enum SystemdServiceRestartType {
     No,
     Always,
@@ -72,12 +110,21 @@ impl fmt::Display for SystemdServiceRestartType {
 
 /// Implementation of [`ServiceManager`] for Linux's [systemd](https://en.wikipedia.org/wiki/Systemd)
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SystemdServiceManager {
     /// Whether or not this manager is operating at the user-level
     pub user: bool,
 
     /// Configuration settings tied to systemd services
     pub config: SystemdConfig,
+
+    /// Strategy deriving the name of the unit of a service from its label
+    pub naming: ServiceNaming,
+
+    /// Runner used to execute the commands of the service manager
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub runner: SharedCommandRunner,
 }
 
 impl SystemdServiceManager {
@@ -94,26 +141,140 @@ impl SystemdServiceManager {
     /// Change manager to work with system services
     pub fn into_system(self) -> Self {
         Self {
-            config: self.config,
             user: false,
+            ..self
         }
     }
 
     /// Change manager to work with user services
     pub fn into_user(self) -> Self {
-        Self {
-            config: self.config,
-            user: true,
-        }
+        Self { user: true, ..self }
     }
 
     /// Update manager to use the specified config
     pub fn with_config(self, config: SystemdConfig) -> Self {
+        Self { config, ..self }
+    }
+
+    /// Update manager to name units using the specified strategy
+    pub fn with_naming(self, naming: ServiceNaming) -> Self {
+        Self { naming, ..self }
+    }
+
+    /// Update manager to execute commands using the specified runner
+    pub fn with_runner(self, runner: impl CommandRunner + 'static) -> Self {
         Self {
-            config,
-            user: self.user,
+            runner: SharedCommandRunner::new(runner),
+            ..self
+        }
+    }
+
+    /// Directory holding the unit files of the services managed at the current level
+    fn dir_path(&self) -> io::Result<PathBuf> {
+        match self.config.root.as_deref() {
+            Some(root) if self.user => Ok(utils::rooted(
+                Some(root),
+                PathBuf::from("/etc/systemd/user"),
+            )),
+            Some(root) => Ok(utils::rooted(Some(root), systemd_global_dir_path())),
+            None if self.user => systemd_user_dir_path(),
+            None => Ok(systemd_global_dir_path()),
         }
     }
+
+    /// Produces the command enabling or disabling the unit at `script_path`
+    fn unit_file_command(&self, cmd: &str, script_path: &Path) -> ServiceCommand {
+        match self.config.root.as_deref() {
+            // Within a root, units are looked up by name rather than by their path on the host
+            Some(root) => {
+                let unit = script_path.file_name().unwrap_or_default();
+                systemctl_command(cmd, [unit], self.user, Some(root))
+            }
+            None => systemctl_command(cmd, [script_path.as_os_str()], self.user, None),
+        }
+    }
+
+    /// Path of the unit file of the service with `label`, failing if it is not installed
+    fn installed_unit_path(&self, label: &ServiceLabel) -> io::Result<PathBuf> {
+        let script_name = self.naming.native_name(label);
+        let script_path = self.dir_path()?.join(format!("{script_name}.service"));
+        if !script_path.exists() {
+            return Err(Error::NotInstalled(script_name).into());
+        }
+        Ok(script_path)
+    }
+
+    /// Produces the commands making the manager pick up changed unit files, which are read
+    /// again when a root boots so there is nothing to reload within one
+    fn reload_commands(&self) -> Vec<ServiceCommand> {
+        match self.config.root {
+            Some(_) => Vec::new(),
+            None => vec![systemctl_command(
+                "daemon-reload",
+                std::iter::empty::<OsString>(),
+                self.user,
+                None,
+            )],
+        }
+    }
+
+    /// Produces the `systemctl is-enabled` command for the service with `label`
+    fn is_enabled_command(&self, label: &ServiceLabel) -> ServiceCommand {
+        // Unlike enable and disable, is-enabled only accepts unit names
+        let unit = format!("{}.service", self.naming.native_name(label));
+        systemctl_command("is-enabled", [unit], self.user, self.config.root.as_deref())
+    }
+
+    /// Produces the command running `systemctl {cmd}` on the service with `label`
+    fn unit_command(&self, cmd: &str, label: &ServiceLabel) -> ServiceCommand {
+        systemctl_command(cmd, [self.naming.native_name(label)], self.user, None)
+    }
+
+    /// Produces the `journalctl` command printing the output of the service with `label`
+    /// selected by `query`
+    fn journalctl_command(&self, label: &ServiceLabel, query: &LogQuery) -> ServiceCommand {
+        let unit = format!("{}.service", self.naming.native_name(label));
+        let unit_arg = if self.user {
+            format!("--user-unit={unit}")
+        } else {
+            format!("--unit={unit}")
+        };
+
+        // Following starts at the last ten lines unless told otherwise
+        let lines = match query.lines {
+            Some(n) => format!("--lines={n}"),
+            None => "--lines=all".to_string(),
+        };
+
+        let mut command = ServiceCommand::new(JOURNALCTL)
+            .arg(unit_arg)
+            .arg("--no-pager")
+            .arg("--output=cat")
+            .arg(lines);
+        if let Some(since) = query.since {
+            let secs = since
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs();
+            command = command.arg(format!("--since=@{secs}"));
+        }
+        if query.follow {
+            command = command.arg("--follow");
+        }
+        command
+    }
+
+    /// Produces the `systemctl show` command queried for a detailed status, using unix
+    /// timestamps when `unix_timestamps` is true
+    fn show_command(&self, label: &ServiceLabel, unix_timestamps: bool) -> ServiceCommand {
+        let unit = format!("{}.service", self.naming.native_name(label));
+        let properties = format!("--property={}", SHOW_PROPERTIES.join(","));
+        let mut args = vec![unit, properties];
+        if unix_timestamps {
+            args.push("--timestamp=unix".to_string());
+        }
+        systemctl_command("show", args, self.user, None)
+    }
 }
 
 impl ServiceManager for SystemdServiceManager {
@@ -126,73 +287,145 @@ impl ServiceManager for SystemdServiceManager {
     }
 
     fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
-        let dir_path = if self.user {
-            systemd_user_dir_path()?
-        } else {
-            systemd_global_dir_path()
-        };
+        utils::install_rendered(&self.runner, &self.render(&ctx)?)
+    }
 
-        std::fs::create_dir_all(&dir_path)?;
+    /// Rewrites a changed unit file and runs `systemctl daemon-reload`, restarting the service
+    /// only if it was running
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        utils::install_or_update_rendered(self, &self.runner, ctx, &self.reload_commands())
+    }
+
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        let dir_path = self.dir_path()?;
 
-        let script_name = ctx.label.to_script_name();
+        let script_name = self.naming.native_name(&ctx.label);
+        naming::validate_native_name(&script_name, MAX_UNIT_NAME_LEN, "systemd")?;
         let script_path = dir_path.join(format!("{script_name}.service"));
-        let service = match ctx.contents {
-            Some(contents) => contents,
-            _ => make_service(
-                &self.config.install,
-                &script_name,
-                &ctx,
-                self.user,
-                ctx.autostart,
-                ctx.disable_restart_on_failure,
-                ctx.requires_network,
+        utils::ensure_no_conflict(&script_path, &script_name, &ctx.label)?;
+
+        let service = match &ctx.contents {
+            Some(contents) => contents.clone(),
+            _ => utils::mark_label(
+                make_service(
+                    &self.config.install,
+                    &script_name,
+                    ctx,
+                    self.user,
+                    ctx.disable_restart_on_failure,
+                    ctx.requires_network,
+                )?,
+                &ctx.label,
+                utils::MarkerStyle::Hash,
             ),
         };
 
-        utils::write_file(
-            script_path.as_path(),
-            service.as_bytes(),
-            SERVICE_FILE_PERMISSIONS,
-        )?;
-
+        let mut commands = Vec::new();
         if ctx.autostart {
-            wrap_output(systemctl(
-                "enable",
-                script_path.to_string_lossy().as_ref(),
-                self.user,
-            )?)?;
+            commands.push(self.unit_file_command("enable", &script_path));
         }
 
-        Ok(())
+        Ok(RenderedService {
+            files: vec![RenderedFile {
+                path: script_path,
+                contents: service.into_bytes(),
+                mode: SERVICE_FILE_PERMISSIONS,
+            }],
+            commands,
+        })
     }
 
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
-        let dir_path = if self.user {
-            systemd_user_dir_path()?
-        } else {
-            systemd_global_dir_path()
-        };
-        let script_name = ctx.label.to_script_name();
+        let dir_path = self.dir_path()?;
+        let script_name = self.naming.native_name(&ctx.label);
         let script_path = dir_path.join(format!("{script_name}.service"));
+        if !script_path.exists() {
+            return Err(Error::NotInstalled(script_name).into());
+        }
 
-        wrap_output(systemctl(
-            "disable",
-            script_path.to_string_lossy().as_ref(),
-            self.user,
-        )?)?;
-        std::fs::remove_file(script_path)
+        let disable = self.unit_file_command("disable", &script_path);
+        wrap_output(utils::output(&self.runner, &disable)?)?;
+        utils::remove_definition(&script_path)
     }
 
     fn start(&self, ctx: ServiceStartCtx) -> io::Result<()> {
-        wrap_output(systemctl("start", &ctx.label.to_script_name(), self.user)?)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+        let command = self.unit_command("start", &ctx.label);
+        wrap_output(utils::output(&self.runner, &command)?)?;
         Ok(())
     }
 
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()> {
-        wrap_output(systemctl("stop", &ctx.label.to_script_name(), self.user)?)?;
+        utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+        let command = self.unit_command("stop", &ctx.label);
+        wrap_output(utils::output(&self.runner, &command)?)?;
+        Ok(())
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+        let command = self.unit_command("restart", &ctx.label);
+        wrap_output(utils::output(&self.runner, &command)?)?;
+        Ok(())
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> io::Result<()> {
+        let script_path = self.installed_unit_path(&ctx.label)?;
+        let command = self.unit_file_command("enable", &script_path);
+        wrap_output(utils::output(&self.runner, &command)?)?;
         Ok(())
     }
 
+    fn disable(&self, ctx: ServiceDisableCtx) -> io::Result<()> {
+        let script_path = self.installed_unit_path(&ctx.label)?;
+        let command = self.unit_file_command("disable", &script_path);
+        wrap_output(utils::output(&self.runner, &command)?)?;
+        Ok(())
+    }
+
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        self.installed_unit_path(&ctx.label)?;
+        let command = self.is_enabled_command(&ctx.label);
+        enabled_from_output(utils::output(&self.runner, &command)?)
+    }
+
+    /// Restores the previous unit file and runs `systemctl daemon-reload`
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> io::Result<()> {
+        let script_path = self.installed_unit_path(&ctx.label)?;
+        let script_name = self.naming.native_name(&ctx.label);
+        utils::restore_backup(&script_path, &script_name, SERVICE_FILE_PERMISSIONS)?;
+        for command in self.reload_commands() {
+            wrap_output(utils::output(&self.runner, &command)?)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the output of the service from the journal using `journalctl`, or from the files
+    /// that the unit file sends it to with `StandardOutput=` and `StandardError=`
+    fn logs(&self, label: ServiceLabel, query: LogQuery) -> io::Result<LogLines> {
+        let script_name = self.naming.native_name(&label);
+        let script_path = self.dir_path()?.join(format!("{script_name}.service"));
+        match std::fs::read_to_string(script_path) {
+            Ok(unit) => {
+                let paths = unit_log_paths(&unit);
+                if !paths.is_empty() {
+                    let root = self.config.root.as_deref();
+                    let paths = paths.into_iter().map(|x| utils::rooted(root, x));
+                    return logs::tail_files(paths.collect(), &query);
+                }
+            }
+            // The journal keeps the output of services that have since been uninstalled
+            Err(x) if x.kind() == io::ErrorKind::NotFound => {}
+            Err(x) => return Err(x),
+        }
+
+        utils::ensure_no_root(self.config.root.as_deref(), "read the logs of")?;
+        let command = self.journalctl_command(&label, &query);
+        self.runner
+            .run_lines(&command)
+            .map_err(|x| utils::tool_not_found(&command, x))
+    }
+
     fn level(&self) -> ServiceLevel {
         if self.user {
             ServiceLevel::User
@@ -211,37 +444,166 @@ impl ServiceManager for SystemdServiceManager {
     }
 
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<crate::ServiceStatus> {
-        let output = systemctl("status", &ctx.label.to_script_name(), self.user)?;
-        // ref: https://www.freedesktop.org/software/systemd/man/latest/systemctl.html#Exit%20status
-        match output.status.code() {
-            Some(4) => Ok(crate::ServiceStatus::NotInstalled),
-            Some(3) => Ok(crate::ServiceStatus::Stopped(None)),
-            Some(0) => Ok(crate::ServiceStatus::Running),
-            _ => Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Command failed with exit code {}: {}",
-                    output.status.code().unwrap_or(-1),
-                    String::from_utf8_lossy(&output.stderr)
-                ),
-            )),
+        if self.config.root.is_some() {
+            let script_name = self.naming.native_name(&ctx.label);
+            let script_path = self.dir_path()?.join(format!("{script_name}.service"));
+            return Ok(utils::offline_status(&script_path));
+        }
+
+        let command = self.unit_command("status", &ctx.label);
+        status_from_output(utils::output(&self.runner, &command)?)
+    }
+
+    fn status_detail(&self, ctx: crate::ServiceStatusCtx) -> io::Result<ServiceStatusDetail> {
+        if self.config.root.is_some() {
+            return Ok(self.status(ctx)?.into());
+        }
+
+        let command = self.show_command(&ctx.label, true);
+        let mut output = utils::output(&self.runner, &command)?;
+
+        // Versions of systemd before 248 do not support `--timestamp`, in which case the time
+        // the service became active cannot be parsed and is left out
+        if !output.status.success() {
+            let command = self.show_command(&ctx.label, false);
+            output = wrap_output(utils::output(&self.runner, &command)?)?;
+        }
+
+        Ok(parse_show(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn list(&self, ctx: crate::ServiceListCtx) -> io::Result<Vec<crate::InstalledService>> {
+        let dir_path = self.dir_path()?;
+
+        let mut services = Vec::new();
+        let units = unit_definitions(&dir_path, utils::list_dir_names(&dir_path, false)?);
+        for label in utils::definition_labels(units, &self.naming, &ctx) {
+            let status = self.status(crate::ServiceStatusCtx {
+                label: label.clone(),
+            })?;
+            services.push(crate::InstalledService { label, status });
         }
+
+        Ok(services)
+    }
+}
+
+/// Interprets the exit code of `systemctl status`
+fn status_from_output(output: CommandOutput) -> io::Result<crate::ServiceStatus> {
+    // ref: https://www.freedesktop.org/software/systemd/man/latest/systemctl.html#Exit%20status
+    match output.status.code() {
+        Some(4) => Ok(crate::ServiceStatus::NotInstalled),
+        Some(3) => Ok(crate::ServiceStatus::Stopped(None)),
+        Some(0) => Ok(crate::ServiceStatus::Running),
+        _ => Err(utils::command_failed(output).into()),
+    }
+}
+
+/// Interprets the output of `systemctl is-enabled`, which exits with a non-zero code for units
+/// that are not enabled
+fn enabled_from_output(output: CommandOutput) -> io::Result<bool> {
+    // ref: https://www.freedesktop.org/software/systemd/man/latest/systemctl.html#is-enabled%20UNIT%E2%80%A6
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match stdout.lines().next().map(str::trim) {
+        Some("enabled" | "enabled-runtime" | "alias") => Ok(true),
+        Some(state) if !state.is_empty() => Ok(false),
+        _ => Err(utils::command_failed(output).into()),
     }
 }
 
-fn systemctl(cmd: &str, label: &str, user: bool) -> io::Result<Output> {
-    let mut command = Command::new(SYSTEMCTL);
+/// Names and paths of the services whose unit files within `dir_path` are named `file_names`
+fn unit_definitions(dir_path: &Path, file_names: Vec<String>) -> Vec<(String, PathBuf)> {
+    file_names
+        .iter()
+        // Template units (e.g. `foo@.service`) cannot be managed without an instance name
+        .filter_map(|file_name| match file_name.strip_suffix(".service") {
+            Some(name) if !name.contains('@') => Some((name.to_string(), dir_path.join(file_name))),
+            _ => None,
+        })
+        .collect()
+}
 
-    command
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
+fn systemctl_command<S: Into<OsString>>(
+    cmd: &str,
+    args: impl IntoIterator<Item = S>,
+    user: bool,
+    root: Option<&Path>,
+) -> ServiceCommand {
+    let mut command = ServiceCommand::new(SYSTEMCTL);
 
-    if user {
-        command.arg("--user");
+    match root {
+        Some(root) => {
+            let mut root_arg = OsString::from("--root=");
+            root_arg.push(root);
+            command = command.arg(root_arg);
+
+            // There is no user manager to talk to within a root, so user units are enabled
+            // for every user instead
+            if user {
+                command = command.arg("--global");
+            }
+        }
+        None if user => command = command.arg("--user"),
+        None => {}
+    }
+
+    command.arg(cmd).args(args)
+}
+
+/// Parses the properties printed by `systemctl show` into a detailed status
+fn parse_show(stdout: &str) -> ServiceStatusDetail {
+    let properties: HashMap<&str, &str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .collect();
+    let property = |name: &str| properties.get(name).copied().unwrap_or_default();
+
+    if property("LoadState") == "not-found" {
+        return ServiceStatusDetail {
+            native_state: Some(property("LoadState").to_string()),
+            ..ServiceStatusDetail::new(ServiceState::NotInstalled)
+        };
     }
 
-    command.arg(cmd).arg(label).output()
+    let state = match property("ActiveState") {
+        "active" | "reloading" => ServiceState::Running,
+        "activating" => ServiceState::Starting,
+        "deactivating" => ServiceState::Stopping,
+        "inactive" => ServiceState::Stopped,
+        "failed" => ServiceState::Failed,
+        _ => ServiceState::Unknown,
+    };
+
+    // ExecMainCode holds the si_code of the main process: CLD_EXITED (1) means that
+    // ExecMainStatus is an exit code, CLD_KILLED (2) and CLD_DUMPED (3) that it is a signal
+    let exec_main_status = property("ExecMainStatus").parse::<i32>().ok();
+    let exit_code = match property("ExecMainCode") {
+        "1" => exec_main_status,
+        "2" | "3" => exec_main_status.map(|signal| 128 + signal),
+        _ => None,
+    };
+
+    // Timestamps are printed as `@{seconds}` when using `--timestamp=unix`
+    let active_since = match state {
+        ServiceState::Running => property("ActiveEnterTimestamp")
+            .strip_prefix('@')
+            .and_then(|secs| secs.parse::<u64>().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        _ => None,
+    };
+
+    ServiceStatusDetail {
+        state,
+        pid: property("MainPID").parse().ok().filter(|pid| *pid != 0),
+        exit_code,
+        active_since,
+        restart_count: property("NRestarts").parse().ok(),
+        native_state: Some(format!(
+            "{} ({})",
+            property("ActiveState"),
+            property("SubState")
+        )),
+    }
 }
 
 #[inline]
@@ -261,10 +623,9 @@ fn make_service(
     description: &str,
     ctx: &ServiceInstallCtx,
     user: bool,
-    autostart: bool,
     disable_restart_on_failure: bool,
     requires_network: bool,
-) -> String {
+) -> io::Result<String> {
     use std::fmt::Write as _;
     let SystemdInstallConfig {
         start_limit_interval_sec,
@@ -275,7 +636,11 @@ fn make_service(
 
     let mut service = String::new();
     let _ = writeln!(service, "[Unit]");
-    let _ = writeln!(service, "Description={description}");
+    let _ = writeln!(
+        service,
+        "Description={}",
+        unit_value("Description", description)?
+    );
 
     if requires_network {
         // delay the start of this service until after networking has been started
@@ -284,6 +649,19 @@ fn make_service(
         let _ = writeln!(service, "Requires=network-online.target");
     }
 
+    for dependency in &ctx.dependencies {
+        let directive = match dependency.kind {
+            DependencyKind::Requires => "Requires",
+            DependencyKind::Wants => "Wants",
+            DependencyKind::After => "After",
+            DependencyKind::Before => "Before",
+            DependencyKind::Conflicts => "Conflicts",
+        };
+        validate_dependency_name("systemd", &dependency.service)?;
+        let unit = dependency_unit_name(&dependency.service);
+        let _ = writeln!(service, "{directive}={unit}");
+    }
+
     if let Some(x) = start_limit_interval_sec {
         let _ = writeln!(service, "StartLimitIntervalSec={x}");
     }
@@ -297,25 +675,55 @@ fn make_service(
         let _ = writeln!(
             service,
             "WorkingDirectory={}",
-            working_directory.to_string_lossy()
+            unit_value(
+                "WorkingDirectory",
+                utils::require_utf8(working_directory.as_os_str(), "The working directory")?
+            )?
         );
     }
 
     if let Some(env_vars) = &ctx.environment {
         for (var, val) in env_vars {
+            if !utils::is_valid_env_name(var) {
+                return Err(Error::InvalidDefinition(format!(
+                    "systemd is unable to set the environment variable {var:?}, as names may \
+                     only hold letters, digits and underscores and may not start with a digit"
+                ))
+                .into());
+            }
+            let val = escape_unit_string("Environment", val, false)?;
             let _ = writeln!(service, "Environment=\"{var}={val}\"");
         }
     }
 
-    let program = ctx.program.to_string_lossy();
-    let args = ctx
-        .args
-        .clone()
-        .into_iter()
-        .map(|a| a.to_string_lossy().to_string())
-        .collect::<Vec<String>>()
-        .join(" ");
-    let _ = writeln!(service, "ExecStart={program} {args}");
+    let _ = writeln!(service, "ExecStart={}", exec_command_line(ctx)?);
+
+    if let Some(x) = output_directive(&ctx.stdout)? {
+        let _ = writeln!(service, "StandardOutput={x}");
+    }
+    if let Some(x) = output_directive(&ctx.stderr)? {
+        let _ = writeln!(service, "StandardError={x}");
+    }
+    if let Some(identifier) = syslog_identifier(&ctx.stdout, &ctx.stderr)? {
+        logs::validate_syslog_identifier("systemd", identifier)?;
+        let _ = writeln!(service, "SyslogIdentifier={identifier}");
+    }
+
+    if let Some(x) = ctx.limits.memory_max {
+        let _ = writeln!(service, "MemoryMax={x}");
+    }
+    if let Some(x) = ctx.limits.nofile {
+        let _ = writeln!(service, "LimitNOFILE={x}");
+    }
+    if let Some(x) = ctx.limits.nproc {
+        let _ = writeln!(service, "LimitNPROC={x}");
+    }
+    if let Some(x) = ctx.limits.core_size {
+        let _ = writeln!(service, "LimitCORE={x}");
+    }
+    if let Some(x) = ctx.limits.cpu_quota {
+        let _ = writeln!(service, "CPUQuota={x}%");
+    }
 
     if !disable_restart_on_failure {
         if *restart != SystemdServiceRestartType::No {
@@ -333,17 +741,756 @@ fn make_service(
     // only applies for a system-level service that doesn't run as root.
     if !user {
         if let Some(username) = &ctx.username {
-            let _ = writeln!(service, "User={username}");
+            let _ = writeln!(service, "User={}", unit_value("User", username)?);
         }
     }
 
-    if user && autostart {
-        let _ = writeln!(service, "[Install]");
+    // The install section is always written so that the service can be enabled later on, while
+    // autostart decides whether it is enabled during the install
+    let _ = writeln!(service, "[Install]");
+    if user {
         let _ = writeln!(service, "WantedBy=default.target");
-    } else if autostart {
-        let _ = writeln!(service, "[Install]");
+    } else {
         let _ = writeln!(service, "WantedBy=multi-user.target");
     }
 
-    service.trim().to_string()
+    Ok(service.trim().to_string())
+}
+
+/// Produces the command line of `ExecStart=` running the program of `ctx` with its arguments
+///
+/// Each word is quoted as needed, with specifiers and variables escaped so that the program
+/// receives its arguments exactly as they are in `ctx`.
+fn exec_command_line(ctx: &ServiceInstallCtx) -> io::Result<String> {
+    let program = utils::os_str_bytes(ctx.program.as_os_str(), "The program")?;
+
+    // systemd strips these from the program after unquoting it, as they change how it is run
+    if program.first().map_or(false, |b| b"@-:+!|".contains(b)) {
+        return Err(Error::InvalidDefinition(format!(
+            "systemd is unable to run {:?}, as it starts with a prefix of ExecStart=",
+            String::from_utf8_lossy(&program)
+        ))
+        .into());
+    }
+
+    let mut words = vec![quote_unit_word("ExecStart", &program)?];
+    for arg in ctx.args_iter() {
+        let arg = utils::os_str_bytes(arg, "The argument")?;
+        words.push(quote_unit_word("ExecStart", &arg)?);
+    }
+    Ok(words.join(" "))
+}
+
+/// Quotes `word` as a single word of a command line in a unit file, leaving it as is if it only
+/// holds characters without any special meaning
+///
+/// Bytes that are not valid UTF-8 are written as `\xNN` escapes, which systemd turns back into
+/// the same bytes.
+fn quote_unit_word(directive: &str, word: &[u8]) -> io::Result<String> {
+    use std::fmt::Write as _;
+    let plain = |b: &u8| b.is_ascii_alphanumeric() || b"-_./:,+@=".contains(b);
+    if !word.is_empty() && word.iter().all(plain) {
+        return Ok(String::from_utf8_lossy(word).into_owned());
+    }
+
+    let mut quoted = String::from("\"");
+    let mut rest = word;
+    while !rest.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(rest) {
+            Ok(_) => (rest.len(), 0),
+            Err(x) => (
+                x.valid_up_to(),
+                x.error_len().unwrap_or(rest.len() - x.valid_up_to()),
+            ),
+        };
+        let (text, tail) = rest.split_at(valid);
+        quoted.push_str(&escape_unit_string(
+            directive,
+            &String::from_utf8_lossy(text),
+            true,
+        )?);
+        for b in &tail[..invalid] {
+            let _ = write!(quoted, "\\x{b:02x}");
+        }
+        rest = &tail[invalid..];
+    }
+    quoted.push('"');
+    Ok(quoted)
+}
+
+/// Escapes `value` to be placed between double quotes in a unit file, using C-style escapes for
+/// quotes, backslashes and control characters and doubling `%` to keep it from starting a
+/// specifier
+///
+/// With `variables`, `$` is doubled as well to keep it from being expanded, as done by
+/// `ExecStart=`. A NUL character cannot be represented and is rejected.
+fn escape_unit_string(directive: &str, value: &str, variables: bool) -> io::Result<String> {
+    use std::fmt::Write as _;
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\0' => {
+                return Err(Error::InvalidDefinition(format!(
+                    "systemd is unable to represent a NUL character in {directive}="
+                ))
+                .into())
+            }
+            '%' => escaped.push_str("%%"),
+            '$' if variables => escaped.push_str("$$"),
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c.is_ascii_control() => {
+                let _ = write!(escaped, "\\x{:02x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    Ok(escaped)
+}
+
+/// Escapes `value` to be the whole value of `directive`, which is taken as is apart from
+/// specifiers
+///
+/// Such values have no quoting, so line breaks, a trailing backslash continuing the line and
+/// surrounding whitespace, which is trimmed, cannot be represented and are rejected.
+fn unit_value(directive: &str, value: &str) -> io::Result<String> {
+    if value.contains(&['\0', '\n', '\r'][..]) || value.ends_with('\\') || value.trim() != value {
+        return Err(Error::InvalidDefinition(format!(
+            "systemd is unable to represent {value:?} as the value of {directive}="
+        ))
+        .into());
+    }
+    Ok(value.replace('%', "%%"))
+}
+
+/// Name of the unit a dependency on `service` refers to, which is a service unless it names
+/// another type of unit such as `network-online.target`
+fn dependency_unit_name(service: &str) -> String {
+    const UNIT_TYPES: &[&str] = &[
+        "service",
+        "socket",
+        "device",
+        "mount",
+        "automount",
+        "swap",
+        "target",
+        "path",
+        "timer",
+        "slice",
+        "scope",
+    ];
+
+    match service.rsplit_once('.') {
+        Some((_, suffix)) if UNIT_TYPES.contains(&suffix) => service.to_string(),
+        _ => format!("{service}.service"),
+    }
+}
+
+/// Value of `StandardOutput=` or `StandardError=` sending a stream to `output`, which is left
+/// out when the stream is inherited
+///
+/// The journal forwards to syslog where one is running, so it stands in for the deprecated
+/// `syslog` value.
+fn output_directive(output: &ServiceOutput) -> io::Result<Option<String>> {
+    let path = |path: &Path| {
+        let path = utils::require_utf8(path.as_os_str(), "The log file")?;
+        unit_value("StandardOutput", path)
+    };
+    Ok(match output {
+        ServiceOutput::Inherit => None,
+        ServiceOutput::Null => Some("null".to_string()),
+        ServiceOutput::File(x) => Some(format!("truncate:{}", path(x)?)),
+        ServiceOutput::Append(x) => Some(format!("append:{}", path(x)?)),
+        ServiceOutput::Syslog { .. } => Some("journal".to_string()),
+    })
+}
+
+/// Identifier tagging the lines that the streams send to syslog, which systemd shares between
+/// stdout and stderr
+fn syslog_identifier<'a>(
+    stdout: &'a ServiceOutput,
+    stderr: &'a ServiceOutput,
+) -> io::Result<Option<&'a str>> {
+    let identifier = |output: &'a ServiceOutput| match output {
+        ServiceOutput::Syslog { identifier } => identifier.as_deref(),
+        _ => None,
+    };
+
+    match (identifier(stdout), identifier(stderr)) {
+        (Some(a), Some(b)) if a != b => Err(Error::Unsupported(format!(
+            "systemd tags stdout and stderr with the same SyslogIdentifier, not {a} and {b}"
+        ))
+        .into()),
+        (a, b) => Ok(a.or(b)),
+    }
+}
+
+/// Paths of the files that the unit file `unit` sends stdout and stderr to
+fn unit_log_paths(unit: &str) -> Vec<PathBuf> {
+    unit.lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            line.strip_prefix("StandardOutput=")
+                .or_else(|| line.strip_prefix("StandardError="))
+        })
+        .filter_map(|value| {
+            ["file:", "append:", "truncate:"]
+                .into_iter()
+                .find_map(|prefix| value.strip_prefix(prefix))
+        })
+        .map(|path| PathBuf::from(path.replace("%%", "%")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DiffState, FakeCommandRunner, ResourceLimits, ServiceDependency};
+    use indoc::indoc;
+
+    fn echo_ctx() -> ServiceInstallCtx {
+        ServiceInstallCtx {
+            label: "com.example.echo".parse().unwrap(),
+            program: PathBuf::from("/usr/local/bin/echo"),
+            args: vec!["hello".into()],
+            contents: None,
+            username: None,
+            working_directory: None,
+            environment: None,
+            autostart: true,
+            disable_restart_on_failure: false,
+            requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: ResourceLimits::default(),
+            dependencies: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_system_service() {
+        let rendered = SystemdServiceManager::system().render(&echo_ctx()).unwrap();
+        assert_eq!(rendered.files.len(), 1);
+
+        let file = &rendered.files[0];
+        assert_eq!(
+            file.path,
+            PathBuf::from("/etc/systemd/system/example-echo.service")
+        );
+        assert_eq!(file.mode, 0o644);
+        let contents = String::from_utf8(file.contents.clone()).unwrap();
+        assert!(contents.contains("ExecStart=/usr/local/bin/echo hello"));
+        assert!(contents.contains("WantedBy=multi-user.target"));
+
+        assert_eq!(
+            rendered.commands,
+            vec![ServiceCommand::new("systemctl")
+                .arg("enable")
+                .arg("/etc/systemd/system/example-echo.service")]
+        );
+    }
+
+    #[test]
+    fn test_render_into_root() {
+        let manager = SystemdServiceManager::user().with_config(SystemdConfig {
+            root: Some(PathBuf::from("/mnt/image")),
+            ..Default::default()
+        });
+
+        let rendered = manager.render(&echo_ctx()).unwrap();
+        assert_eq!(
+            rendered.files[0].path,
+            PathBuf::from("/mnt/image/etc/systemd/user/example-echo.service")
+        );
+        assert_eq!(
+            rendered.commands,
+            vec![ServiceCommand::new("systemctl")
+                .arg("--root=/mnt/image")
+                .arg("--global")
+                .arg("enable")
+                .arg("example-echo.service")]
+        );
+    }
+
+    #[test]
+    fn test_install_into_root_with_runner() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let runner = FakeCommandRunner::new();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(runner.clone());
+
+        manager.install(echo_ctx()).unwrap();
+
+        let script_path = root.path().join("etc/systemd/system/example-echo.service");
+        assert!(script_path.is_file());
+        assert_eq!(runner.commands().len(), 1);
+        assert_eq!(
+            runner.commands()[0].args.last().unwrap(),
+            "example-echo.service"
+        );
+    }
+
+    #[test]
+    fn test_install_or_update_into_root() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let runner = FakeCommandRunner::new();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(runner.clone());
+
+        assert_eq!(
+            manager.install_or_update(echo_ctx()).unwrap(),
+            InstallOutcome::Installed
+        );
+        assert_eq!(
+            manager.install_or_update(echo_ctx()).unwrap(),
+            InstallOutcome::Unchanged
+        );
+
+        let mut ctx = echo_ctx();
+        ctx.args = vec![OsString::from("goodbye")];
+        assert_eq!(
+            manager.install_or_update(ctx).unwrap(),
+            InstallOutcome::Updated { restarted: false }
+        );
+
+        // Only the initial install enables the unit, and nothing is reloaded within a root
+        let script_path = root.path().join("etc/systemd/system/example-echo.service");
+        assert!(std::fs::read_to_string(script_path)
+            .unwrap()
+            .contains("goodbye"));
+        assert_eq!(runner.commands().len(), 1);
+    }
+
+    #[test]
+    fn test_diff_detects_hand_edits() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(FakeCommandRunner::new());
+
+        assert_eq!(manager.diff(&echo_ctx()).unwrap().state, DiffState::Missing);
+        manager.install(echo_ctx()).unwrap();
+        assert_eq!(
+            manager.diff(&echo_ctx()).unwrap().state,
+            DiffState::Identical
+        );
+
+        let script_path = root.path().join("etc/systemd/system/example-echo.service");
+        let edited = std::fs::read_to_string(&script_path)
+            .unwrap()
+            .replace("echo hello", "echo edited");
+        std::fs::write(&script_path, edited).unwrap();
+
+        let diff = manager.diff(&echo_ctx()).unwrap();
+        assert_eq!(diff.state, DiffState::Differs);
+        assert!(diff.unified().contains(
+            "-ExecStart=/usr/local/bin/echo edited\n+ExecStart=/usr/local/bin/echo hello\n"
+        ));
+    }
+
+    #[test]
+    fn test_rollback_restores_previous_unit() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(FakeCommandRunner::new());
+        let label: ServiceLabel = "com.example.echo".parse().unwrap();
+
+        manager.install(echo_ctx()).unwrap();
+        let err = manager
+            .rollback(ServiceRollbackCtx::new(label.clone()))
+            .unwrap_err();
+        assert!(matches!(
+            Error::from_io_error(&err),
+            Some(Error::NoBackup(_))
+        ));
+
+        let script_path = root.path().join("etc/systemd/system/example-echo.service");
+        let original = std::fs::read_to_string(&script_path).unwrap();
+        let mut ctx = echo_ctx();
+        ctx.args = vec![OsString::from("goodbye")];
+        manager.install_or_update(ctx).unwrap();
+        assert_ne!(std::fs::read_to_string(&script_path).unwrap(), original);
+
+        // The backup is hidden from listing, and is what the unit is rolled back to
+        assert_eq!(
+            manager
+                .list(crate::ServiceListCtx::default())
+                .unwrap()
+                .len(),
+            1
+        );
+        manager.rollback(ServiceRollbackCtx::new(label)).unwrap();
+        assert_eq!(std::fs::read_to_string(&script_path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_install_detects_name_conflicts() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(FakeCommandRunner::new());
+
+        manager.install(echo_ctx()).unwrap();
+
+        // Reinstalling the same label is fine, while another qualifier maps to the same unit
+        manager.render(&echo_ctx()).unwrap();
+        let mut ctx = echo_ctx();
+        ctx.label = "org.example.echo".parse().unwrap();
+        let err = manager.render(&ctx).unwrap_err();
+        assert!(matches!(
+            Error::from_io_error(&err),
+            Some(Error::NameConflict { name, existing })
+                if name == "example-echo" && existing == "com.example.echo"
+        ));
+
+        // The qualified name keeps both apart
+        let manager = manager.with_naming(ServiceNaming::QualifiedName);
+        manager.install(ctx).unwrap();
+
+        let labels: Vec<String> = manager
+            .list(crate::ServiceListCtx::default())
+            .unwrap()
+            .into_iter()
+            .map(|service| service.label.to_qualified_name())
+            .collect();
+        assert_eq!(labels.len(), 2);
+        assert!(labels.contains(&"com.example.echo".to_string()));
+        assert!(labels.contains(&"org.example.echo".to_string()));
+    }
+
+    #[test]
+    fn test_enable_and_disable_into_root() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let runner = FakeCommandRunner::new();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(runner.clone());
+
+        let mut ctx = echo_ctx();
+        ctx.autostart = false;
+        manager.install(ctx).unwrap();
+        assert!(runner.commands().is_empty());
+
+        let label: ServiceLabel = "com.example.echo".parse().unwrap();
+        manager
+            .enable(ServiceEnableCtx::new(label.clone()))
+            .unwrap();
+        manager
+            .disable(ServiceDisableCtx::new(label.clone()))
+            .unwrap();
+
+        runner.push_output(0, "enabled\n", "");
+        runner.push_output(1, "disabled\n", "");
+        let status_ctx = crate::ServiceStatusCtx::new(label);
+        assert!(manager.is_enabled(status_ctx.clone()).unwrap());
+        assert!(!manager.is_enabled(status_ctx).unwrap());
+
+        let mut root_arg = OsString::from("--root=");
+        root_arg.push(root.path());
+        let commands: Vec<_> = ["enable", "disable", "is-enabled", "is-enabled"]
+            .into_iter()
+            .map(|cmd| {
+                ServiceCommand::new("systemctl")
+                    .arg(&root_arg)
+                    .arg(cmd)
+                    .arg("example-echo.service")
+            })
+            .collect();
+        assert_eq!(runner.commands(), commands);
+
+        let err = manager
+            .enable(ServiceEnableCtx::new("com.example.other".parse().unwrap()))
+            .unwrap_err();
+        assert!(matches!(
+            Error::from_io_error(&err),
+            Some(Error::NotInstalled(_))
+        ));
+    }
+
+    #[test]
+    fn test_render_dependencies() {
+        let manager = SystemdServiceManager::system();
+        let mut ctx = echo_ctx();
+        ctx.dependencies = vec![
+            ServiceDependency::requires("rabbitmq-server"),
+            ServiceDependency::after("rabbitmq-server.service"),
+            ServiceDependency::wants("network-online.target"),
+            ServiceDependency::before("example-consumer"),
+            ServiceDependency::conflicts("example-legacy"),
+        ];
+
+        let rendered = manager.render(&ctx).unwrap();
+        let unit = String::from_utf8_lossy(&rendered.files[0].contents);
+        assert!(unit.contains(
+            "Requires=rabbitmq-server.service\n\
+             After=rabbitmq-server.service\n\
+             Wants=network-online.target\n\
+             Before=example-consumer.service\n\
+             Conflicts=example-legacy.service\n"
+        ));
+
+        for service in ["a\nExecStartPre=/bin/evil", "a b", "50%"] {
+            ctx.dependencies = vec![ServiceDependency::requires(service)];
+            let err = manager.render(&ctx).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn test_render_quotes_and_escapes_values() {
+        let manager = SystemdServiceManager::system();
+        let mut ctx = echo_ctx();
+        ctx.program = PathBuf::from("/opt/my app/echo");
+        ctx.args = vec![
+            "plain-arg=1".into(),
+            "two words".into(),
+            "it's \"quoted\"".into(),
+            "$HOME".into(),
+            "100%".into(),
+            "back\\slash".into(),
+            ";".into(),
+            "".into(),
+            "line\nbreak\u{1}".into(),
+        ];
+        ctx.working_directory = Some(PathBuf::from("/srv/100% data"));
+        ctx.environment = Some(vec![(
+            "GREETING".to_string(),
+            "hello \"world\" $USER 50%\n".to_string(),
+        )]);
+
+        let rendered = manager.render(&ctx).unwrap();
+        let unit = String::from_utf8_lossy(&rendered.files[0].contents);
+        assert!(unit.contains(
+            r#"ExecStart="/opt/my app/echo" plain-arg=1 "two words" "it's \"quoted\"" "$$HOME" "100%%" "back\\slash" ";" "" "line\nbreak\x01""#
+        ));
+        assert!(unit.contains("WorkingDirectory=/srv/100%% data\n"));
+        assert!(unit.contains(r#"Environment="GREETING=hello \"world\" $USER 50%%\n""#));
+
+        ctx.args = vec!["nul\0".into()];
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.args = Vec::new();
+        ctx.environment = Some(vec![("2FA".to_string(), "on".to_string())]);
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.environment = None;
+        ctx.working_directory = Some(PathBuf::from("/srv/data\n"));
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.working_directory = None;
+        ctx.username = Some("nobody\nExecStartPre=/bin/touch /tmp/pwned".to_string());
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        ctx.username = None;
+        ctx.program = PathBuf::from("-echo");
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_render_preserves_bytes_of_arguments() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let manager = SystemdServiceManager::system();
+        let mut ctx = echo_ctx();
+        ctx.args = vec![OsString::from_vec(b"caf\xe9 \xc3\xa9".to_vec())];
+
+        let rendered = manager.render(&ctx).unwrap();
+        let unit = String::from_utf8(rendered.files[0].contents.clone()).unwrap();
+        assert!(unit.contains("ExecStart=/usr/local/bin/echo \"caf\\xe9 \u{e9}\"\n"));
+
+        // Paths that systemd takes as they are cannot be escaped
+        ctx.working_directory = Some(PathBuf::from(OsString::from_vec(b"/srv/\xff".to_vec())));
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_render_output_targets_and_limits() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = SystemdServiceManager::system().with_config(SystemdConfig {
+            root: Some(root.path().to_path_buf()),
+            ..Default::default()
+        });
+
+        let mut ctx = echo_ctx();
+        ctx.stdout = ServiceOutput::Append(PathBuf::from("/var/log/echo.log"));
+        ctx.stderr = ServiceOutput::syslog("echo");
+        ctx.limits = ResourceLimits::new()
+            .with_memory_max(1 << 30)
+            .with_nofile(4096)
+            .with_nproc(64)
+            .with_core_size(0)
+            .with_cpu_quota(150);
+        let rendered = manager.render(&ctx).unwrap();
+        let unit = String::from_utf8_lossy(&rendered.files[0].contents);
+        assert!(unit.contains(
+            "StandardOutput=append:/var/log/echo.log\n\
+             StandardError=journal\n\
+             SyslogIdentifier=echo\n\
+             MemoryMax=1073741824\n\
+             LimitNOFILE=4096\n\
+             LimitNPROC=64\n\
+             LimitCORE=0\n\
+             CPUQuota=150%\n"
+        ));
+
+        ctx.stdout = ServiceOutput::syslog("other");
+        let err = manager.render(&ctx).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        for identifier in ["echo\nExecStartPre=/bin/evil", "two words", "%n"] {
+            ctx.stdout = ServiceOutput::syslog(identifier);
+            ctx.stderr = ctx.stdout.clone();
+            let err = manager.render(&ctx).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        // Output sent to files is read back from them rather than from the journal
+        ctx.stdout = ServiceOutput::File(PathBuf::from("/var/log/echo.log"));
+        ctx.stderr = ServiceOutput::Null;
+        manager.install(ctx.clone()).unwrap();
+        std::fs::create_dir_all(root.path().join("var/log")).unwrap();
+        std::fs::write(root.path().join("var/log/echo.log"), "hello\n").unwrap();
+
+        let lines: Vec<String> = manager
+            .logs(ctx.label, LogQuery::new())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, ["hello"]);
+    }
+
+    #[test]
+    fn test_logs_are_read_from_the_journal() {
+        let runner = FakeCommandRunner::new();
+        runner.push_output(0, "hello\nworld\n", "");
+        runner.push_output(1, "", "Failed to open journal");
+        let manager = SystemdServiceManager::user().with_runner(runner.clone());
+        let label: ServiceLabel = "com.example.echo".parse().unwrap();
+
+        let since = UNIX_EPOCH + Duration::from_secs(1700000000);
+        let query = LogQuery::new().with_lines(20).with_since(since);
+        let lines: Vec<String> = manager
+            .logs(label.clone(), query)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(lines, ["hello", "world"]);
+
+        let mut lines = manager.logs(label, LogQuery::new()).unwrap();
+        assert!(lines.next().unwrap().is_err());
+
+        let commands: Vec<String> = runner.commands().iter().map(|x| x.to_string()).collect();
+        assert_eq!(
+            commands,
+            [
+                "journalctl --user-unit=example-echo.service --no-pager --output=cat --lines=20 \
+                 --since=@1700000000",
+                "journalctl --user-unit=example-echo.service --no-pager --output=cat --lines=all",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_status_failure_is_reported() {
+        let runner = FakeCommandRunner::new();
+        runner.push_output(1, "", "Failed to connect to bus: Operation not permitted");
+        let manager = SystemdServiceManager::system().with_runner(runner);
+
+        let err = manager
+            .status(crate::ServiceStatusCtx {
+                label: "com.example.echo".parse().unwrap(),
+            })
+            .unwrap_err();
+        assert!(matches!(
+            Error::from_io_error(&err),
+            Some(Error::PermissionDenied(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_show_running() {
+        let stdout = indoc! {"
+            LoadState=loaded
+            ActiveState=active
+            SubState=running
+            MainPID=1234
+            ExecMainCode=0
+            ExecMainStatus=0
+            NRestarts=2
+            ActiveEnterTimestamp=@1700000000
+        "};
+
+        let detail = parse_show(stdout);
+        assert_eq!(detail.state, ServiceState::Running);
+        assert_eq!(detail.pid, Some(1234));
+        assert_eq!(detail.exit_code, None);
+        assert_eq!(
+            detail.active_since,
+            Some(UNIX_EPOCH + Duration::from_secs(1700000000))
+        );
+        assert_eq!(detail.restart_count, Some(2));
+        assert_eq!(detail.native_state.as_deref(), Some("active (running)"));
+    }
+
+    #[test]
+    fn test_parse_show_killed() {
+        let stdout = indoc! {"
+            LoadState=loaded
+            ActiveState=failed
+            SubState=failed
+            MainPID=0
+            ExecMainCode=2
+            ExecMainStatus=9
+            NRestarts=0
+            ActiveEnterTimestamp=@1700000000
+        "};
+
+        let detail = parse_show(stdout);
+        assert_eq!(detail.state, ServiceState::Failed);
+        assert_eq!(detail.pid, None);
+        assert_eq!(detail.exit_code, Some(137));
+        assert_eq!(detail.active_since, None);
+    }
+
+    #[test]
+    fn test_parse_show_not_found() {
+        let stdout = indoc! {"
+            LoadState=not-found
+            ActiveState=inactive
+            SubState=dead
+        "};
+
+        assert_eq!(parse_show(stdout).state, ServiceState::NotInstalled);
+    }
 }
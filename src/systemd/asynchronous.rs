@@ -0,0 +1,285 @@
+use super::{
+    enabled_from_output, parse_show, status_from_output, unit_definitions, SystemdServiceManager,
+    SERVICE_FILE_PERMISSIONS,
+};
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::utils::{self, nonblocking, wrap_output};
+use crate::{
+    Error, InstallOutcome, InstalledService, ServiceDiff, ServiceDisableCtx, ServiceEnableCtx,
+    ServiceInstallCtx, ServiceLabel, ServiceLevel, ServiceListCtx, ServiceRestartCtx,
+    ServiceRollbackCtx, ServiceStartCtx, ServiceStatus, ServiceStatusCtx, ServiceStatusDetail,
+    ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::{io, path::PathBuf};
+
+impl SystemdServiceManager {
+    /// Path of the unit file of the service with `label`, failing if it is not installed
+    async fn installed_unit_path_async(&self, label: &ServiceLabel) -> io::Result<PathBuf> {
+        let script_name = self.naming.native_name(label);
+        let script_path = self.dir_path()?.join(format!("{script_name}.service"));
+        if !nonblocking::exists(&script_path).await {
+            return Err(Error::NotInstalled(script_name).into());
+        }
+        Ok(script_path)
+    }
+}
+
+impl AsyncServiceManager for SystemdServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        Box::pin(async move { crate::ServiceManager::available(self) })
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, &ctx)?;
+            nonblocking::install_rendered(&self.runner, &rendered).await
+        })
+    }
+
+    fn diff<'a>(&'a self, ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, ctx)?;
+            nonblocking::diff_rendered(&rendered.files).await
+        })
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async move {
+            let reload = self.reload_commands();
+            nonblocking::install_or_update_rendered(self, &self.runner, ctx, &reload).await
+        })
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_name = self.naming.native_name(&ctx.label);
+            let script_path = self.dir_path()?.join(format!("{script_name}.service"));
+            if !nonblocking::exists(&script_path).await {
+                return Err(Error::NotInstalled(script_name).into());
+            }
+
+            let disable = self.unit_file_command("disable", &script_path);
+            wrap_output(nonblocking::output(&self.runner, &disable).await?)?;
+            nonblocking::remove_definition(&script_path).await
+        })
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "start")?;
+            let command = self.unit_command("start", &ctx.label);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "stop")?;
+            let command = self.unit_command("stop", &ctx.label);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            utils::ensure_no_root(self.config.root.as_deref(), "restart")?;
+            let command = self.unit_command("restart", &ctx.label);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_path = self.installed_unit_path_async(&ctx.label).await?;
+            let command = self.unit_file_command("enable", &script_path);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_path = self.installed_unit_path_async(&ctx.label).await?;
+            let command = self.unit_file_command("disable", &script_path);
+            wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            Ok(())
+        })
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            self.installed_unit_path_async(&ctx.label).await?;
+            let command = self.is_enabled_command(&ctx.label);
+            enabled_from_output(nonblocking::output(&self.runner, &command).await?)
+        })
+    }
+
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let script_path = self.installed_unit_path_async(&ctx.label).await?;
+            let script_name = self.naming.native_name(&ctx.label);
+            nonblocking::restore_backup(&script_path, &script_name, SERVICE_FILE_PERMISSIONS)
+                .await?;
+            for command in self.reload_commands() {
+                wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn level(&self) -> ServiceLevel {
+        crate::ServiceManager::level(self)
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        crate::ServiceManager::set_level(self, level)
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        Box::pin(async move {
+            if self.config.root.is_some() {
+                let script_name = self.naming.native_name(&ctx.label);
+                let script_path = self.dir_path()?.join(format!("{script_name}.service"));
+                return Ok(nonblocking::offline_status(&script_path).await);
+            }
+
+            let command = self.unit_command("status", &ctx.label);
+            status_from_output(nonblocking::output(&self.runner, &command).await?)
+        })
+    }
+
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        Box::pin(async move {
+            if self.config.root.is_some() {
+                return Ok(self.status(ctx).await?.into());
+            }
+
+            let command = self.show_command(&ctx.label, true);
+            let mut output = nonblocking::output(&self.runner, &command).await?;
+
+            // Versions of systemd before 248 do not support `--timestamp`
+            if !output.status.success() {
+                let command = self.show_command(&ctx.label, false);
+                output = wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+            }
+
+            Ok(parse_show(&String::from_utf8_lossy(&output.stdout)))
+        })
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async move {
+            let dir_path = self.dir_path()?;
+            let file_names = nonblocking::list_dir_names(&dir_path, false).await?;
+            let units = unit_definitions(&dir_path, file_names);
+
+            let mut services = Vec::new();
+            for label in nonblocking::definition_labels(units, &self.naming, &ctx).await {
+                let status = self
+                    .status(ServiceStatusCtx {
+                        label: label.clone(),
+                    })
+                    .await?;
+                services.push(InstalledService { label, status });
+            }
+
+            Ok(services)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeCommandRunner, ServiceCommand, SystemdConfig};
+    use std::path::PathBuf;
+
+    #[tokio::test]
+    async fn test_install_and_uninstall_into_root_with_runner() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let runner = FakeCommandRunner::new();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(runner.clone());
+
+        let label: crate::ServiceLabel = "com.example.echo".parse().unwrap();
+        manager
+            .install(ServiceInstallCtx {
+                label: label.clone(),
+                program: PathBuf::from("/usr/local/bin/echo"),
+                args: vec!["hello".into()],
+                contents: None,
+                username: None,
+                working_directory: None,
+                environment: None,
+                autostart: true,
+                disable_restart_on_failure: false,
+                requires_network: false,
+                stdout: crate::ServiceOutput::Inherit,
+                stderr: crate::ServiceOutput::Inherit,
+                limits: crate::ResourceLimits::default(),
+                dependencies: Vec::new(),
+            })
+            .await
+            .unwrap();
+
+        let script_path = root.path().join("etc/systemd/system/example-echo.service");
+        assert!(script_path.is_file());
+        assert_eq!(
+            manager
+                .status(ServiceStatusCtx {
+                    label: label.clone(),
+                })
+                .await
+                .unwrap(),
+            ServiceStatus::Stopped(None)
+        );
+
+        manager
+            .uninstall(ServiceUninstallCtx { label })
+            .await
+            .unwrap();
+        assert!(!script_path.exists());
+
+        let mut root_arg = std::ffi::OsString::from("--root=");
+        root_arg.push(root.path());
+        assert_eq!(
+            runner.commands(),
+            vec![
+                ServiceCommand::new("systemctl")
+                    .arg(&root_arg)
+                    .arg("enable")
+                    .arg("example-echo.service"),
+                ServiceCommand::new("systemctl")
+                    .arg(&root_arg)
+                    .arg("disable")
+                    .arg("example-echo.service"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_failure_reports_permission_denied() {
+        let runner = FakeCommandRunner::new();
+        runner.push_output(1, "", "Failed to connect to bus: Permission denied");
+        let manager = SystemdServiceManager::user().with_runner(runner.clone());
+
+        let err = manager
+            .status(ServiceStatusCtx {
+                label: "com.example.echo".parse().unwrap(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            runner.commands()[0].to_string(),
+            "systemctl --user status example-echo"
+        );
+    }
+}
@@ -0,0 +1,411 @@
+use super::{
+    Error, InstallOutcome, ServiceInstallCtx, ServiceLabel, ServiceManager, ServiceRollbackCtx,
+    ServiceStartCtx, ServiceStatus, ServiceStatusCtx, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::io;
+
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
+/// Operation applied to a service as a step of a [`ServiceTransaction`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[allow(clippy::large_enum_variant)]
+pub enum ServiceOperation {
+    /// Installs the service, or updates it if it is installed with a different definition
+    Install(ServiceInstallCtx),
+
+    /// Uninstalls the service
+    Uninstall(ServiceUninstallCtx),
+
+    /// Starts the service, unless it is already running
+    Start(ServiceStartCtx),
+
+    /// Stops the service, unless it is not running
+    Stop(ServiceStopCtx),
+}
+
+impl ServiceOperation {
+    /// Label of the service targeted by the operation
+    pub fn label(&self) -> &ServiceLabel {
+        match self {
+            Self::Install(ctx) => &ctx.label,
+            Self::Uninstall(ctx) => &ctx.label,
+            Self::Start(ctx) => &ctx.label,
+            Self::Stop(ctx) => &ctx.label,
+        }
+    }
+}
+
+/// Change made to a service by a [`ServiceTransaction`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum ServiceChange {
+    /// Service was installed
+    Installed,
+
+    /// Service was installed with a different definition, which was replaced
+    Updated,
+
+    /// Service definition was restored to the one it had before it was updated
+    RolledBack,
+
+    /// Service was uninstalled
+    Uninstalled,
+
+    /// Service was started
+    Started,
+
+    /// Service was stopped
+    Stopped,
+
+    /// Service was already in the requested state, so nothing was done
+    Unchanged,
+}
+
+/// Change made to a single service by a [`ServiceTransaction`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct TransactionStep {
+    /// Label of the service
+    pub label: ServiceLabel,
+
+    /// Change made to the service
+    pub change: ServiceChange,
+}
+
+/// Outcome of applying a [`ServiceTransaction`]
+#[derive(Debug, Default)]
+pub struct TransactionReport {
+    /// Steps that were applied, in the order they were applied, including the ones that were
+    /// undone afterwards
+    pub applied: Vec<TransactionStep>,
+
+    /// Steps that undid applied ones after a failure, in the order they were made
+    pub rolled_back: Vec<TransactionStep>,
+
+    /// Error of the operation that failed, which made the transaction roll back
+    pub error: Option<io::Error>,
+
+    /// Errors met while rolling back, for the steps that could not be undone
+    pub rollback_errors: Vec<io::Error>,
+}
+
+impl TransactionReport {
+    /// Returns true if every operation of the transaction was applied
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+
+    /// Returns true if the transaction failed, and every applied step was undone
+    pub fn is_rolled_back(&self) -> bool {
+        self.error.is_some() && self.rollback_errors.is_empty()
+    }
+}
+
+/// Set of operations applied to several services in order, undoing the applied ones in reverse
+/// order if one of them fails
+///
+/// Installs are applied through [`ServiceManager::install_or_update`], so they are undone by
+/// uninstalling a newly installed service, or by [`ServiceManager::rollback`] for an updated
+/// one. Starts and stops are undone by the opposite operation, unless the service was already in
+/// the requested state. Uninstalls cannot be undone, as the definition of the service is gone,
+/// so an uninstall may only be the last operation. A transaction with an uninstall followed by
+/// another operation fails with [`Error::Unsupported`] before applying anything.
+///
+/// ```no_run
+/// use service_manager::*;
+///
+/// let manager = <dyn ServiceManager>::native().expect("Failed to detect management platform");
+/// let broker: ServiceLabel = "com.example.broker".parse().unwrap();
+/// let consumer: ServiceLabel = "com.example.consumer".parse().unwrap();
+///
+/// let report = ServiceTransaction::new()
+///     .install(ServiceInstallCtx::builder(broker.clone(), "/usr/bin/broker").build())
+///     .install(ServiceInstallCtx::builder(consumer.clone(), "/usr/bin/consumer").build())
+///     .start(ServiceStartCtx::new(broker))
+///     .start(ServiceStartCtx::new(consumer))
+///     .apply(&*manager);
+///
+/// if let Some(x) = &report.error {
+///     eprintln!("Rolled back {} steps after failing: {x}", report.rolled_back.len());
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub struct ServiceTransaction {
+    /// Operations to apply, in order
+    pub operations: Vec<ServiceOperation>,
+}
+
+impl ServiceTransaction {
+    /// Creates a new transaction without any operation
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an operation to the end of the transaction
+    pub fn operation(mut self, operation: ServiceOperation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    /// Adds the install of a service to the end of the transaction
+    pub fn install(self, ctx: ServiceInstallCtx) -> Self {
+        self.operation(ServiceOperation::Install(ctx))
+    }
+
+    /// Adds the uninstall of a service to the end of the transaction
+    pub fn uninstall(self, ctx: ServiceUninstallCtx) -> Self {
+        self.operation(ServiceOperation::Uninstall(ctx))
+    }
+
+    /// Adds the start of a service to the end of the transaction
+    pub fn start(self, ctx: ServiceStartCtx) -> Self {
+        self.operation(ServiceOperation::Start(ctx))
+    }
+
+    /// Adds the stop of a service to the end of the transaction
+    pub fn stop(self, ctx: ServiceStopCtx) -> Self {
+        self.operation(ServiceOperation::Stop(ctx))
+    }
+
+    /// Applies the operations in order through `manager`, undoing the applied ones in reverse
+    /// order as soon as one fails
+    pub fn apply<M: ServiceManager + ?Sized>(self, manager: &M) -> TransactionReport {
+        let mut report = TransactionReport::default();
+        if let Err(x) = check_undoable(&self.operations) {
+            report.error = Some(x);
+            return report;
+        }
+
+        for operation in self.operations {
+            match apply_operation(manager, operation) {
+                Ok(step) => report.applied.push(step),
+                Err(x) => {
+                    report.error = Some(x);
+                    break;
+                }
+            }
+        }
+
+        if report.error.is_some() {
+            for step in report.applied.iter().rev() {
+                match undo_step(manager, step) {
+                    Ok(Some(step)) => report.rolled_back.push(step),
+                    Ok(None) => {}
+                    Err(x) => report.rollback_errors.push(x),
+                }
+            }
+        }
+
+        report
+    }
+}
+
+/// Applies a single operation, returning the change it made
+fn apply_operation<M: ServiceManager + ?Sized>(
+    manager: &M,
+    operation: ServiceOperation,
+) -> io::Result<TransactionStep> {
+    let label = operation.label().clone();
+    let change = match operation {
+        ServiceOperation::Install(ctx) => match manager.install_or_update(ctx)? {
+            InstallOutcome::Installed => ServiceChange::Installed,
+            InstallOutcome::Updated { .. } => ServiceChange::Updated,
+            InstallOutcome::Unchanged => ServiceChange::Unchanged,
+        },
+        ServiceOperation::Uninstall(ctx) => {
+            manager.uninstall(ctx)?;
+            ServiceChange::Uninstalled
+        }
+        ServiceOperation::Start(ctx) => {
+            if is_running(manager, &label)? {
+                ServiceChange::Unchanged
+            } else {
+                manager.start(ctx)?;
+                ServiceChange::Started
+            }
+        }
+        ServiceOperation::Stop(ctx) => {
+            if is_running(manager, &label)? {
+                manager.stop(ctx)?;
+                ServiceChange::Stopped
+            } else {
+                ServiceChange::Unchanged
+            }
+        }
+    };
+
+    Ok(TransactionStep { label, change })
+}
+
+/// Undoes the change made by an applied step, returning the change that undid it if any
+fn undo_step<M: ServiceManager + ?Sized>(
+    manager: &M,
+    step: &TransactionStep,
+) -> io::Result<Option<TransactionStep>> {
+    let label = step.label.clone();
+    let change = match step.change {
+        ServiceChange::Installed => {
+            manager.uninstall(ServiceUninstallCtx::new(label.clone()))?;
+            ServiceChange::Uninstalled
+        }
+        ServiceChange::Updated => {
+            manager.rollback(ServiceRollbackCtx::new(label.clone()))?;
+            ServiceChange::RolledBack
+        }
+        ServiceChange::Started => {
+            manager.stop(ServiceStopCtx::new(label.clone()))?;
+            ServiceChange::Stopped
+        }
+        ServiceChange::Stopped => {
+            manager.start(ServiceStartCtx::new(label.clone()))?;
+            ServiceChange::Started
+        }
+        ServiceChange::Uninstalled => return Err(uninstall_not_undoable(&label)),
+        ServiceChange::RolledBack | ServiceChange::Unchanged => return Ok(None),
+    };
+
+    Ok(Some(TransactionStep { label, change }))
+}
+
+/// Checks that every applied operation can be undone should a later one fail, which rules out
+/// an uninstall followed by any other operation
+fn check_undoable(operations: &[ServiceOperation]) -> io::Result<()> {
+    let followed = operations.split_last().map_or(&[][..], |(_, rest)| rest);
+    match followed.iter().find_map(|x| match x {
+        ServiceOperation::Uninstall(ctx) => Some(&ctx.label),
+        _ => None,
+    }) {
+        Some(label) => Err(Error::Unsupported(format!(
+            "Unable to undo the uninstall of {label} should a later operation fail, so it must be \
+             the last operation of the transaction"
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Error reported when rolling back the uninstall of the service with `label`
+fn uninstall_not_undoable(label: &ServiceLabel) -> io::Error {
+    Error::Unsupported(format!(
+        "Unable to undo the uninstall of {label}, whose definition is gone"
+    ))
+    .into()
+}
+
+/// Returns true if the service with `label` is running
+fn is_running<M: ServiceManager + ?Sized>(manager: &M, label: &ServiceLabel) -> io::Result<bool> {
+    Ok(manager.status(ServiceStatusCtx::new(label.clone()))? == ServiceStatus::Running)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FakeCommandRunner, SystemdConfig, SystemdServiceManager};
+    use std::path::PathBuf;
+
+    fn ctx(label: &str) -> ServiceInstallCtx {
+        ServiceInstallCtx::builder(label.parse().unwrap(), "/usr/local/bin/echo")
+            .arg("hello")
+            .build()
+    }
+
+    #[test]
+    fn test_apply_rolls_back_in_reverse_order() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(FakeCommandRunner::new());
+        let unit_path = |name: &str| -> PathBuf {
+            root.path()
+                .join(format!("etc/systemd/system/example-{name}.service"))
+        };
+
+        // Services cannot be started within a root, so the start fails after both installs
+        let report = ServiceTransaction::new()
+            .install(ctx("com.example.broker"))
+            .install(ctx("com.example.consumer"))
+            .start(ServiceStartCtx::new("com.example.broker".parse().unwrap()))
+            .apply(&manager);
+
+        assert!(report.is_rolled_back());
+        assert!(matches!(
+            Error::from_io_error(report.error.as_ref().unwrap()),
+            Some(Error::Unsupported(_))
+        ));
+        assert_eq!(
+            report.applied,
+            vec![
+                TransactionStep {
+                    label: "com.example.broker".parse().unwrap(),
+                    change: ServiceChange::Installed,
+                },
+                TransactionStep {
+                    label: "com.example.consumer".parse().unwrap(),
+                    change: ServiceChange::Installed,
+                },
+            ]
+        );
+        assert_eq!(
+            report.rolled_back,
+            vec![
+                TransactionStep {
+                    label: "com.example.consumer".parse().unwrap(),
+                    change: ServiceChange::Uninstalled,
+                },
+                TransactionStep {
+                    label: "com.example.broker".parse().unwrap(),
+                    change: ServiceChange::Uninstalled,
+                },
+            ]
+        );
+        assert!(!unit_path("broker").exists());
+        assert!(!unit_path("consumer").exists());
+    }
+
+    #[test]
+    fn test_apply_rejects_operations_after_an_uninstall() {
+        let root = assert_fs::TempDir::new().unwrap();
+        let manager = SystemdServiceManager::system()
+            .with_config(SystemdConfig {
+                root: Some(root.path().to_path_buf()),
+                ..Default::default()
+            })
+            .with_runner(FakeCommandRunner::new());
+        manager.install(ctx("com.example.broker")).unwrap();
+        let unit_path = root
+            .path()
+            .join("etc/systemd/system/example-broker.service");
+
+        let report = ServiceTransaction::new()
+            .uninstall(ServiceUninstallCtx::new(
+                "com.example.broker".parse().unwrap(),
+            ))
+            .install(ctx("com.example.consumer"))
+            .apply(&manager);
+        assert!(matches!(
+            Error::from_io_error(report.error.as_ref().unwrap()),
+            Some(Error::Unsupported(_))
+        ));
+        assert!(report.applied.is_empty());
+        assert!(unit_path.exists());
+
+        // An uninstall placed last never has to be undone
+        let report = ServiceTransaction::new()
+            .install(ctx("com.example.consumer"))
+            .uninstall(ServiceUninstallCtx::new(
+                "com.example.broker".parse().unwrap(),
+            ))
+            .apply(&manager);
+        assert!(report.is_success());
+        assert_eq!(report.applied.len(), 2);
+        assert!(!unit_path.exists());
+    }
+}
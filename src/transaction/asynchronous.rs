@@ -0,0 +1,126 @@
+use super::{
+    check_undoable, uninstall_not_undoable, ServiceChange, ServiceOperation, ServiceTransaction,
+    TransactionReport, TransactionStep,
+};
+use crate::asynchronous::AsyncServiceManager;
+use crate::{
+    InstallOutcome, ServiceLabel, ServiceRollbackCtx, ServiceStartCtx, ServiceStatus,
+    ServiceStatusCtx, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::io;
+
+impl ServiceTransaction {
+    /// Applies the operations in order through an [`AsyncServiceManager`], undoing the applied
+    /// ones in reverse order as soon as one fails, like [`ServiceTransaction::apply`]
+    pub async fn apply_async<M: AsyncServiceManager + ?Sized>(
+        self,
+        manager: &M,
+    ) -> TransactionReport {
+        let mut report = TransactionReport::default();
+        if let Err(x) = check_undoable(&self.operations) {
+            report.error = Some(x);
+            return report;
+        }
+
+        for operation in self.operations {
+            match apply_operation(manager, operation).await {
+                Ok(step) => report.applied.push(step),
+                Err(x) => {
+                    report.error = Some(x);
+                    break;
+                }
+            }
+        }
+
+        if report.error.is_some() {
+            for step in report.applied.iter().rev() {
+                match undo_step(manager, step).await {
+                    Ok(Some(step)) => report.rolled_back.push(step),
+                    Ok(None) => {}
+                    Err(x) => report.rollback_errors.push(x),
+                }
+            }
+        }
+
+        report
+    }
+}
+
+/// Applies a single operation, returning the change it made
+async fn apply_operation<M: AsyncServiceManager + ?Sized>(
+    manager: &M,
+    operation: ServiceOperation,
+) -> io::Result<TransactionStep> {
+    let label = operation.label().clone();
+    let change = match operation {
+        ServiceOperation::Install(ctx) => match manager.install_or_update(ctx).await? {
+            InstallOutcome::Installed => ServiceChange::Installed,
+            InstallOutcome::Updated { .. } => ServiceChange::Updated,
+            InstallOutcome::Unchanged => ServiceChange::Unchanged,
+        },
+        ServiceOperation::Uninstall(ctx) => {
+            manager.uninstall(ctx).await?;
+            ServiceChange::Uninstalled
+        }
+        ServiceOperation::Start(ctx) => {
+            if is_running(manager, &label).await? {
+                ServiceChange::Unchanged
+            } else {
+                manager.start(ctx).await?;
+                ServiceChange::Started
+            }
+        }
+        ServiceOperation::Stop(ctx) => {
+            if is_running(manager, &label).await? {
+                manager.stop(ctx).await?;
+                ServiceChange::Stopped
+            } else {
+                ServiceChange::Unchanged
+            }
+        }
+    };
+
+    Ok(TransactionStep { label, change })
+}
+
+/// Undoes the change made by an applied step, returning the change that undid it if any
+async fn undo_step<M: AsyncServiceManager + ?Sized>(
+    manager: &M,
+    step: &TransactionStep,
+) -> io::Result<Option<TransactionStep>> {
+    let label = step.label.clone();
+    let change = match step.change {
+        ServiceChange::Installed => {
+            let ctx = ServiceUninstallCtx::new(label.clone());
+            manager.uninstall(ctx).await?;
+            ServiceChange::Uninstalled
+        }
+        ServiceChange::Updated => {
+            manager
+                .rollback(ServiceRollbackCtx::new(label.clone()))
+                .await?;
+            ServiceChange::RolledBack
+        }
+        ServiceChange::Started => {
+            manager.stop(ServiceStopCtx::new(label.clone())).await?;
+            ServiceChange::Stopped
+        }
+        ServiceChange::Stopped => {
+            manager.start(ServiceStartCtx::new(label.clone())).await?;
+            ServiceChange::Started
+        }
+        ServiceChange::Uninstalled => return Err(uninstall_not_undoable(&label)),
+        ServiceChange::RolledBack | ServiceChange::Unchanged => return Ok(None),
+    };
+
+    Ok(Some(TransactionStep { label, change }))
+}
+
+/// Returns true if the service with `label` is running
+async fn is_running<M: AsyncServiceManager + ?Sized>(
+    manager: &M,
+    label: &ServiceLabel,
+) -> io::Result<bool> {
+    let status = manager.status(ServiceStatusCtx::new(label.clone())).await?;
+    Ok(status == ServiceStatus::Running)
+}
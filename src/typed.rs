@@ -1,12 +1,24 @@
 use super::{
     LaunchdServiceManager, OpenRcServiceManager, RcdServiceManager, ScServiceManager,
-    ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceManagerKind, ServiceStartCtx,
-    ServiceStopCtx, ServiceUninstallCtx, SystemdServiceManager, WinSwServiceManager,
+    ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceManagerKind, ServiceRestartCtx,
+    ServiceStartCtx, ServiceStopCtx, ServiceUninstallCtx, SystemdServiceManager,
+    WinSwServiceManager,
 };
 use std::io;
 
 /// Represents an implementation of a known [`ServiceManager`]
+///
+/// With the `serde` feature, this doubles as a specification of the manager to use, tagged with
+/// its [`ServiceManagerKind`] alongside the fields of that manager. Missing fields take their
+/// default values and the command runner is never serialized, so a deserialized manager runs
+/// commands as processes.
+///
+/// ```json
+/// { "kind": "systemd", "user": true, "config": { "install": { "restart": "always" } } }
+/// ```
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "lowercase"))]
 pub enum TypedServiceManager {
     Launchd(LaunchdServiceManager),
     OpenRc(OpenRcServiceManager),
@@ -29,6 +41,9 @@ macro_rules! using {
     }};
 }
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 impl ServiceManager for TypedServiceManager {
     fn available(&self) -> io::Result<bool> {
         using!(self, x -> x.available())
@@ -38,6 +53,18 @@ impl ServiceManager for TypedServiceManager {
         using!(self, x -> x.install(ctx))
     }
 
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<crate::RenderedService> {
+        using!(self, x -> x.render(ctx))
+    }
+
+    fn diff(&self, ctx: &ServiceInstallCtx) -> io::Result<crate::ServiceDiff> {
+        using!(self, x -> x.diff(ctx))
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<crate::InstallOutcome> {
+        using!(self, x -> x.install_or_update(ctx))
+    }
+
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
         using!(self, x -> x.uninstall(ctx))
     }
@@ -50,6 +77,34 @@ impl ServiceManager for TypedServiceManager {
         using!(self, x -> x.stop(ctx))
     }
 
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        using!(self, x -> x.restart(ctx))
+    }
+
+    fn enable(&self, ctx: crate::ServiceEnableCtx) -> io::Result<()> {
+        using!(self, x -> x.enable(ctx))
+    }
+
+    fn disable(&self, ctx: crate::ServiceDisableCtx) -> io::Result<()> {
+        using!(self, x -> x.disable(ctx))
+    }
+
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        using!(self, x -> x.is_enabled(ctx))
+    }
+
+    fn rollback(&self, ctx: crate::ServiceRollbackCtx) -> io::Result<()> {
+        using!(self, x -> x.rollback(ctx))
+    }
+
+    fn logs(
+        &self,
+        label: crate::ServiceLabel,
+        query: crate::LogQuery,
+    ) -> io::Result<crate::LogLines> {
+        using!(self, x -> x.logs(label, query))
+    }
+
     fn level(&self) -> ServiceLevel {
         using!(self, x -> x.level())
     }
@@ -61,6 +116,17 @@ impl ServiceManager for TypedServiceManager {
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<crate::ServiceStatus> {
         using!(self, x -> x.status(ctx))
     }
+
+    fn status_detail(
+        &self,
+        ctx: crate::ServiceStatusCtx,
+    ) -> io::Result<crate::ServiceStatusDetail> {
+        using!(self, x -> x.status_detail(ctx))
+    }
+
+    fn list(&self, ctx: crate::ServiceListCtx) -> io::Result<Vec<crate::InstalledService>> {
+        using!(self, x -> x.list(ctx))
+    }
 }
 
 impl TypedServiceManager {
@@ -167,3 +233,34 @@ impl From<super::WinSwServiceManager> for TypedServiceManager {
         Self::WinSw(manager)
     }
 }
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::{SystemdConfig, SystemdInstallConfig, SystemdServiceRestartType};
+
+    #[test]
+    fn test_deserialize_spec_tagged_by_kind() {
+        let manager: TypedServiceManager = serde_json::from_str(
+            r#"{ "kind": "systemd", "user": true, "config": { "install": { "restart": "always" } } }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            manager,
+            TypedServiceManager::Systemd(SystemdServiceManager::user().with_config(
+                SystemdConfig {
+                    install: SystemdInstallConfig {
+                        restart: SystemdServiceRestartType::Always,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }
+            ))
+        );
+
+        let json = serde_json::to_value(&manager).unwrap();
+        assert_eq!(json["kind"], "systemd");
+        assert_eq!(json["config"]["install"]["restart"], "always");
+    }
+}
@@ -0,0 +1,86 @@
+use super::TypedServiceManager;
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::{
+    InstallOutcome, InstalledService, ServiceDiff, ServiceDisableCtx, ServiceEnableCtx,
+    ServiceInstallCtx, ServiceLevel, ServiceListCtx, ServiceRestartCtx, ServiceRollbackCtx,
+    ServiceStartCtx, ServiceStatus, ServiceStatusCtx, ServiceStatusDetail, ServiceStopCtx,
+    ServiceUninstallCtx,
+};
+use std::io;
+
+impl AsyncServiceManager for TypedServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        using!(self, x -> x.available())
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.install(ctx))
+    }
+
+    fn diff<'a>(&'a self, ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        using!(self, x -> x.diff(ctx))
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        using!(self, x -> x.install_or_update(ctx))
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.uninstall(ctx))
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.start(ctx))
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.stop(ctx))
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.restart(ctx))
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.enable(ctx))
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.disable(ctx))
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        using!(self, x -> x.is_enabled(ctx))
+    }
+
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        using!(self, x -> x.rollback(ctx))
+    }
+
+    fn level(&self) -> ServiceLevel {
+        using!(self, x -> x.level())
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        using!(self, x -> x.set_level(level))
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        using!(self, x -> x.status(ctx))
+    }
+
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        using!(self, x -> x.status_detail(ctx))
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        using!(self, x -> x.list(ctx))
+    }
+}
+
+impl TypedServiceManager {
+    /// Consumes underlying [`AsyncServiceManager`] and moves it onto the heap
+    pub fn into_async_box(self) -> Box<dyn AsyncServiceManager> {
+        using!(self, x -> Box::new(x))
+    }
+}
@@ -1,15 +1,215 @@
+use crate::{
+    CommandRunner, DiffState, Error, FileDiff, InstallOutcome, RenderedFile, RenderedService,
+    ServiceCommand, ServiceDiff, ServiceInstallCtx, ServiceLabel, ServiceListCtx, ServiceManager,
+    ServiceNaming, ServiceRestartCtx, ServiceStatus, ServiceStatusCtx,
+};
 use std::{
     borrow::Cow,
+    ffi::{OsStr, OsString},
     fs::OpenOptions,
     io::{self, Write},
-    path::Path,
+    ops::Deref,
+    path::{Path, PathBuf},
     process::Output,
 };
 
+/// Resolves an absolute `path` within `root`, leaving it unchanged if there is no root
+pub fn rooted(root: Option<&Path>, path: PathBuf) -> PathBuf {
+    match root {
+        Some(root) => root.join(path.strip_prefix("/").unwrap_or(&path)),
+        None => path,
+    }
+}
+
+/// Fails with [`Error::Unsupported`] if a `root` is set, as there is no running service manager
+/// within an alternate root to perform `operation`
+pub fn ensure_no_root(root: Option<&Path>, operation: &str) -> io::Result<()> {
+    match root {
+        Some(root) => Err(Error::Unsupported(format!(
+            "Unable to {operation} a service installed in the alternate root {}",
+            root.display()
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// Status of a service within an alternate root, which is never running
+pub fn offline_status(definition_path: &Path) -> ServiceStatus {
+    if definition_path.exists() {
+        ServiceStatus::Stopped(None)
+    } else {
+        ServiceStatus::NotInstalled
+    }
+}
+
+/// Reports a missing definition file of the service `name` as [`Error::NotInstalled`], leaving
+/// any other error unchanged
+pub fn not_installed(err: io::Error, name: &str) -> io::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        Error::NotInstalled(name.to_string()).into()
+    } else {
+        err
+    }
+}
+
+/// Lists the names of the regular files (or directories if `dirs` is true) within `path`
+///
+/// A missing directory is treated as empty, and hidden names or names that are not valid unicode
+/// are skipped.
+pub fn list_dir_names(path: &Path, dirs: bool) -> io::Result<Vec<String>> {
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(x) if x.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(x) => return Err(x),
+    };
+
+    let mut names = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let matches = if dirs {
+            file_type.is_dir()
+        } else {
+            file_type.is_file()
+        };
+
+        if matches {
+            if let Ok(name) = entry.file_name().into_string() {
+                if !name.starts_with('.') {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    names.sort();
+    Ok(names)
+}
+
+/// Names and paths of the services whose scripts within `dir_path` are named `script_names`
+pub fn script_definitions(dir_path: &Path, script_names: Vec<String>) -> Vec<(String, PathBuf)> {
+    script_names
+        .into_iter()
+        .map(|script_name| {
+            let path = dir_path.join(&script_name);
+            (script_name, path)
+        })
+        .collect()
+}
+
+/// Labels of the services with the native names and definition files of `definitions`,
+/// filtered by `ctx`
+pub fn definition_labels(
+    definitions: Vec<(String, PathBuf)>,
+    naming: &ServiceNaming,
+    ctx: &ServiceListCtx,
+) -> Vec<ServiceLabel> {
+    definitions
+        .into_iter()
+        .filter_map(|(name, path)| {
+            let contents = std::fs::read(path).ok();
+            label_from_definition(contents.as_deref(), &name, naming)
+        })
+        .filter(|label| ctx.matches(label))
+        .collect()
+}
+
+/// Label of the service with the native `name`, read from the `contents` of its definition when
+/// recorded there by [`mark_label`], and otherwise recovered from the name using `naming`
+pub fn label_from_definition(
+    contents: Option<&[u8]>,
+    name: &str,
+    naming: &ServiceNaming,
+) -> Option<ServiceLabel> {
+    let recorded = contents.and_then(|contents| {
+        marked_label(&String::from_utf8_lossy(contents)).and_then(|label| label.parse().ok())
+    });
+    recorded.or_else(|| naming.label_from_native(name))
+}
+
+/// Compares the rendered `files` of a service with the ones on disk
+pub fn diff_rendered(files: &[RenderedFile]) -> io::Result<ServiceDiff> {
+    let mut diffs = Vec::new();
+    for file in files {
+        let installed = match std::fs::read(&file.path) {
+            Ok(contents) => Some(contents),
+            Err(x) if x.kind() == io::ErrorKind::NotFound => None,
+            Err(x) => return Err(x),
+        };
+        diffs.push(FileDiff::new(file, installed.as_deref()));
+    }
+    Ok(ServiceDiff::new(diffs))
+}
+
+/// Installs a service whose definition is made of rendered files through `manager`, or updates
+/// it by rewriting its files, running the `reload` commands and restarting the service if it was
+/// running
+pub fn install_or_update_rendered<M: ServiceManager + ?Sized>(
+    manager: &M,
+    runner: &dyn CommandRunner,
+    ctx: ServiceInstallCtx,
+    reload: &[ServiceCommand],
+) -> io::Result<InstallOutcome> {
+    let rendered = manager.render(&ctx)?;
+    match diff_rendered(&rendered.files)?.state {
+        DiffState::Missing => {
+            manager.install(ctx)?;
+            Ok(InstallOutcome::Installed)
+        }
+        DiffState::Identical => Ok(InstallOutcome::Unchanged),
+        DiffState::Differs => {
+            let running =
+                manager.status(ServiceStatusCtx::new(ctx.label.clone()))? == ServiceStatus::Running;
+            write_rendered_files(&rendered.files)?;
+            for cmd in reload {
+                wrap_output(output(runner, cmd)?)?;
+            }
+            if running {
+                manager.restart(ServiceRestartCtx::new(ctx.label))?;
+            }
+            Ok(InstallOutcome::Updated { restarted: running })
+        }
+    }
+}
+
+/// Path of the backup kept of the definition at `path`, which is hidden alongside it so that
+/// init systems scanning the directory skip it
+pub fn backup_path(path: &Path) -> PathBuf {
+    hidden_sibling_path(path, ".bak")
+}
+
+/// Path of a hidden file alongside `path`, named after it with `suffix`
+fn hidden_sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
 /// Writes/overwrites a file, assigning the permissions of `mode` if on a unix system
-pub fn write_file(path: &Path, data: &[u8], _mode: u32) -> io::Result<()> {
+///
+/// The file is replaced atomically, and its previous contents are kept at [`backup_path`] when
+/// they differ from `data`.
+pub fn write_file(path: &Path, data: &[u8], mode: u32) -> io::Result<()> {
+    match std::fs::read(path) {
+        Ok(previous) if previous != data => write_file_atomic(&backup_path(path), &previous, mode)?,
+        Ok(_) => {}
+        Err(x) if x.kind() == io::ErrorKind::NotFound => {}
+        Err(x) => return Err(x),
+    }
+
+    write_file_atomic(path, data, mode)
+}
+
+/// Writes/overwrites a file by writing a temporary file within the same directory, syncing it
+/// and renaming it over `path`, so `path` is never left partially written
+fn write_file_atomic(path: &Path, data: &[u8], _mode: u32) -> io::Result<()> {
+    let tmp_path = hidden_sibling_path(path, ".tmp");
+    let _ = std::fs::remove_file(&tmp_path);
+
     let mut opts = OpenOptions::new();
-    opts.create(true).write(true).truncate(true);
+    opts.create_new(true).write(true);
 
     #[cfg(unix)]
     {
@@ -17,60 +217,712 @@ pub fn write_file(path: &Path, data: &[u8], _mode: u32) -> io::Result<()> {
         opts.mode(_mode);
     }
 
-    let mut file = opts.open(path)?;
-    file.write_all(data)?;
+    let written = opts.open(&tmp_path).and_then(|mut file| {
+        file.write_all(data)?;
+
+        // Ensure that the data/metadata is synced and catch errors before renaming
+        file.sync_all()
+    });
+    if let Err(x) = written.and_then(|_| std::fs::rename(&tmp_path, path)) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(x);
+    }
+
+    // Persist the rename itself, which is only possible by syncing the directory on unix
+    #[cfg(unix)]
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        std::fs::File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+/// Restores the backup kept by [`write_file`] of the definition at `path`, which belongs to the
+/// service named `name`
+///
+/// The backup is left in place, and a missing backup fails with [`Error::NoBackup`].
+pub fn restore_backup(path: &Path, name: &str, mode: u32) -> io::Result<()> {
+    let backup = match std::fs::read(backup_path(path)) {
+        Ok(backup) => backup,
+        Err(x) if x.kind() == io::ErrorKind::NotFound => {
+            return Err(Error::NoBackup(name.to_string()).into())
+        }
+        Err(x) => return Err(x),
+    };
+    write_file_atomic(path, &backup, mode)
+}
+
+/// Removes the definition at `path` along with its backup, if any
+pub fn remove_definition(path: &Path) -> io::Result<()> {
+    std::fs::remove_file(path)?;
+    let _ = std::fs::remove_file(backup_path(path));
+    Ok(())
+}
+
+/// Installs a rendered service by writing its files and then running its commands, failing on the
+/// first command that does not succeed
+pub fn install_rendered(runner: &dyn CommandRunner, rendered: &RenderedService) -> io::Result<()> {
+    write_rendered_files(&rendered.files)?;
+    for cmd in &rendered.commands {
+        wrap_output(output(runner, cmd)?)?;
+    }
+    Ok(())
+}
+
+/// Writes the rendered files of a service, creating their parent directories if missing
+pub fn write_rendered_files(files: &[RenderedFile]) -> io::Result<()> {
+    for file in files {
+        if let Some(parent) = file.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_file(&file.path, &file.contents, file.mode)?;
+    }
+    Ok(())
+}
+
+/// Finds the value assigned to the shell variable `name` at the start of a line of `script`,
+/// undoing the quoting of the value
+///
+/// Only literal values are understood: double quotes, single quotes and backslashes are removed,
+/// but expansions such as `$var` are kept as written.
+pub fn script_variable(script: &str, name: &str) -> Option<String> {
+    script.lines().find_map(|line| {
+        let value = line.trim_start().strip_prefix(name)?.strip_prefix('=')?;
+        Some(unquote_shell_word(value))
+    })
+}
+
+/// Finds the value of the shell variable `name` like [`script_variable`], then undoes the quoting
+/// of the word it holds, as done when the value is passed through `eval`
+pub fn evaluated_script_variable(script: &str, name: &str) -> Option<String> {
+    script_variable(script, name).map(|value| unquote_shell_word(&value))
+}
+
+/// Reads the first shell word at the start of `s`, removing its quotes and escapes
+fn unquote_shell_word(s: &str) -> String {
+    let mut word = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => word.extend(chars.by_ref().take_while(|c| *c != '\'')),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some(c @ ('$' | '`' | '"' | '\\')) => word.push(c),
+                            Some('\n') | None => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                        },
+                        c => word.push(c),
+                    }
+                }
+            }
+            '\\' => word.extend(chars.next()),
+            c if c.is_whitespace() || c == ';' => break,
+            c => word.push(c),
+        }
+    }
+    word
+}
+
+/// Bytes of `s`, failing with [`Error::InvalidDefinition`] naming `what` on platforms where
+/// strings are not made of bytes and `s` is not valid Unicode
+pub fn os_str_bytes<'a>(s: &'a OsStr, what: &str) -> io::Result<Cow<'a, [u8]>> {
+    #[cfg(unix)]
+    {
+        let _ = what;
+        Ok(Cow::Borrowed(std::os::unix::ffi::OsStrExt::as_bytes(s)))
+    }
+
+    #[cfg(not(unix))]
+    {
+        Ok(Cow::Borrowed(require_utf8(s, what)?.as_bytes()))
+    }
+}
+
+/// Returns `s` as UTF-8, failing with [`Error::InvalidDefinition`] naming `what` if it holds
+/// other bytes, for definitions whose format can only hold text
+pub fn require_utf8<'a>(s: &'a OsStr, what: &str) -> io::Result<&'a str> {
+    s.to_str().ok_or_else(|| {
+        Error::InvalidDefinition(format!(
+            "{what} {s:?} is not valid UTF-8, which the service definition cannot hold"
+        ))
+        .into()
+    })
+}
+
+/// Returns true if `name` can name an environment variable, holding only letters, digits and
+/// underscores without starting with a digit, as accepted by both systemd and the shell
+pub fn is_valid_env_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Returns true if `word` can be written as a shell word without any quoting, holding neither
+/// whitespace nor characters with a special meaning to the shell
+pub fn is_plain_shell_word(word: &[u8]) -> bool {
+    !word.is_empty()
+        && word
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"_@%+=:,./-".contains(b))
+}
+
+/// Quotes `word` as a single word of a POSIX shell command line, leaving it as is if it is
+/// plain
+///
+/// Single quotes keep every byte literally, so the only escaping needed is closing the quotes
+/// around a single quote. A shell word cannot hold a NUL byte, which is rejected.
+pub fn quote_shell_word(word: &[u8]) -> io::Result<Vec<u8>> {
+    if word.contains(&0) {
+        return Err(Error::InvalidDefinition(format!(
+            "A shell script is unable to hold the NUL character of {:?}",
+            String::from_utf8_lossy(word)
+        ))
+        .into());
+    }
+    if is_plain_shell_word(word) {
+        return Ok(word.to_vec());
+    }
+
+    let mut quoted = Vec::with_capacity(word.len() + 2);
+    quoted.push(b'\'');
+    for b in word {
+        match b {
+            b'\'' => quoted.extend_from_slice(br"'\''"),
+            b => quoted.push(*b),
+        }
+    }
+    quoted.push(b'\'');
+    Ok(quoted)
+}
+
+/// Quotes each of `words` as done by [`quote_shell_word`] and joins them into a command line,
+/// for a variable whose value is evaluated by the shell again, such as rc.d `${name}_options`
+pub fn quote_shell_words<'a>(words: impl IntoIterator<Item = &'a [u8]>) -> io::Result<Vec<u8>> {
+    let words = words
+        .into_iter()
+        .map(quote_shell_word)
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(words.join(&b' '))
+}
+
+/// Places `value` between double quotes, escaping the characters that keep a special meaning
+/// within them so that it is assigned as is
+pub fn quote_shell_string(value: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(value.len() + 2);
+    quoted.push(b'"');
+    for b in value {
+        if matches!(b, b'$' | b'`' | b'"' | b'\\') {
+            quoted.push(b'\\');
+        }
+        quoted.push(*b);
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+/// Text preceding the label recorded within a generated service definition
+const LABEL_MARKER: &str = "service-manager label:";
+
+/// Kind of comment used to record the label within a service definition
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MarkerStyle {
+    /// `# ...`, placed after the shebang line if there is one
+    Hash,
+
+    /// `<!-- ... -->`, placed after the XML declaration if there is one
+    Xml,
+}
+
+/// Records `label` as a comment within the generated `contents` of a service definition, so
+/// that services whose labels map to the same native name can be told apart
+pub fn mark_label(contents: String, label: &ServiceLabel, style: MarkerStyle) -> String {
+    let marked = mark_label_bytes(contents.into_bytes(), label, style);
+
+    // The comment is only inserted after an ASCII character, which keeps the contents valid
+    String::from_utf8(marked).expect("marked contents are valid UTF-8")
+}
+
+/// Records `label` within `contents` as done by [`mark_label`], for contents such as shell
+/// scripts that may hold bytes that are not valid UTF-8
+pub fn mark_label_bytes(contents: Vec<u8>, label: &ServiceLabel, style: MarkerStyle) -> Vec<u8> {
+    let (comment, split_at) = match style {
+        MarkerStyle::Hash => (
+            format!("# {LABEL_MARKER} {label}\n"),
+            contents.starts_with(b"#!").then(|| {
+                contents
+                    .iter()
+                    .position(|b| *b == b'\n')
+                    .map_or(contents.len(), |i| i + 1)
+            }),
+        ),
+        MarkerStyle::Xml => (
+            format!("<!-- {LABEL_MARKER} {label} -->\n"),
+            contents
+                .starts_with(b"<?xml")
+                .then(|| contents.windows(2).position(|w| w == b"?>").map(|i| i + 2))
+                .flatten(),
+        ),
+    };
+
+    match split_at {
+        Some(i) => {
+            let (head, tail) = contents.split_at(i);
+            let start = tail.iter().position(|b| *b != b'\n').unwrap_or(tail.len());
+            let mut marked = head.to_vec();
+            if !head.ends_with(b"\n") {
+                marked.push(b'\n');
+            }
+            marked.extend_from_slice(comment.as_bytes());
+            marked.extend_from_slice(&tail[start..]);
+            marked
+        }
+        None => [comment.as_bytes(), &contents].concat(),
+    }
+}
+
+/// Label recorded by [`mark_label`] within the `contents` of a service definition
+pub fn marked_label(contents: &str) -> Option<&str> {
+    contents.lines().find_map(|line| {
+        let (_, label) = line.split_once(LABEL_MARKER)?;
+        Some(label.trim().trim_end_matches("-->").trim_end())
+    })
+}
+
+/// Fails with [`Error::NameConflict`] if the service definition at `path` was generated for a
+/// service other than `label`
+///
+/// Definitions that are missing or were not generated by this crate do not conflict.
+pub fn ensure_no_conflict(path: &Path, name: &str, label: &ServiceLabel) -> io::Result<()> {
+    let contents = match std::fs::read(path) {
+        Ok(contents) => contents,
+        Err(x) if x.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(x) => return Err(x),
+    };
+
+    match marked_label(&String::from_utf8_lossy(&contents)) {
+        Some(existing) if existing != label.to_qualified_name() => Err(Error::NameConflict {
+            name: name.to_string(),
+            existing: existing.to_string(),
+        }
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Default of [`ServiceInstallCtx::autostart`](crate::ServiceInstallCtx::autostart) when it is
+/// missing from a serialized context, matching
+/// [`ServiceInstallCtx::new`](crate::ServiceInstallCtx::new)
+#[cfg(feature = "serde")]
+pub fn default_autostart() -> bool {
+    true
+}
+
+/// (De)serializes a list of [`OsString`](std::ffi::OsString) as a list of strings, like serde does
+/// for paths, rather than as platform-specific byte or wide-character sequences
+#[cfg(feature = "serde")]
+pub mod os_strings {
+    use serde::{ser::Error, Deserialize, Deserializer, Serializer};
+    use std::ffi::OsString;
+
+    pub fn serialize<S: Serializer>(values: &[OsString], serializer: S) -> Result<S::Ok, S::Error> {
+        let strings = values
+            .iter()
+            .map(|value| {
+                value.to_str().ok_or_else(|| {
+                    S::Error::custom(format!("{value:?} contains invalid UTF-8 characters"))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        serializer.collect_seq(strings)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<OsString>, D::Error> {
+        let strings = Vec::<String>::deserialize(deserializer)?;
+        Ok(strings.into_iter().map(OsString::from).collect())
+    }
+
+    /// Same as the parent module for an optional list
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serialize, Serializer};
+        use std::ffi::OsString;
+
+        pub fn serialize<S: Serializer>(
+            values: &Option<Vec<OsString>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            #[derive(Serialize)]
+            struct Wrapper<'a>(#[serde(with = "super")] &'a [OsString]);
+
+            values.as_deref().map(Wrapper).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<OsString>>, D::Error> {
+            #[derive(Deserialize)]
+            struct Wrapper(#[serde(with = "super")] Vec<OsString>);
+
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(values)| values))
+        }
+    }
+}
+
+/// Asynchronous counterparts of the helpers used to install services and run their commands
+#[cfg(feature = "tokio")]
+pub mod nonblocking {
+    use super::{
+        backup_path, command_output, hidden_sibling_path, label_from_definition, tool_not_found,
+        wrap_output, CommandOutput,
+    };
+    use crate::{
+        asynchronous::AsyncServiceManager, CommandRunner, DiffState, Error, FileDiff,
+        InstallOutcome, RenderedFile, RenderedService, ServiceCommand, ServiceDiff,
+        ServiceInstallCtx, ServiceLabel, ServiceListCtx, ServiceNaming, ServiceRestartCtx,
+        ServiceStatus, ServiceStatusCtx,
+    };
+    use std::{
+        io,
+        path::{Path, PathBuf},
+    };
+    use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+
+    /// Determines whether anything exists at `path`, treating errors as it not existing
+    pub async fn exists(path: &Path) -> bool {
+        tokio::fs::metadata(path).await.is_ok()
+    }
+
+    /// Status of a service within an alternate root, which is never running
+    pub async fn offline_status(definition_path: &Path) -> ServiceStatus {
+        if exists(definition_path).await {
+            ServiceStatus::Stopped(None)
+        } else {
+            ServiceStatus::NotInstalled
+        }
+    }
+
+    /// Lists the names of the regular files (or directories if `dirs` is true) within `path`
+    ///
+    /// A missing directory is treated as empty, and hidden names or names that are not valid
+    /// unicode are skipped.
+    pub async fn list_dir_names(path: &Path, dirs: bool) -> io::Result<Vec<String>> {
+        let mut entries = match tokio::fs::read_dir(path).await {
+            Ok(entries) => entries,
+            Err(x) if x.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(x) => return Err(x),
+        };
+
+        let mut names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let file_type = entry.file_type().await?;
+            let matches = if dirs {
+                file_type.is_dir()
+            } else {
+                file_type.is_file()
+            };
+
+            if matches {
+                if let Ok(name) = entry.file_name().into_string() {
+                    if !name.starts_with('.') {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+
+        names.sort();
+        Ok(names)
+    }
+
+    /// Labels of the services with the native names and definition files of `definitions`,
+    /// filtered by `ctx`
+    pub async fn definition_labels(
+        definitions: Vec<(String, PathBuf)>,
+        naming: &ServiceNaming,
+        ctx: &ServiceListCtx,
+    ) -> Vec<ServiceLabel> {
+        let mut labels = Vec::new();
+        for (name, path) in definitions {
+            let contents = tokio::fs::read(path).await.ok();
+            if let Some(label) = label_from_definition(contents.as_deref(), &name, naming) {
+                if ctx.matches(&label) {
+                    labels.push(label);
+                }
+            }
+        }
+        labels
+    }
+
+    /// Compares the rendered `files` of a service with the ones on disk
+    pub async fn diff_rendered(files: &[RenderedFile]) -> io::Result<ServiceDiff> {
+        let mut diffs = Vec::new();
+        for file in files {
+            let installed = match tokio::fs::read(&file.path).await {
+                Ok(contents) => Some(contents),
+                Err(x) if x.kind() == io::ErrorKind::NotFound => None,
+                Err(x) => return Err(x),
+            };
+            diffs.push(FileDiff::new(file, installed.as_deref()));
+        }
+        Ok(ServiceDiff::new(diffs))
+    }
+
+    /// Installs a service whose definition is made of rendered files through `manager`, or
+    /// updates it by rewriting its files, running the `reload` commands and restarting the
+    /// service if it was running
+    pub async fn install_or_update_rendered<M>(
+        manager: &M,
+        runner: &dyn CommandRunner,
+        ctx: ServiceInstallCtx,
+        reload: &[ServiceCommand],
+    ) -> io::Result<InstallOutcome>
+    where
+        M: AsyncServiceManager + crate::ServiceManager,
+    {
+        let rendered = crate::ServiceManager::render(manager, &ctx)?;
+        match diff_rendered(&rendered.files).await?.state {
+            DiffState::Missing => {
+                AsyncServiceManager::install(manager, ctx).await?;
+                Ok(InstallOutcome::Installed)
+            }
+            DiffState::Identical => Ok(InstallOutcome::Unchanged),
+            DiffState::Differs => {
+                let status =
+                    AsyncServiceManager::status(manager, ServiceStatusCtx::new(ctx.label.clone()))
+                        .await?;
+                let running = status == ServiceStatus::Running;
+                write_rendered_files(&rendered.files).await?;
+                for cmd in reload {
+                    wrap_output(output(runner, cmd).await?)?;
+                }
+                if running {
+                    AsyncServiceManager::restart(manager, ServiceRestartCtx::new(ctx.label))
+                        .await?;
+                }
+                Ok(InstallOutcome::Updated { restarted: running })
+            }
+        }
+    }
+
+    /// Writes/overwrites a file like its blocking counterpart, replacing it atomically and
+    /// keeping its previous contents at [`backup_path`] when they differ from `data`
+    pub async fn write_file(path: &Path, data: &[u8], mode: u32) -> io::Result<()> {
+        match tokio::fs::read(path).await {
+            Ok(previous) if previous != data => {
+                write_file_atomic(&backup_path(path), &previous, mode).await?
+            }
+            Ok(_) => {}
+            Err(x) if x.kind() == io::ErrorKind::NotFound => {}
+            Err(x) => return Err(x),
+        }
+
+        write_file_atomic(path, data, mode).await
+    }
+
+    /// Writes/overwrites a file through a synced temporary file renamed over `path`
+    async fn write_file_atomic(path: &Path, data: &[u8], _mode: u32) -> io::Result<()> {
+        let tmp_path = hidden_sibling_path(path, ".tmp");
+        let _ = tokio::fs::remove_file(&tmp_path).await;
+
+        let mut opts = OpenOptions::new();
+        opts.create_new(true).write(true);
+
+        #[cfg(unix)]
+        opts.mode(_mode);
+
+        let written = async {
+            let mut file = opts.open(&tmp_path).await?;
+            file.write_all(data).await?;
+
+            // Ensure that the data/metadata is synced and catch errors before renaming
+            file.sync_all().await?;
+            tokio::fs::rename(&tmp_path, path).await
+        };
+        if let Err(x) = written.await {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(x);
+        }
+
+        // Persist the rename itself, which is only possible by syncing the directory on unix
+        #[cfg(unix)]
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            tokio::fs::File::open(parent).await?.sync_all().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Restores the backup kept by [`write_file`] of the definition at `path`, like its blocking
+    /// counterpart
+    pub async fn restore_backup(path: &Path, name: &str, mode: u32) -> io::Result<()> {
+        let backup = match tokio::fs::read(backup_path(path)).await {
+            Ok(backup) => backup,
+            Err(x) if x.kind() == io::ErrorKind::NotFound => {
+                return Err(Error::NoBackup(name.to_string()).into())
+            }
+            Err(x) => return Err(x),
+        };
+        write_file_atomic(path, &backup, mode).await
+    }
+
+    /// Removes the definition at `path` along with its backup, if any
+    pub async fn remove_definition(path: &Path) -> io::Result<()> {
+        tokio::fs::remove_file(path).await?;
+        let _ = tokio::fs::remove_file(backup_path(path)).await;
+        Ok(())
+    }
+
+    /// Installs a rendered service by writing its files and then running its commands, failing
+    /// on the first command that does not succeed
+    pub async fn install_rendered(
+        runner: &dyn CommandRunner,
+        rendered: &RenderedService,
+    ) -> io::Result<()> {
+        write_rendered_files(&rendered.files).await?;
+        for cmd in &rendered.commands {
+            wrap_output(output(runner, cmd).await?)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the rendered files of a service, creating their parent directories if missing
+    pub async fn write_rendered_files(files: &[RenderedFile]) -> io::Result<()> {
+        for file in files {
+            if let Some(parent) = file.path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            write_file(&file.path, &file.contents, file.mode).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs a command to completion using `runner`, capturing its output
+    ///
+    /// A program that cannot be found is reported as [`Error::ToolNotFound`](crate::Error).
+    pub async fn output(
+        runner: &dyn CommandRunner,
+        cmd: &ServiceCommand,
+    ) -> io::Result<CommandOutput> {
+        match runner.run_async(cmd).await {
+            Ok(output) => Ok(command_output(cmd, output)),
+            Err(x) => Err(tool_not_found(cmd, x)),
+        }
+    }
+}
 
-    // Ensure that the data/metadata is synced and catch errors before dropping
-    file.sync_all()
+/// Output of a command, along with the program and arguments that produced it
+pub struct CommandOutput {
+    pub program: String,
+    pub args: Vec<String>,
+    pub output: Output,
+}
+
+impl Deref for CommandOutput {
+    type Target = Output;
+
+    fn deref(&self) -> &Self::Target {
+        &self.output
+    }
+}
+
+/// Runs a command to completion using `runner`, capturing its output
+///
+/// A program that cannot be found is reported as [`Error::ToolNotFound`].
+pub fn output(runner: &dyn CommandRunner, cmd: &ServiceCommand) -> io::Result<CommandOutput> {
+    match runner.run(cmd) {
+        Ok(output) => Ok(command_output(cmd, output)),
+        Err(x) => Err(tool_not_found(cmd, x)),
+    }
+}
+
+/// Pairs the output of `cmd` with its program and arguments
+pub fn command_output(cmd: &ServiceCommand, output: Output) -> CommandOutput {
+    CommandOutput {
+        program: cmd.program.to_string_lossy().into_owned(),
+        args: cmd
+            .args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect(),
+        output,
+    }
+}
+
+/// Reports an error of kind [`io::ErrorKind::NotFound`] from running `cmd` as
+/// [`Error::ToolNotFound`], leaving any other error unchanged
+pub fn tool_not_found(cmd: &ServiceCommand, err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        Error::ToolNotFound(cmd.program.to_string_lossy().into_owned()).into()
+    } else {
+        err
+    }
 }
 
 /// Warp the output of a command in a `std::io::Result` if the command failed
-#[cfg(not(feature = "encoding"))]
-pub fn wrap_output(output: Output) -> std::io::Result<Output> {
+pub fn wrap_output(output: CommandOutput) -> io::Result<CommandOutput> {
     if output.status.success() {
         Ok(output)
     } else {
-        let mut msg = String::from_utf8_lossy(&output.stderr);
-        if msg.trim().is_empty() {
-            msg = String::from_utf8_lossy(&output.stdout);
-        }
-        if msg.is_empty() {
-            msg = Cow::Borrowed("Failed to execute command with no output");
-        }
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Command failed with exit code {}: {}",
-                output.status.code().unwrap_or(-1),
-                msg
-            ),
-        ))
+        Err(command_failed(output).into())
     }
 }
 
-/// Warp the output of a command in a `std::io::Result` if the command failed
-#[cfg(feature = "encoding")]
-pub fn wrap_output(output: Output) -> std::io::Result<Output> {
-    use encoding::decode;
+/// Converts the output of a failed command into an [`Error`]
+///
+/// Failures whose output mentions missing permissions are reported as
+/// [`Error::PermissionDenied`], everything else as [`Error::CommandFailed`].
+pub fn command_failed(output: CommandOutput) -> Error {
+    let err = Error::CommandFailed {
+        program: output.program,
+        args: output.args,
+        exit_code: output.output.status.code(),
+        stdout: decode_lossy(&output.output.stdout),
+        stderr: decode_lossy(&output.output.stderr),
+    };
 
-    if output.status.success() {
-        Ok(output)
+    let msg = err.to_string();
+    let lowercase_msg = msg.to_lowercase();
+    if [
+        "permission denied",
+        "access denied",
+        "access is denied",
+        "operation not permitted",
+        "interactive authentication required",
+    ]
+    .iter()
+    .any(|x| lowercase_msg.contains(x))
+    {
+        Error::PermissionDenied(msg)
     } else {
-        let mut msg = decode(&output.stderr).unwrap_or_default();
-        if msg.trim().is_empty() {
-            msg = decode(&output.stdout).unwrap_or_default();
-        }
-        if msg.is_empty() {
-            msg = Cow::Borrowed("Failed to execute command with no output");
-        }
-        Err(io::Error::new(
-            io::ErrorKind::Other,
-            format!(
-                "Command failed with exit code {}: {}",
-                output.status.code().unwrap_or(-1),
-                msg
-            ),
-        ))
+        err
+    }
+}
+
+/// Decodes the output of a command into a string, replacing anything that cannot be decoded
+#[cfg(not(feature = "encoding"))]
+fn decode_lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Decodes the output of a command into a string, replacing anything that cannot be decoded
+#[cfg(feature = "encoding")]
+fn decode_lossy(bytes: &[u8]) -> String {
+    match encoding::decode(bytes) {
+        Ok(s) => s.into_owned(),
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
     }
 }
 
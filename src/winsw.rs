@@ -1,26 +1,37 @@
-use crate::utils::wrap_output;
+use crate::utils::{wrap_output, CommandOutput};
 use crate::ServiceStatus;
 
 use super::{
-    ServiceInstallCtx, ServiceLevel, ServiceManager, ServiceStartCtx, ServiceStopCtx,
-    ServiceUninstallCtx,
+    dependency::unsupported_dependency, limits::unsupported_limit, logs, naming, utils,
+    CommandRunner, DependencyKind, Error, InstallOutcome, LogLines, LogQuery, RenderedFile,
+    RenderedService, ServiceCommand, ServiceDisableCtx, ServiceEnableCtx, ServiceInstallCtx,
+    ServiceLabel, ServiceLevel, ServiceManager, ServiceNaming, ServiceOutput, ServiceRestartCtx,
+    ServiceRollbackCtx, ServiceStartCtx, ServiceStopCtx, ServiceUninstallCtx, SharedCommandRunner,
 };
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
 use std::fs::File;
-use std::io::{self, BufWriter, Cursor, Write};
+use std::io::{self, Cursor, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Output, Stdio};
 use xml::common::XmlVersion;
 use xml::reader::EventReader;
 use xml::writer::{EmitterConfig, EventWriter, XmlEvent};
 
+#[cfg(feature = "tokio")]
+mod asynchronous;
+
 static WINSW_EXE: &str = "winsw.exe";
+const CONFIG_FILE_PERMISSIONS: u32 = 0o644;
 
-///
-/// Service configuration
-///
+/// Longest service name whose definition file `{name}.xml` fits in a file name
+const MAX_SERVICE_NAME_LEN: usize = 255 - ".xml".len();
+
+//
+// Service configuration
+//
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct WinSwConfig {
     pub install: WinSwInstallConfig,
     pub options: WinSwOptionsConfig,
@@ -38,6 +49,8 @@ impl Default for WinSwConfig {
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct WinSwInstallConfig {
     pub failure_action: WinSwOnFailureAction,
     pub reset_failure_time: Option<String>,
@@ -45,10 +58,16 @@ pub struct WinSwInstallConfig {
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct WinSwOptionsConfig {
     pub priority: Option<WinSwPriority>,
     pub stop_timeout: Option<String>,
     pub stop_executable: Option<PathBuf>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::utils::os_strings::option")
+    )]
     pub stop_args: Option<Vec<OsString>>,
     pub start_mode: Option<WinSwStartType>,
     pub delayed_autostart: Option<bool>,
@@ -58,6 +77,8 @@ pub struct WinSwOptionsConfig {
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum WinSwOnFailureAction {
     Restart(Option<String>),
     Reboot,
@@ -66,6 +87,8 @@ pub enum WinSwOnFailureAction {
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum WinSwStartType {
     // The service automatically starts along with the OS, before user login.
     Automatic,
@@ -78,6 +101,8 @@ pub enum WinSwStartType {
 }
 
 #[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum WinSwPriority {
     #[default]
     Normal,
@@ -88,15 +113,30 @@ pub enum WinSwPriority {
     AboveNormal,
 }
 
-///
-/// Service manager implementation
-///
+//
+// Service manager implementation
+//
 
 /// Implementation of [`ServiceManager`] for [Window Service](https://en.wikipedia.org/wiki/Windows_service)
 /// leveraging [`winsw.exe`](https://github.com/winsw/winsw)
-#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct WinSwServiceManager {
     pub config: WinSwConfig,
+
+    /// Strategy used to name the services, defaulting to their qualified name
+    pub naming: ServiceNaming,
+
+    /// Runner used to execute the commands of the service manager
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub runner: SharedCommandRunner,
+}
+
+impl Default for WinSwServiceManager {
+    fn default() -> Self {
+        Self::system()
+    }
 }
 
 impl WinSwServiceManager {
@@ -106,11 +146,53 @@ impl WinSwServiceManager {
             options: WinSwOptionsConfig::default(),
             service_definition_dir_path: PathBuf::from("C:\\ProgramData\\service-manager"),
         };
-        Self { config }
+        Self {
+            config,
+            naming: ServiceNaming::QualifiedName,
+            runner: SharedCommandRunner::default(),
+        }
     }
 
     pub fn with_config(self, config: WinSwConfig) -> Self {
-        Self { config }
+        Self { config, ..self }
+    }
+
+    /// Update manager to name services using the specified strategy
+    pub fn with_naming(self, naming: ServiceNaming) -> Self {
+        Self { naming, ..self }
+    }
+
+    /// Update manager to execute commands using the specified runner
+    pub fn with_runner(self, runner: impl CommandRunner + 'static) -> Self {
+        Self {
+            runner: SharedCommandRunner::new(runner),
+            ..self
+        }
+    }
+
+    /// Directory holding the service definition and logs of the service with `service_name`
+    fn service_instance_path(&self, service_name: &str) -> PathBuf {
+        self.config.service_definition_dir_path.join(service_name)
+    }
+
+    /// Rewrites the `startmode` of the service with `label` and has WinSW apply it
+    fn set_start_mode(&self, label: &ServiceLabel, start_mode: WinSwStartType) -> io::Result<()> {
+        let service_name = self.naming.native_name(label);
+        let config_path =
+            service_config_path(&self.config.service_definition_dir_path, &service_name);
+        let xml = std::fs::read_to_string(&config_path)
+            .map_err(|x| utils::not_installed(x, &service_name))?;
+        let xml = with_start_mode(&xml, start_mode);
+        utils::write_file(&config_path, xml.as_bytes(), CONFIG_FILE_PERMISSIONS)?;
+
+        let service_instance_path = self.service_instance_path(&service_name);
+        wrap_output(winsw_exe(
+            &self.runner,
+            "refresh",
+            &service_name,
+            &service_instance_path,
+        )?)?;
+        Ok(())
     }
 
     pub fn write_service_configuration(
@@ -118,22 +200,54 @@ impl WinSwServiceManager {
         ctx: &ServiceInstallCtx,
         config: &WinSwConfig,
     ) -> io::Result<()> {
-        let mut file = File::create(path).unwrap();
+        let contents =
+            Self::render_service_configuration(ctx, config, &ctx.label.to_qualified_name())?;
+        let mut file = File::create(path)?;
+        file.write_all(&contents)
+    }
+
+    /// Produces the contents of the XML service configuration of the service with
+    /// `service_name` without writing it anywhere
+    fn render_service_configuration(
+        ctx: &ServiceInstallCtx,
+        config: &WinSwConfig,
+        service_name: &str,
+    ) -> io::Result<Vec<u8>> {
         if let Some(contents) = &ctx.contents {
             if Self::is_valid_xml(contents) {
-                file.write_all(contents.as_bytes())?;
-                return Ok(());
+                return Ok(contents.as_bytes().to_vec());
+            }
+            return Err(Error::InvalidDefinition(
+                "The contents override was not a valid XML document".to_string(),
+            )
+            .into());
+        }
+
+        // WinSW has no way to limit the resources of the service it wraps
+        if !ctx.limits.is_empty() {
+            return Err(unsupported_limit("WinSW", "the resources of a service"));
+        }
+
+        // Dependencies of Windows services both require and order them
+        let mut depend = config
+            .options
+            .dependent_services
+            .clone()
+            .unwrap_or_default();
+        for dependency in &ctx.dependencies {
+            match dependency.kind {
+                DependencyKind::Requires | DependencyKind::After => {
+                    if !depend.contains(&dependency.service) {
+                        depend.push(dependency.service.clone());
+                    }
+                }
+                kind => return Err(unsupported_dependency("WinSW", kind)),
             }
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "The contents override was not a valid XML document",
-            ));
         }
 
-        let file = BufWriter::new(file);
         let mut writer = EmitterConfig::new()
             .perform_indent(true)
-            .create_writer(file);
+            .create_writer(Vec::new());
         writer
             .write(XmlEvent::StartDocument {
                 version: XmlVersion::Version10,
@@ -158,20 +272,24 @@ impl WinSwServiceManager {
             })?;
 
         // Mandatory values
-        Self::write_element(&mut writer, "id", &ctx.label.to_qualified_name())?;
+        Self::write_element(&mut writer, "id", service_name)?;
         Self::write_element(&mut writer, "name", &ctx.label.to_qualified_name())?;
-        Self::write_element(&mut writer, "executable", &ctx.program.to_string_lossy())?;
+        // XML only holds text, so paths and arguments that are not valid Unicode are rejected
+        // rather than replaced
+        Self::write_element(
+            &mut writer,
+            "executable",
+            utils::require_utf8(ctx.program.as_os_str(), "The program")?,
+        )?;
         Self::write_element(
             &mut writer,
             "description",
             &format!("Service for {}", ctx.label.to_qualified_name()),
         )?;
         let args = ctx
-            .args
-            .clone()
-            .into_iter()
-            .map(|s| s.into_string().unwrap_or_default())
-            .collect::<Vec<String>>()
+            .args_iter()
+            .map(|s| utils::require_utf8(s, "The argument"))
+            .collect::<io::Result<Vec<&str>>>()?
             .join(" ");
         Self::write_element(&mut writer, "arguments", &args)?;
 
@@ -179,7 +297,7 @@ impl WinSwServiceManager {
             Self::write_element(
                 &mut writer,
                 "workingdirectory",
-                &working_directory.to_string_lossy(),
+                utils::require_utf8(working_directory.as_os_str(), "The working directory")?,
             )?;
         }
         if let Some(env_vars) = &ctx.environment {
@@ -228,14 +346,14 @@ impl WinSwServiceManager {
             Self::write_element(
                 &mut writer,
                 "stopexecutable",
-                &stop_executable.to_string_lossy(),
+                utils::require_utf8(stop_executable.as_os_str(), "The stop executable")?,
             )?;
         }
         if let Some(stop_args) = &config.options.stop_args {
             let stop_args = stop_args
                 .iter()
-                .map(|s| s.to_string_lossy().into_owned())
-                .collect::<Vec<String>>()
+                .map(|s| utils::require_utf8(s, "The stop argument"))
+                .collect::<io::Result<Vec<&str>>>()?
                 .join(" ");
             Self::write_element(&mut writer, "stoparguments", &stop_args)?;
         }
@@ -248,6 +366,14 @@ impl WinSwServiceManager {
             Self::write_element(&mut writer, "startmode", "Manual")?;
         }
 
+        if let Some((mode, log_dir)) = log_settings(service_name, &ctx.stdout, &ctx.stderr)? {
+            if let Some(log_dir) = log_dir {
+                let log_dir = utils::require_utf8(log_dir.as_os_str(), "The log directory")?;
+                Self::write_element(&mut writer, "logpath", log_dir)?;
+            }
+            Self::write_element_with_attributes(&mut writer, "log", &[("mode", mode)], None)?;
+        }
+
         if let Some(delayed_autostart) = config.options.delayed_autostart {
             Self::write_element(
                 &mut writer,
@@ -255,10 +381,8 @@ impl WinSwServiceManager {
                 &delayed_autostart.to_string(),
             )?;
         }
-        if let Some(dependent_services) = &config.options.dependent_services {
-            for service in dependent_services {
-                Self::write_element(&mut writer, "depend", service)?;
-            }
+        for service in &depend {
+            Self::write_element(&mut writer, "depend", service)?;
         }
         if let Some(interactive) = config.options.interactive {
             Self::write_element(&mut writer, "interactive", &interactive.to_string())?;
@@ -275,7 +399,7 @@ impl WinSwServiceManager {
             )
         })?;
 
-        Ok(())
+        Ok(writer.into_inner())
     }
 
     fn write_element<W: Write>(
@@ -368,27 +492,58 @@ impl ServiceManager for WinSwServiceManager {
     }
 
     fn install(&self, ctx: ServiceInstallCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        let service_instance_path = self
-            .config
-            .service_definition_dir_path
-            .join(service_name.clone());
-        std::fs::create_dir_all(&service_instance_path)?;
+        utils::install_rendered(&self.runner, &self.render(&ctx)?)
+    }
+
+    /// Rewrites a changed service configuration and runs `winsw refresh`, restarting the
+    /// service only if it was running
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> io::Result<InstallOutcome> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let refresh = winsw_command(
+            "refresh",
+            &service_name,
+            &self.service_instance_path(&service_name),
+        );
+        utils::install_or_update_rendered(self, &self.runner, ctx, &[refresh])
+    }
+
+    fn render(&self, ctx: &ServiceInstallCtx) -> io::Result<RenderedService> {
+        let service_name = self.naming.native_name(&ctx.label);
+        naming::validate_native_name(&service_name, MAX_SERVICE_NAME_LEN, "WinSW")?;
+        let service_instance_path = self.service_instance_path(&service_name);
 
         let service_config_path = service_instance_path.join(format!("{service_name}.xml"));
-        Self::write_service_configuration(&service_config_path, &ctx, &self.config)?;
+        utils::ensure_no_conflict(&service_config_path, &service_name, &ctx.label)?;
 
-        wrap_output(winsw_exe("install", &service_name, &service_instance_path)?)?;
-        Ok(())
+        let mut contents = Self::render_service_configuration(ctx, &self.config, &service_name)?;
+        if ctx.contents.is_none() {
+            let xml = String::from_utf8(contents)
+                .map_err(|x| io::Error::new(io::ErrorKind::InvalidData, x))?;
+            contents = utils::mark_label(xml, &ctx.label, utils::MarkerStyle::Xml).into_bytes();
+        }
+
+        Ok(RenderedService {
+            files: vec![RenderedFile {
+                path: service_config_path,
+                contents,
+                mode: CONFIG_FILE_PERMISSIONS,
+            }],
+            commands: vec![winsw_command(
+                "install",
+                &service_name,
+                &service_instance_path,
+            )],
+        })
     }
 
     fn uninstall(&self, ctx: ServiceUninstallCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        let service_instance_path = self
-            .config
-            .service_definition_dir_path
-            .join(service_name.clone());
+        let service_name = self.naming.native_name(&ctx.label);
+        let service_instance_path = self.service_instance_path(&service_name);
+        if !service_instance_path.exists() {
+            return Err(Error::NotInstalled(service_name).into());
+        }
         wrap_output(winsw_exe(
+            &self.runner,
             "uninstall",
             &service_name,
             &service_instance_path,
@@ -403,25 +558,100 @@ impl ServiceManager for WinSwServiceManager {
     }
 
     fn start(&self, ctx: ServiceStartCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        let service_instance_path = self
-            .config
-            .service_definition_dir_path
-            .join(service_name.clone());
-        wrap_output(winsw_exe("start", &service_name, &service_instance_path)?)?;
+        let service_name = self.naming.native_name(&ctx.label);
+        let service_instance_path = self.service_instance_path(&service_name);
+        wrap_output(winsw_exe(
+            &self.runner,
+            "start",
+            &service_name,
+            &service_instance_path,
+        )?)?;
         Ok(())
     }
 
     fn stop(&self, ctx: ServiceStopCtx) -> io::Result<()> {
-        let service_name = ctx.label.to_qualified_name();
-        let service_instance_path = self
-            .config
-            .service_definition_dir_path
-            .join(service_name.clone());
-        wrap_output(winsw_exe("stop", &service_name, &service_instance_path)?)?;
+        let service_name = self.naming.native_name(&ctx.label);
+        let service_instance_path = self.service_instance_path(&service_name);
+        wrap_output(winsw_exe(
+            &self.runner,
+            "stop",
+            &service_name,
+            &service_instance_path,
+        )?)?;
+        Ok(())
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> io::Result<()> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let service_instance_path = self.service_instance_path(&service_name);
+        wrap_output(winsw_exe(
+            &self.runner,
+            "restart",
+            &service_name,
+            &service_instance_path,
+        )?)?;
+        Ok(())
+    }
+
+    /// Sets the `startmode` of the service to `Automatic` and runs `winsw refresh`
+    fn enable(&self, ctx: ServiceEnableCtx) -> io::Result<()> {
+        self.set_start_mode(&ctx.label, WinSwStartType::Automatic)
+    }
+
+    /// Sets the `startmode` of the service to `Manual` and runs `winsw refresh`
+    fn disable(&self, ctx: ServiceDisableCtx) -> io::Result<()> {
+        self.set_start_mode(&ctx.label, WinSwStartType::Manual)
+    }
+
+    /// Reports whether the `startmode` within the service configuration starts it automatically
+    fn is_enabled(&self, ctx: crate::ServiceStatusCtx) -> io::Result<bool> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let config_path =
+            service_config_path(&self.config.service_definition_dir_path, &service_name);
+        let xml = std::fs::read_to_string(config_path)
+            .map_err(|x| utils::not_installed(x, &service_name))?;
+        Ok(starts_automatically(&xml))
+    }
+
+    /// Restores the previous service configuration and runs `winsw refresh`
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> io::Result<()> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let config_path =
+            service_config_path(&self.config.service_definition_dir_path, &service_name);
+        if !config_path.exists() {
+            return Err(Error::NotInstalled(service_name).into());
+        }
+
+        utils::restore_backup(&config_path, &service_name, CONFIG_FILE_PERMISSIONS)?;
+        wrap_output(winsw_exe(
+            &self.runner,
+            "refresh",
+            &service_name,
+            &self.service_instance_path(&service_name),
+        )?)?;
         Ok(())
     }
 
+    /// Reads the `{name}.out.log` and `{name}.err.log` files that WinSW writes the output of the
+    /// service to within its `logpath`, which defaults to the directory of the service
+    fn logs(&self, label: ServiceLabel, query: LogQuery) -> io::Result<LogLines> {
+        let service_name = self.naming.native_name(&label);
+        let config_path =
+            service_config_path(&self.config.service_definition_dir_path, &service_name);
+        let xml = std::fs::read_to_string(config_path)
+            .map_err(|x| utils::not_installed(x, &service_name))?;
+
+        let log_dir = match element_text(&xml, "logpath") {
+            Some(log_dir) => PathBuf::from(log_dir),
+            None => self.service_instance_path(&service_name),
+        };
+        let paths = ["out", "err"]
+            .into_iter()
+            .map(|stream| log_dir.join(format!("{service_name}.{stream}.log")))
+            .collect();
+        logs::tail_files(paths, &query)
+    }
+
     fn level(&self) -> ServiceLevel {
         ServiceLevel::System
     }
@@ -429,51 +659,265 @@ impl ServiceManager for WinSwServiceManager {
     fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
         match level {
             ServiceLevel::System => Ok(()),
-            ServiceLevel::User => Err(io::Error::new(
-                io::ErrorKind::Unsupported,
-                "Windows does not support user-level services",
-            )),
+            ServiceLevel::User => Err(Error::Unsupported(
+                "Windows does not support user-level services".to_string(),
+            )
+            .into()),
         }
     }
 
     fn status(&self, ctx: crate::ServiceStatusCtx) -> io::Result<ServiceStatus> {
-        let service_name = ctx.label.to_qualified_name();
-        let service_instance_path = self
-            .config
-            .service_definition_dir_path
-            .join(service_name.clone());
+        let service_name = self.naming.native_name(&ctx.label);
+        let service_instance_path = self.service_instance_path(&service_name);
         if !service_instance_path.exists() {
             return Ok(ServiceStatus::NotInstalled);
         }
-        let output = winsw_exe("status", &service_name, &service_instance_path)?;
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            // It seems the error message is thrown by WinSW v2.x because only WinSW.[xml|yml] is supported
-            if stderr.contains("System.IO.FileNotFoundException: Unable to locate WinSW.[xml|yml] file within executable directory") {
-                return Ok(ServiceStatus::NotInstalled);
-            }
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            // Unsuccessful output status seems to be incorrect sometimes
-            if stdout.contains("Active") {
-                return Ok(ServiceStatus::Running);
-            }
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!("Failed to get service status: {}", stderr),
+        let output = winsw_exe(
+            &self.runner,
+            "status",
+            &service_name,
+            &service_instance_path,
+        )?;
+        status_from_output(output)
+    }
+
+    fn status_detail(
+        &self,
+        ctx: crate::ServiceStatusCtx,
+    ) -> io::Result<crate::ServiceStatusDetail> {
+        let service_name = self.naming.native_name(&ctx.label);
+        let service_instance_path = self.service_instance_path(&service_name);
+        if !service_instance_path.exists() {
+            return Ok(crate::ServiceStatusDetail::new(
+                crate::ServiceState::NotInstalled,
             ));
         }
+
+        let output = winsw_exe(
+            &self.runner,
+            "status",
+            &service_name,
+            &service_instance_path,
+        )?;
+        detail_from_output(output)
+    }
+
+    fn list(&self, ctx: crate::ServiceListCtx) -> io::Result<Vec<crate::InstalledService>> {
+        let dir_path = &self.config.service_definition_dir_path;
+
+        let mut services = Vec::new();
+        for service_name in utils::list_dir_names(dir_path, true)? {
+            // Only directories holding a service definition were created by this manager
+            let config_path = service_config_path(dir_path, &service_name);
+            if !config_path.is_file() {
+                continue;
+            }
+
+            let contents = std::fs::read(&config_path).ok();
+            let label = match utils::label_from_definition(
+                contents.as_deref(),
+                &service_name,
+                &self.naming,
+            ) {
+                Some(label) if ctx.matches(&label) => label,
+                _ => continue,
+            };
+
+            let status = self.status(crate::ServiceStatusCtx {
+                label: label.clone(),
+            })?;
+            services.push(crate::InstalledService { label, status });
+        }
+
+        Ok(services)
+    }
+}
+
+/// Interprets the output of `winsw status`
+fn status_from_output(output: CommandOutput) -> io::Result<ServiceStatus> {
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        // It seems the error message is thrown by WinSW v2.x because only WinSW.[xml|yml] is supported
+        if stderr.contains("System.IO.FileNotFoundException: Unable to locate WinSW.[xml|yml] file within executable directory") {
+            return Ok(ServiceStatus::NotInstalled);
+        }
         let stdout = String::from_utf8_lossy(&output.stdout);
-        if stdout.contains("NonExistent") {
-            Ok(ServiceStatus::NotInstalled)
-        } else if stdout.contains("running") {
-            Ok(ServiceStatus::Running)
-        } else {
-            Ok(ServiceStatus::Stopped(None))
+        // Unsuccessful output status seems to be incorrect sometimes
+        if stdout.contains("Active") {
+            return Ok(ServiceStatus::Running);
+        }
+        return Err(utils::command_failed(output).into());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if stdout.contains("NonExistent") {
+        Ok(ServiceStatus::NotInstalled)
+    } else if stdout.contains("running") {
+        Ok(ServiceStatus::Running)
+    } else {
+        Ok(ServiceStatus::Stopped(None))
+    }
+}
+
+/// Interprets the output of `winsw status` into a detailed status, falling back to the state
+/// derived by [`status_from_output`] if it cannot be parsed
+fn detail_from_output(output: CommandOutput) -> io::Result<crate::ServiceStatusDetail> {
+    match parse_status(&String::from_utf8_lossy(&output.stdout)) {
+        Some(detail) => Ok(detail),
+        None => Ok(status_from_output(output)?.into()),
+    }
+}
+
+/// Path of the service definition of the service with `service_name` within `dir_path`
+fn service_config_path(dir_path: &Path, service_name: &str) -> PathBuf {
+    dir_path
+        .join(service_name)
+        .join(format!("{service_name}.xml"))
+}
+
+/// Produces the service configuration `xml` with its `startmode` element set to `start_mode`
+fn with_start_mode(xml: &str, start_mode: WinSwStartType) -> String {
+    let element = format!("<startmode>{start_mode:?}</startmode>");
+    if let Some(start) = xml.find("<startmode>") {
+        if let Some(end) = xml[start..].find("</startmode>") {
+            let end = start + end + "</startmode>".len();
+            return format!("{}{element}{}", &xml[..start], &xml[end..]);
+        }
+    }
+
+    match xml.rfind("</service>") {
+        Some(end) => format!("{}  {element}\n{}", &xml[..end], &xml[end..]),
+        None => xml.to_string(),
+    }
+}
+
+/// Produces the WinSW log mode and log directory sending the streams of the service with
+/// `service_name` to `stdout` and `stderr`, which are left out when both streams are inherited
+///
+/// WinSW writes stdout and stderr to `{service_name}.out.log` and `{service_name}.err.log` within
+/// a single log directory using a single mode, so files must be named that way and streams may
+/// not be sent to different directories or handled differently. Syslog is unsupported.
+fn log_settings<'a>(
+    service_name: &str,
+    stdout: &'a ServiceOutput,
+    stderr: &'a ServiceOutput,
+) -> io::Result<Option<(&'static str, Option<&'a Path>)>> {
+    let mut settings = None;
+    for (stream, output) in [("out", stdout), ("err", stderr)] {
+        let setting = match output {
+            ServiceOutput::Inherit => continue,
+            ServiceOutput::Null => ("none", None),
+            ServiceOutput::File(path) | ServiceOutput::Append(path) => {
+                let file_name = format!("{service_name}.{stream}.log");
+                if path.file_name() != Some(OsStr::new(&file_name)) {
+                    return Err(Error::Unsupported(format!(
+                        "WinSW names the std{stream} log file {file_name}, not {}",
+                        path.display()
+                    ))
+                    .into());
+                }
+
+                let mode = match output {
+                    ServiceOutput::File(_) => "reset",
+                    _ => "append",
+                };
+                (mode, path.parent())
+            }
+            ServiceOutput::Syslog { .. } => {
+                return Err(Error::Unsupported(
+                    "WinSW is unable to send the output of a service to syslog".to_string(),
+                )
+                .into())
+            }
+        };
+
+        match settings {
+            Some(settings) if settings != setting => {
+                return Err(Error::Unsupported(
+                    "WinSW uses the same log mode and directory for stdout and stderr".to_string(),
+                )
+                .into())
+            }
+            _ => settings = Some(setting),
+        }
+    }
+    Ok(settings)
+}
+
+/// Text of the first element called `element_name` within the service configuration `xml`
+fn element_text(xml: &str, element_name: &str) -> Option<String> {
+    let mut in_element = false;
+    for event in EventReader::new(Cursor::new(xml)).into_iter().flatten() {
+        match event {
+            xml::reader::XmlEvent::StartElement { name, .. } => {
+                in_element = name.local_name == element_name;
+            }
+            xml::reader::XmlEvent::Characters(text) if in_element => return Some(text),
+            xml::reader::XmlEvent::EndElement { .. } => in_element = false,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Determines if the `startmode` of the service configuration `xml` starts it automatically,
+/// which is the default of WinSW
+fn starts_automatically(xml: &str) -> bool {
+    let mut in_start_mode = false;
+    for event in EventReader::new(Cursor::new(xml)).into_iter().flatten() {
+        match event {
+            xml::reader::XmlEvent::StartElement { name, .. } => {
+                in_start_mode = name.local_name == "startmode";
+            }
+            xml::reader::XmlEvent::Characters(mode) if in_start_mode => {
+                return !mode.trim().eq_ignore_ascii_case("manual")
+                    && !mode.trim().eq_ignore_ascii_case("disabled");
+            }
+            xml::reader::XmlEvent::EndElement { .. } => in_start_mode = false,
+            _ => {}
         }
     }
+    true
+}
+
+/// Parses the output of `winsw status`, e.g. `Active (running)` or `Inactive (stopped)`
+fn parse_status(stdout: &str) -> Option<crate::ServiceStatusDetail> {
+    let line = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    let lower = line.to_lowercase();
+    let state = if lower.contains("nonexistent") {
+        crate::ServiceState::NotInstalled
+    } else if lower.contains("start pending") || lower.contains("continue pending") {
+        crate::ServiceState::Starting
+    } else if lower.contains("stop pending") || lower.contains("pause pending") {
+        crate::ServiceState::Stopping
+    } else if lower.contains("paused") {
+        crate::ServiceState::Paused
+    } else if lower.contains("running") {
+        crate::ServiceState::Running
+    } else if lower.contains("stopped") {
+        crate::ServiceState::Stopped
+    } else {
+        return None;
+    };
+
+    Some(crate::ServiceStatusDetail {
+        native_state: Some(line.to_string()),
+        ..crate::ServiceStatusDetail::new(state)
+    })
+}
+
+fn winsw_exe(
+    runner: &dyn CommandRunner,
+    cmd: &str,
+    service_name: &str,
+    working_dir_path: &Path,
+) -> io::Result<CommandOutput> {
+    utils::output(runner, &winsw_command(cmd, service_name, working_dir_path))
 }
 
-fn winsw_exe(cmd: &str, service_name: &str, working_dir_path: &Path) -> io::Result<Output> {
+fn winsw_command(cmd: &str, service_name: &str, working_dir_path: &Path) -> ServiceCommand {
     let winsw_path = match std::env::var("WINSW_PATH") {
         Ok(val) => {
             let path = PathBuf::from(val);
@@ -486,15 +930,10 @@ fn winsw_exe(cmd: &str, service_name: &str, working_dir_path: &Path) -> io::Resu
         Err(_) => PathBuf::from(WINSW_EXE),
     };
 
-    let mut command = Command::new(winsw_path);
-    command
-        .stdin(Stdio::null())
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    command.current_dir(working_dir_path);
-    command.arg(cmd).arg(format!("{}.xml", service_name));
-
-    command.output()
+    ServiceCommand::new(winsw_path)
+        .arg(cmd)
+        .arg(format!("{}.xml", service_name))
+        .current_dir(working_dir_path)
 }
 
 #[cfg(test)]
@@ -626,6 +1065,10 @@ mod tests {
             autostart: true,
             disable_restart_on_failure: false,
             requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: crate::ResourceLimits::default(),
+            dependencies: Vec::new(),
         };
 
         WinSwServiceManager::write_service_configuration(
@@ -675,6 +1118,10 @@ mod tests {
             autostart: false,
             disable_restart_on_failure: false,
             requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: crate::ResourceLimits::default(),
+            dependencies: Vec::new(),
         };
 
         WinSwServiceManager::write_service_configuration(
@@ -704,6 +1151,31 @@ mod tests {
         assert_eq!("Manual", get_element_value(&xml, "startmode"));
     }
 
+    #[test]
+    fn test_with_start_mode() {
+        let xml = indoc! {r#"
+            <?xml version="1.0" encoding="UTF-8"?>
+            <service>
+              <id>org.example.my_service</id>
+              <startmode>Manual</startmode>
+            </service>
+        "#};
+        assert!(!starts_automatically(xml));
+
+        let enabled = with_start_mode(xml, WinSwStartType::Automatic);
+        assert_eq!("Automatic", get_element_value(&enabled, "startmode"));
+        assert!(starts_automatically(&enabled));
+
+        let disabled = with_start_mode(&enabled, WinSwStartType::Manual);
+        assert_eq!(xml, disabled);
+
+        // WinSW starts services automatically unless told otherwise
+        let xml = xml.replace("  <startmode>Manual</startmode>\n", "");
+        assert!(starts_automatically(&xml));
+        let disabled = with_start_mode(&xml, WinSwStartType::Manual);
+        assert_eq!("Manual", get_element_value(&disabled, "startmode"));
+    }
+
     #[test]
     fn test_service_configuration_with_special_start_type_should_override_autostart() {
         let temp_dir = assert_fs::TempDir::new().unwrap();
@@ -724,6 +1196,10 @@ mod tests {
             autostart: false,
             disable_restart_on_failure: false,
             requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: crate::ResourceLimits::default(),
+            dependencies: Vec::new(),
         };
 
         let mut config = WinSwConfig::default();
@@ -778,6 +1254,13 @@ mod tests {
             autostart: true,
             disable_restart_on_failure: false,
             requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: crate::ResourceLimits::default(),
+            dependencies: vec![
+                crate::ServiceDependency::requires("service3"),
+                crate::ServiceDependency::after("service1"),
+            ],
         };
 
         let config = WinSwConfig {
@@ -872,8 +1355,7 @@ mod tests {
         assert_eq!("true", get_element_value(&xml, "delayedAutoStart"));
 
         let dependent_services = get_element_values(&xml, "depend");
-        assert_eq!("service1", dependent_services[0]);
-        assert_eq!("service2", dependent_services[1]);
+        assert_eq!(dependent_services, ["service1", "service2", "service3"]);
 
         assert_eq!("true", get_element_value(&xml, "interactive"));
         assert_eq!("true", get_element_value(&xml, "beeponshutdown"));
@@ -909,6 +1391,10 @@ mod tests {
             autostart: true,
             disable_restart_on_failure: false,
             requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: crate::ResourceLimits::default(),
+            dependencies: Vec::new(),
         };
 
         WinSwServiceManager::write_service_configuration(
@@ -954,6 +1440,10 @@ mod tests {
             autostart: true,
             disable_restart_on_failure: false,
             requires_network: false,
+            stdout: ServiceOutput::Inherit,
+            stderr: ServiceOutput::Inherit,
+            limits: crate::ResourceLimits::default(),
+            dependencies: Vec::new(),
         };
 
         let result = WinSwServiceManager::write_service_configuration(
@@ -970,4 +1460,85 @@ mod tests {
             ),
         }
     }
+
+    #[test]
+    fn test_service_configuration_with_unsupported_dependency() {
+        let ctx = ServiceInstallCtx::builder("com.example.echo".parse().unwrap(), "echo.exe")
+            .dependency(crate::ServiceDependency::wants("service1"))
+            .build();
+
+        let err = WinSwServiceManager::render_service_configuration(
+            &ctx,
+            &WinSwConfig::default(),
+            "echo",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_service_configuration_with_invalid_utf8_argument() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut ctx = ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "echo.exe");
+        ctx.args = vec![OsString::from_vec(b"caf\xe9".to_vec())];
+
+        let err = WinSwServiceManager::render_service_configuration(
+            &ctx,
+            &WinSwConfig::default(),
+            "echo",
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_parse_status() {
+        let detail = parse_status("Active (running)\r\n").unwrap();
+        assert_eq!(detail.state, crate::ServiceState::Running);
+        assert_eq!(detail.native_state.as_deref(), Some("Active (running)"));
+
+        let detail = parse_status("Inactive (stopped)").unwrap();
+        assert_eq!(detail.state, crate::ServiceState::Stopped);
+
+        let detail = parse_status("NonExistent").unwrap();
+        assert_eq!(detail.state, crate::ServiceState::NotInstalled);
+
+        assert!(parse_status("").is_none());
+    }
+
+    #[test]
+    fn test_service_configuration_with_log_settings() {
+        let mut ctx = ServiceInstallCtx::new("com.example.echo".parse().unwrap(), "echo.exe");
+        ctx.stdout = ServiceOutput::Append(PathBuf::from("/logs/echo.out.log"));
+        ctx.stderr = ServiceOutput::Append(PathBuf::from("/logs/echo.err.log"));
+
+        let xml = WinSwServiceManager::render_service_configuration(
+            &ctx,
+            &WinSwConfig::default(),
+            "echo",
+        )
+        .unwrap();
+        let xml = String::from_utf8(xml).unwrap();
+        assert_eq!(element_text(&xml, "logpath").as_deref(), Some("/logs"));
+        assert_eq!(
+            get_element_attribute_value(&xml, "log", "mode"),
+            "append".to_string()
+        );
+
+        ctx.stderr = ServiceOutput::Null;
+        let err = log_settings("echo", &ctx.stdout, &ctx.stderr).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        ctx.stdout = ServiceOutput::File(PathBuf::from("/logs/echo.log"));
+        let err = log_settings("echo", &ctx.stdout, &ctx.stderr).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+        ctx.stdout = ServiceOutput::Null;
+        assert_eq!(
+            log_settings("echo", &ctx.stdout, &ctx.stderr).unwrap(),
+            Some(("none", None))
+        );
+    }
 }
@@ -0,0 +1,212 @@
+use super::{
+    detail_from_output, service_config_path, starts_automatically, status_from_output,
+    winsw_command, with_start_mode, WinSwServiceManager, WinSwStartType, CONFIG_FILE_PERMISSIONS,
+};
+use crate::asynchronous::{AsyncServiceManager, ServiceFuture};
+use crate::utils::{self, nonblocking, wrap_output};
+use crate::{
+    Error, InstallOutcome, InstalledService, ServiceDiff, ServiceDisableCtx, ServiceEnableCtx,
+    ServiceInstallCtx, ServiceLabel, ServiceLevel, ServiceListCtx, ServiceRestartCtx,
+    ServiceRollbackCtx, ServiceStartCtx, ServiceState, ServiceStatus, ServiceStatusCtx,
+    ServiceStatusDetail, ServiceStopCtx, ServiceUninstallCtx,
+};
+use std::io;
+
+impl WinSwServiceManager {
+    /// Runs `winsw {cmd}` on the service with `label`, failing if it does not succeed
+    async fn winsw_exe_async(&self, cmd: &str, label: &ServiceLabel) -> io::Result<()> {
+        let service_name = self.naming.native_name(label);
+        let service_instance_path = self.service_instance_path(&service_name);
+        let command = winsw_command(cmd, &service_name, &service_instance_path);
+        wrap_output(nonblocking::output(&self.runner, &command).await?)?;
+        Ok(())
+    }
+
+    /// Rewrites the `startmode` of the service like its blocking counterpart
+    async fn set_start_mode_async(
+        &self,
+        label: &ServiceLabel,
+        start_mode: WinSwStartType,
+    ) -> io::Result<()> {
+        let service_name = self.naming.native_name(label);
+        let config_path =
+            service_config_path(&self.config.service_definition_dir_path, &service_name);
+        let xml = tokio::fs::read_to_string(&config_path)
+            .await
+            .map_err(|x| utils::not_installed(x, &service_name))?;
+        let xml = with_start_mode(&xml, start_mode);
+        nonblocking::write_file(&config_path, xml.as_bytes(), CONFIG_FILE_PERMISSIONS).await?;
+        self.winsw_exe_async("refresh", label).await
+    }
+}
+
+impl AsyncServiceManager for WinSwServiceManager {
+    fn available(&self) -> ServiceFuture<'_, bool> {
+        Box::pin(async move { crate::ServiceManager::available(self) })
+    }
+
+    fn install(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, &ctx)?;
+            nonblocking::install_rendered(&self.runner, &rendered).await
+        })
+    }
+
+    fn diff<'a>(&'a self, ctx: &'a ServiceInstallCtx) -> ServiceFuture<'a, ServiceDiff> {
+        Box::pin(async move {
+            let rendered = crate::ServiceManager::render(self, ctx)?;
+            nonblocking::diff_rendered(&rendered.files).await
+        })
+    }
+
+    fn install_or_update(&self, ctx: ServiceInstallCtx) -> ServiceFuture<'_, InstallOutcome> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let refresh = winsw_command(
+                "refresh",
+                &service_name,
+                &self.service_instance_path(&service_name),
+            );
+            nonblocking::install_or_update_rendered(self, &self.runner, ctx, &[refresh]).await
+        })
+    }
+
+    fn uninstall(&self, ctx: ServiceUninstallCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let service_instance_path = self.service_instance_path(&service_name);
+            if !nonblocking::exists(&service_instance_path).await {
+                return Err(Error::NotInstalled(service_name).into());
+            }
+            self.winsw_exe_async("uninstall", &ctx.label).await?;
+
+            // Remove the directory for the same reasons as the blocking uninstall
+            tokio::fs::remove_dir_all(service_instance_path).await
+        })
+    }
+
+    fn start(&self, ctx: ServiceStartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move { self.winsw_exe_async("start", &ctx.label).await })
+    }
+
+    fn stop(&self, ctx: ServiceStopCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move { self.winsw_exe_async("stop", &ctx.label).await })
+    }
+
+    fn restart(&self, ctx: ServiceRestartCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move { self.winsw_exe_async("restart", &ctx.label).await })
+    }
+
+    fn enable(&self, ctx: ServiceEnableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            self.set_start_mode_async(&ctx.label, WinSwStartType::Automatic)
+                .await
+        })
+    }
+
+    fn disable(&self, ctx: ServiceDisableCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            self.set_start_mode_async(&ctx.label, WinSwStartType::Manual)
+                .await
+        })
+    }
+
+    fn is_enabled(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, bool> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let config_path =
+                service_config_path(&self.config.service_definition_dir_path, &service_name);
+            let xml = tokio::fs::read_to_string(config_path)
+                .await
+                .map_err(|x| utils::not_installed(x, &service_name))?;
+            Ok(starts_automatically(&xml))
+        })
+    }
+
+    fn rollback(&self, ctx: ServiceRollbackCtx) -> ServiceFuture<'_, ()> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let config_path =
+                service_config_path(&self.config.service_definition_dir_path, &service_name);
+            if !nonblocking::exists(&config_path).await {
+                return Err(Error::NotInstalled(service_name).into());
+            }
+
+            nonblocking::restore_backup(&config_path, &service_name, CONFIG_FILE_PERMISSIONS)
+                .await?;
+            self.winsw_exe_async("refresh", &ctx.label).await
+        })
+    }
+
+    fn level(&self) -> ServiceLevel {
+        crate::ServiceManager::level(self)
+    }
+
+    fn set_level(&mut self, level: ServiceLevel) -> io::Result<()> {
+        crate::ServiceManager::set_level(self, level)
+    }
+
+    fn status(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatus> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let service_instance_path = self.service_instance_path(&service_name);
+            if !nonblocking::exists(&service_instance_path).await {
+                return Ok(ServiceStatus::NotInstalled);
+            }
+
+            let command = winsw_command("status", &service_name, &service_instance_path);
+            status_from_output(nonblocking::output(&self.runner, &command).await?)
+        })
+    }
+
+    fn status_detail(&self, ctx: ServiceStatusCtx) -> ServiceFuture<'_, ServiceStatusDetail> {
+        Box::pin(async move {
+            let service_name = self.naming.native_name(&ctx.label);
+            let service_instance_path = self.service_instance_path(&service_name);
+            if !nonblocking::exists(&service_instance_path).await {
+                return Ok(ServiceStatusDetail::new(ServiceState::NotInstalled));
+            }
+
+            let command = winsw_command("status", &service_name, &service_instance_path);
+            detail_from_output(nonblocking::output(&self.runner, &command).await?)
+        })
+    }
+
+    fn list(&self, ctx: ServiceListCtx) -> ServiceFuture<'_, Vec<InstalledService>> {
+        Box::pin(async move {
+            let dir_path = &self.config.service_definition_dir_path;
+
+            let mut services = Vec::new();
+            for service_name in nonblocking::list_dir_names(dir_path, true).await? {
+                // Only directories holding a service definition were created by this manager
+                let config_path = service_config_path(dir_path, &service_name);
+                let is_file = tokio::fs::metadata(&config_path)
+                    .await
+                    .map(|metadata| metadata.is_file())
+                    .unwrap_or(false);
+                if !is_file {
+                    continue;
+                }
+
+                let contents = tokio::fs::read(&config_path).await.ok();
+                let label = match utils::label_from_definition(
+                    contents.as_deref(),
+                    &service_name,
+                    &self.naming,
+                ) {
+                    Some(label) if ctx.matches(&label) => label,
+                    _ => continue,
+                };
+
+                let status = self
+                    .status(ServiceStatusCtx {
+                        label: label.clone(),
+                    })
+                    .await?;
+                services.push(InstalledService { label, status });
+            }
+
+            Ok(services)
+        })
+    }
+}
@@ -93,16 +93,12 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     eprintln!("Cleanup previous test if exists");
     if matches!(
         manager
-            .status(ServiceStatusCtx {
-                label: service_label.clone(),
-            })
+            .status(ServiceStatusCtx::new(service_label.clone()))
             .unwrap(),
         ServiceStatus::Stopped(_) | ServiceStatus::Running
     ) {
         manager
-            .uninstall(ServiceUninstallCtx {
-                label: service_label.clone(),
-            })
+            .uninstall(ServiceUninstallCtx::new(service_label.clone()))
             .unwrap();
 
         wait();
@@ -112,9 +108,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     assert!(
         matches!(
             manager
-                .status(ServiceStatusCtx {
-                    label: service_label.clone(),
-                })
+                .status(ServiceStatusCtx::new(service_label.clone()))
                 .unwrap(),
             ServiceStatus::NotInstalled,
         ),
@@ -123,19 +117,14 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
 
     // Install the service
     eprintln!("Installing service");
-    manager
-        .install(ServiceInstallCtx {
-            label: service_label.clone(),
-            program: temp_bin_path,
-            args,
-            contents: None,
-            username: username.clone(),
-            working_directory: None,
-            environment: None,
-            autostart: false,
-            disable_restart_on_failure: true,
-        })
-        .unwrap();
+    let mut ctx = ServiceInstallCtx::builder(service_label.clone(), temp_bin_path)
+        .args(args)
+        .autostart(false)
+        .restart_on_failure(false);
+    if let Some(username) = username.clone() {
+        ctx = ctx.username(username);
+    }
+    manager.install(ctx.build()).unwrap();
 
     // Wait for service to be installed
     wait();
@@ -144,9 +133,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     assert!(
         matches!(
             manager
-                .status(ServiceStatusCtx {
-                    label: service_label.clone(),
-                })
+                .status(ServiceStatusCtx::new(service_label.clone()))
                 .unwrap(),
             ServiceStatus::Stopped(_)
         ),
@@ -159,9 +146,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     // Start the service
     eprintln!("Starting service");
     manager
-        .start(ServiceStartCtx {
-            label: service_label.clone(),
-        })
+        .start(ServiceStartCtx::new(service_label.clone()))
         .unwrap();
 
     // Wait for the service to start
@@ -171,9 +156,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     assert!(
         matches!(
             manager
-                .status(ServiceStatusCtx {
-                    label: service_label.clone(),
-                })
+                .status(ServiceStatusCtx::new(service_label.clone()))
                 .unwrap(),
             ServiceStatus::Running
         ),
@@ -194,9 +177,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     // Stop the service
     eprintln!("Stopping service");
     if manager.is_openrc() && is_running_in_ci() {
-        let res = manager.stop(ServiceStopCtx {
-            label: service_label.clone(),
-        });
+        let res = manager.stop(ServiceStopCtx::new(service_label.clone()));
         if res.is_err() {
             eprintln!(
                 "OpenRC stop is bugged in CI test, so skipping: {}",
@@ -205,9 +186,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
         }
     } else {
         manager
-            .stop(ServiceStopCtx {
-                label: service_label.clone(),
-            })
+            .stop(ServiceStopCtx::new(service_label.clone()))
             .unwrap();
     }
 
@@ -218,9 +197,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     assert!(
         matches!(
             manager
-                .status(ServiceStatusCtx {
-                    label: service_label.clone(),
-                })
+                .status(ServiceStatusCtx::new(service_label.clone()))
                 .unwrap(),
             ServiceStatus::Stopped(_)
         ),
@@ -230,9 +207,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     // Uninstall the service
     eprintln!("Uninstalling service");
     manager
-        .uninstall(ServiceUninstallCtx {
-            label: service_label.clone(),
-        })
+        .uninstall(ServiceUninstallCtx::new(service_label.clone()))
         .unwrap();
     wait();
 
@@ -240,9 +215,7 @@ pub fn run_test(manager: &TypedServiceManager, username: Option<String>) -> Opti
     assert!(
         matches!(
             manager
-                .status(ServiceStatusCtx {
-                    label: service_label,
-                })
+                .status(ServiceStatusCtx::new(service_label))
                 .unwrap(),
             ServiceStatus::NotInstalled
         ),